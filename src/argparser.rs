@@ -1,6 +1,12 @@
 //! A simple argument parser, because all the existing ones are too complex, yet not flexible enough
 //! to deal with the use case of ending parsing after finding an argument not beginning with "-".
 //!
+//! Flags are booleans. Counts are flags that count how many times they're given, like `-vv`.
+//! Options take a value, as `--option value`, `--option=value`, `-o value`, or `-ovalue`, and
+//! keep the last one given. Lists take values like options, but keep every one given, in order.
+//! Flags in a `conflicts` group can't be given together, which is checked once they're parsed.
+//! The `counts`, `lists`, and `conflicts` sections can be left out.
+//!
 //! Methods in this module exit upon failure.
 
 #[macro_export]
@@ -13,71 +19,339 @@ macro_rules! BuildArgumentParser {
         flags: {
             $(
                 #[doc = $flag_description:expr]
-                $flag: ident: ($short: literal, $long:literal)
+                $flag: ident: $spec:tt
             ),*
         }
+
+        $(
+            counts: {
+                $(
+                    #[doc = $count_description:expr]
+                    $count: ident: $count_spec:tt
+                ),*
+            }
+        )?
+
+        options: {
+            $(
+                #[doc = $option_description:expr]
+                $option: ident: $option_spec:tt
+            ),*
+        }
+
+        $(
+            lists: {
+                $(
+                    #[doc = $list_description:expr]
+                    $list: ident: $list_spec:tt
+                ),*
+            }
+        )?
+
+        $(
+            conflicts: {
+                $(
+                    [$($member: ident),+]
+                ),*
+            }
+        )?
     ) => {
         BuildArgumentParser!{@
             $name,
             $description,
             $usage,
 
+            options: {
+                $(
+                    #[doc = $option_description]
+                    $option: $option_spec
+                ),*
+            }
+
+            lists: {
+                $($(
+                    #[doc = $list_description]
+                    $list: $list_spec
+                ),*)?
+            }
+
+            conflicts: {
+                $($(
+                    [$($member),+]
+                ),*)?
+            }
+
+            counts: {
+                $($(
+                    #[doc = $count_description]
+                    $count: $count_spec,
+                )*)?
+                /// Log what's going on to stderr. Repeat (-vv) for more detail
+                verbose: ('v', "--verbose")
+            }
+
             $(
                 #[doc = $flag_description]
-                $flag:  ($short, $long),
+                $flag: $spec,
             )*
             /// Print help information
             help: ('h', "--help"),
-            /// Print version info and exit
+            /// Print version info and exit. Use --version=json for machine-readable output
             version: ('V', "--version")
         }
     };
+    // Flags and options are either `(SHORT, LONG)` or just `(LONG)`
+    (@short ($short:literal, $long:literal)) => { Some($short) };
+    (@short ($long:literal)) => { None::<char> };
+    (@long ($short:literal, $long:literal)) => { $long };
+    (@long ($long:literal)) => { $long };
     (@
         $name:literal,
         $description:literal,
         $usage:literal,
 
+        options: {
+            $(
+                #[doc = $option_description:expr]
+                $option: ident: $option_spec:tt
+            ),*
+        }
+
+        lists: {
+            $(
+                #[doc = $list_description:expr]
+                $list: ident: $list_spec:tt
+            ),*
+        }
+
+        conflicts: {
+            $(
+                [$($member: ident),+]
+            ),*
+        }
+
+        counts: {
+            $(
+                #[doc = $count_description:expr]
+                $count: ident: $count_spec:tt
+            ),*
+        }
+
         $(
             #[doc = $flag_description:expr]
-            $flag: ident: ($short: literal, $long:literal)
+            $flag: ident: $spec:tt
         ),*
     ) => {
+        /// A flag or option, as listed in `--help`.
+        pub struct Flag {
+            pub short: Option<char>,
+            pub long: &'static str,
+            /// Whether it's an option, which takes a value
+            pub takes_value: bool,
+            pub description: &'static str,
+        }
+
         #[derive(Default)]
         pub struct ArgumentParser {
             $(
-                $flag: bool
-            ),*
+                $flag: bool,
+            )*
+            $(
+                $count: u8,
+            )*
+            $(
+                $option: Option<String>,
+            )*
+            $(
+                $list: Vec<String>,
+            )*
+            /// Output format requested with `--version=FORMAT`. The caller is responsible for
+            /// printing version info in this format.
+            version_format: Option<String>,
         }
         impl ArgumentParser {
+            /// Every flag, then every count, option, and list, for generating shell completions.
+            pub const FLAGS: &'static [Flag] = &[
+                $(
+                    Flag {
+                        short: BuildArgumentParser!(@short $spec),
+                        long: BuildArgumentParser!(@long $spec),
+                        takes_value: false,
+                        description: $flag_description,
+                    },
+                )*
+                $(
+                    Flag {
+                        short: BuildArgumentParser!(@short $count_spec),
+                        long: BuildArgumentParser!(@long $count_spec),
+                        takes_value: false,
+                        description: $count_description,
+                    },
+                )*
+                $(
+                    Flag {
+                        short: BuildArgumentParser!(@short $option_spec),
+                        long: BuildArgumentParser!(@long $option_spec),
+                        takes_value: true,
+                        description: $option_description,
+                    },
+                )*
+                $(
+                    Flag {
+                        short: BuildArgumentParser!(@short $list_spec),
+                        long: BuildArgumentParser!(@long $list_spec),
+                        takes_value: true,
+                        description: $list_description,
+                    },
+                )*
+            ];
+
             /// Process a single argument. Determine what flag it's associated with and fail if there's no
             /// associated flag.
-            fn process_argument(&mut self, argument: &str) {
+            ///
+            /// Options take their value from `next` unless it's given as `--option=value`. Return
+            /// whether `next` was consumed.
+            fn process_argument(&mut self, argument: &str, next: Option<&String>) -> bool {
                 let is_long = argument.starts_with("--");
 
                 if is_long {
-                    match argument {
-                        $(
-                           $long => { self.$flag = true }
-                        ),*
-                        _ => {
-                            eprintln!("Invalid flag '{}'", argument);
-                            std::process::exit(1);
+                    if let Some(format) = argument.strip_prefix("--version=") {
+                        self.version = true;
+                        self.version_format = Some(format.into());
+                        return false;
+                    }
+                    $(
+                        if let Some(value) = argument
+                            .strip_prefix(BuildArgumentParser!(@long $option_spec))
+                            .and_then(|rest| rest.strip_prefix('='))
+                        {
+                            self.$option = Some(value.into());
+                            return false;
                         }
-                    };
-                    return;
+                        if argument == BuildArgumentParser!(@long $option_spec) {
+                            let Some(value) = next else {
+                                eprintln!("Flag '{}' expects a value", argument);
+                                std::process::exit(1);
+                            };
+                            self.$option = Some(value.clone());
+                            return true;
+                        }
+                    )*
+                    $(
+                        if let Some(value) = argument
+                            .strip_prefix(BuildArgumentParser!(@long $list_spec))
+                            .and_then(|rest| rest.strip_prefix('='))
+                        {
+                            self.$list.push(value.into());
+                            return false;
+                        }
+                        if argument == BuildArgumentParser!(@long $list_spec) {
+                            let Some(value) = next else {
+                                eprintln!("Flag '{}' expects a value", argument);
+                                std::process::exit(1);
+                            };
+                            self.$list.push(value.clone());
+                            return true;
+                        }
+                    )*
+                    $(
+                        if argument == BuildArgumentParser!(@long $spec) {
+                            self.$flag = true;
+                            return false;
+                        }
+                    )*
+                    $(
+                        if argument == BuildArgumentParser!(@long $count_spec) {
+                            self.$count = self.$count.saturating_add(1);
+                            return false;
+                        }
+                    )*
+                    eprintln!("Invalid flag '{}'", argument);
+                    std::process::exit(1);
                 }
 
-                for character in (&argument[1..]).chars() {
-                    match character {
-                        $(
-                           $short => { self.$flag = true }
-                        ),*
-                        _ => {
-                            eprintln!("Invalid flag '{}'", character);
-                            std::process::exit(1);
+                'characters: for (index, character) in (&argument[1..]).char_indices() {
+                    // An option takes the rest of the argument as its value (`-j4`), or the next
+                    // argument if there's nothing left (`-j 4`)
+                    $(
+                        if BuildArgumentParser!(@short $option_spec) == Some(character) {
+                            let rest = &argument[1 + index + character.len_utf8()..];
+                            if !rest.is_empty() {
+                                self.$option = Some(rest.into());
+                                return false;
+                            }
+                            let Some(value) = next else {
+                                eprintln!("Flag '{}' expects a value", character);
+                                std::process::exit(1);
+                            };
+                            self.$option = Some(value.clone());
+                            return true;
                         }
-                    };
+                    )*
+                    $(
+                        if BuildArgumentParser!(@short $list_spec) == Some(character) {
+                            let rest = &argument[1 + index + character.len_utf8()..];
+                            if !rest.is_empty() {
+                                self.$list.push(rest.into());
+                                return false;
+                            }
+                            let Some(value) = next else {
+                                eprintln!("Flag '{}' expects a value", character);
+                                std::process::exit(1);
+                            };
+                            self.$list.push(value.clone());
+                            return true;
+                        }
+                    )*
+                    $(
+                        if BuildArgumentParser!(@short $spec) == Some(character) {
+                            self.$flag = true;
+                            continue 'characters;
+                        }
+                    )*
+                    $(
+                        if BuildArgumentParser!(@short $count_spec) == Some(character) {
+                            self.$count = self.$count.saturating_add(1);
+                            continue 'characters;
+                        }
+                    )*
+                    eprintln!("Invalid flag '{}'", character);
+                    std::process::exit(1);
                 }
+                false
+            }
+
+            // The long name of a flag, from its field
+            fn long_name(field: &str) -> &'static str {
+                $(
+                    if field == stringify!($flag) {
+                        return BuildArgumentParser!(@long $spec);
+                    }
+                )*
+                unreachable!("Only flags can conflict")
+            }
+
+            /// Describe the first conflict group more than one of whose flags were given, if any.
+            fn conflict(&self) -> Option<String> {
+                $(
+                    let group = [$((stringify!($member), self.$member)),+];
+                    if group.iter().filter(|(_, given)| *given).count() > 1 {
+                        let names: Vec<String> = group
+                            .iter()
+                            .map(|(field, _)| format!("`{}`", Self::long_name(field)))
+                            .collect();
+                        return Some(match &names[..] {
+                            [first, second] => {
+                                format!("{first} and {second} can not be used together")
+                            }
+                            [rest @ .., last] => {
+                                format!("Only one of {}, and {last} can be used", rest.join(", "))
+                            }
+                            [] => unreachable!(),
+                        });
+                    }
+                )*
+                None
             }
 
             /// Process a list of arguments up until the first non-flag is found,
@@ -86,7 +360,7 @@ macro_rules! BuildArgumentParser {
                 // Very first argument is just the name, so skip it
                 let mut position: usize = 1;
 
-                for arg in &arguments[position..] {
+                while let Some(arg) = arguments.get(position) {
                     // Explicitly stop processing args
                     if arg == "--" {
                         position += 1;
@@ -94,7 +368,9 @@ macro_rules! BuildArgumentParser {
                     }
 
                     if arg.starts_with('-') {
-                        self.process_argument(arg.as_str());
+                        if self.process_argument(arg.as_str(), arguments.get(position + 1)) {
+                            position += 1;
+                        }
                         position += 1;
                         continue;
                     };
@@ -108,13 +384,66 @@ macro_rules! BuildArgumentParser {
                     );
 
                     $(
-                        println!("    -{}, {:15}{}", $short, $long, $flag_description);
+                        let short = match BuildArgumentParser!(@short $spec) {
+                            Some(short) => format!("-{},", short),
+                            None => String::new(),
+                        };
+                        println!(
+                            "    {:4}{:15}{}",
+                            short,
+                            BuildArgumentParser!(@long $spec),
+                            $flag_description
+                        );
+                    )*
+
+                    $(
+                        let short = match BuildArgumentParser!(@short $count_spec) {
+                            Some(short) => format!("-{},", short),
+                            None => String::new(),
+                        };
+                        println!(
+                            "    {:4}{:15}{}",
+                            short,
+                            BuildArgumentParser!(@long $count_spec),
+                            $count_description
+                        );
+                    )*
+
+                    $(
+                        let short = match BuildArgumentParser!(@short $option_spec) {
+                            Some(short) => format!("-{},", short),
+                            None => String::new(),
+                        };
+                        println!(
+                            "    {:4}{:15}{}",
+                            short,
+                            concat!(BuildArgumentParser!(@long $option_spec), " VALUE"),
+                            $option_description
+                        );
+                    )*
+
+                    $(
+                        let short = match BuildArgumentParser!(@short $list_spec) {
+                            Some(short) => format!("-{},", short),
+                            None => String::new(),
+                        };
+                        println!(
+                            "    {:4}{:15}{} (repeatable)",
+                            short,
+                            concat!(BuildArgumentParser!(@long $list_spec), " VALUE"),
+                            $list_description
+                        );
                     )*
 
                     std::process::exit(0);
                 };
 
-                if self.version {
+                if let Some(conflict) = self.conflict() {
+                    eprintln!("{}: {}", $name, conflict);
+                    std::process::exit(1);
+                }
+
+                if self.version && self.version_format.is_none() {
                     println!("{} {}", $name, env!("CARGO_PKG_VERSION"));
                     std::process::exit(0);
                 };
@@ -136,7 +465,31 @@ mod test {
             /// Turn flag 1 on
             flag1:('1', "--flag1"),
             /// Turn flag 2 on
-            flag2:('2', "--flag2")
+            flag2:('2', "--flag2"),
+            /// Turn flag 3 on
+            flag3:("--flag3")
+        }
+
+        counts: {
+            /// Count something
+            count1: ('c', "--count1")
+        }
+
+        options: {
+            /// Set option 1
+            option1: ("--option1"),
+            /// Set option 2
+            option2: ('o', "--option2")
+        }
+
+        lists: {
+            /// Add to list 1
+            list1: ('L', "--list1")
+        }
+
+        conflicts: {
+            [flag2, flag3],
+            [flag3, help, version]
         }
     }
 
@@ -171,5 +524,105 @@ mod test {
         ap.process_arguments(&args);
         assert!(!ap.flag1);
         assert!(ap.flag2);
+
+        let mut ap = ArgumentParser::default();
+        let args = ["mock", "--version=json", "-2"].map(String::from);
+        ap.process_arguments(&args);
+        assert!(ap.version);
+        assert_eq!(ap.version_format.as_deref(), Some("json"));
+
+        let mut ap = ArgumentParser::default();
+        let args = ["mock", "-v1v", "--verbose", "-cc"].map(String::from);
+        ap.process_arguments(&args);
+        assert!(ap.flag1);
+        assert_eq!(ap.verbose, 3);
+        assert_eq!(ap.count1, 2);
+
+        let mut ap = ArgumentParser::default();
+        let args = ["mock", "--flag3", "-1"].map(String::from);
+        ap.process_arguments(&args);
+        assert!(ap.flag1);
+        assert!(!ap.flag2);
+        assert!(ap.flag3);
+
+        let mut ap = ArgumentParser::default();
+        let args = ["mock", "--option1", "value", "-1", "binary"].map(String::from);
+        assert_eq!(ap.process_arguments(&args), ["binary"]);
+        assert_eq!(ap.option1.as_deref(), Some("value"));
+        assert!(ap.flag1);
+
+        let mut ap = ArgumentParser::default();
+        let args = ["mock", "--option1=-value", "binary"].map(String::from);
+        assert_eq!(ap.process_arguments(&args), ["binary"]);
+        assert_eq!(ap.option1.as_deref(), Some("-value"));
+
+        let mut ap = ArgumentParser::default();
+        let args = ["mock", "-1o", "value", "binary"].map(String::from);
+        assert_eq!(ap.process_arguments(&args), ["binary"]);
+        assert_eq!(ap.option2.as_deref(), Some("value"));
+        assert!(ap.flag1);
+
+        let mut ap = ArgumentParser::default();
+        let args = ["mock", "-ovalue", "binary"].map(String::from);
+        assert_eq!(ap.process_arguments(&args), ["binary"]);
+        assert_eq!(ap.option2.as_deref(), Some("value"));
+        assert!(ap.list1.is_empty());
+
+        let mut ap = ArgumentParser::default();
+        let args = [
+            "mock",
+            "--list1",
+            "a",
+            "--list1=b",
+            "-1Lc",
+            "-L",
+            "d",
+            "binary",
+        ]
+        .map(String::from);
+        assert_eq!(ap.process_arguments(&args), ["binary"]);
+        assert_eq!(ap.list1, ["a", "b", "c", "d"]);
+        assert!(ap.flag1);
+    }
+
+    #[test]
+    fn conflicts() {
+        let mut ap = ArgumentParser::default();
+        assert_eq!(ap.conflict(), None);
+        ap.flag3 = true;
+        ap.version = true;
+        assert_eq!(
+            ap.conflict().as_deref(),
+            Some("Only one of `--flag3`, `--help`, and `--version` can be used")
+        );
+        ap.flag2 = true;
+        assert_eq!(
+            ap.conflict().as_deref(),
+            Some("`--flag2` and `--flag3` can not be used together")
+        );
+    }
+
+    #[test]
+    fn flag_table() {
+        let longs: Vec<_> = ArgumentParser::FLAGS.iter().map(|flag| flag.long).collect();
+        assert_eq!(
+            longs,
+            [
+                "--flag1",
+                "--flag2",
+                "--flag3",
+                "--help",
+                "--version",
+                "--count1",
+                "--verbose",
+                "--option1",
+                "--option2",
+                "--list1"
+            ]
+        );
+        let option2 = &ArgumentParser::FLAGS[8];
+        assert_eq!(option2.short, Some('o'));
+        assert!(option2.takes_value);
+        assert_eq!(option2.description, " Set option 2");
     }
 }
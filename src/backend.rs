@@ -0,0 +1,256 @@
+//! External listers, like `fd` or `git ls-files`, that can stand in for walking an entry point.
+//!
+//! The command is run in the entry point, and prints one path per line (or per NUL, as with
+//! `git ls-files -z`), relative to it or absolute. What it prints still goes through the pattern's
+//! glob and selectors, but it decides what's searched, so hidden and pruned entries are up to it.
+//! Directories that only appear as the parents of listed paths are searched too. Whatever order
+//! the command prints paths in, they're searched in path order, like a walk.
+//!
+//! The candidates of filter mode are listed the same way, except that nothing is run, and
+//! relative candidates are relative to the current directory rather than the entry point.
+use std::{
+    collections::HashSet,
+    env,
+    io::{self, Read},
+    path::{Component, Path, PathBuf},
+    process::{Command, Output, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::info;
+
+use crate::{
+    errors::{LaxError, LaxResult as Result},
+    index::Entry,
+};
+
+// List the entries under an entry point with a backend command, split on whitespace. Returns
+// `None` if the command is still running at `deadline`, in which case it's killed
+pub(crate) fn list(
+    command: &str,
+    entry_point: &Path,
+    deadline: Option<Instant>,
+) -> Result<Option<Vec<Entry>>> {
+    let failed = |reason: String| LaxError::Backend(command.into(), reason);
+    let mut words = command.split_whitespace();
+    let program = words
+        .next()
+        .ok_or_else(|| failed("The command is empty".into()))?;
+    info!("Listing {} with `{command}`", entry_point.display());
+    let mut lister = Command::new(program);
+    lister
+        .args(words)
+        .current_dir(entry_point)
+        .stdin(Stdio::null());
+    let output = match deadline {
+        None => lister.output().map(Some),
+        Some(deadline) => output_by(&mut lister, deadline),
+    }
+    .map_err(|err| failed(err.to_string()))?;
+    let Some(output) = output else {
+        info!("Giving up on listing {}", entry_point.display());
+        return Ok(None);
+    };
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(failed(match stderr.lines().next() {
+            Some(line) => line.into(),
+            None => output.status.to_string(),
+        }));
+    }
+
+    let canonical = entry_point.canonicalize().ok();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let separator = if stdout.contains('\0') { '\0' } else { '\n' };
+    Ok(Some(entries(stdout.split(separator), |path| {
+        // Listers like `plocate` print absolute paths, which may be outside the entry point
+        if path.is_absolute() {
+            [Some(entry_point), canonical.as_deref()]
+                .into_iter()
+                .flatten()
+                .find_map(|prefix| path.strip_prefix(prefix).ok())
+                .map(PathBuf::from)
+        } else {
+            Some(normalize(path))
+        }
+    })))
+}
+
+// Run a command to completion and collect its output, like `Command::output`, unless it's still
+// running at `deadline`. Then it's killed, and there's no output
+fn output_by(command: &mut Command, deadline: Instant) -> io::Result<Option<Output>> {
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // Read both pipes while waiting, so the command can't fill one and block forever
+    fn read_all(pipe: Option<impl Read + Send + 'static>) -> thread::JoinHandle<Vec<u8>> {
+        thread::spawn(move || {
+            let mut contents = Vec::new();
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_end(&mut contents);
+            }
+            contents
+        })
+    }
+    let stdout = read_all(child.stdout.take());
+    let stderr = read_all(child.stderr.take());
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(Output {
+                status,
+                stdout: stdout.join().unwrap_or_default(),
+                stderr: stderr.join().unwrap_or_default(),
+            }));
+        }
+        if Instant::now() >= deadline {
+            // The readers are left to finish whenever the pipes close, which may be never if the
+            // command started children of its own
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(None);
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+// List the candidates of filter mode that are under an entry point, without touching the
+// filesystem
+pub(crate) fn candidates(candidates: &[String], entry_point: &Path) -> Vec<Entry> {
+    let entry_point = normalize(entry_point);
+    let absolute = normalize(&env::current_dir().unwrap_or_default().join(&entry_point));
+    entries(candidates.iter().map(String::as_str), |path| {
+        let prefix = if path.is_absolute() {
+            &absolute
+        } else {
+            &entry_point
+        };
+        normalize(path).strip_prefix(prefix).ok().map(PathBuf::from)
+    })
+}
+
+// Turn listed paths into entries, in path order, with `relative` making each relative to the entry
+// point. Paths it can't are outside the entry point, and are skipped
+fn entries<'a>(
+    paths: impl Iterator<Item = &'a str>,
+    relative: impl Fn(&Path) -> Option<PathBuf>,
+) -> Vec<Entry> {
+    let mut directories = HashSet::new();
+    let mut entries = Vec::new();
+    for line in paths {
+        let line = line.trim_end_matches('\r');
+        let is_dir = line.ends_with('/');
+        let Some(relative) = relative(Path::new(line.trim_end_matches('/'))) else {
+            continue;
+        };
+        if relative.as_os_str().is_empty() {
+            continue;
+        }
+
+        if !is_dir {
+            entries.push(entry(&relative, false));
+        }
+        let directory = if is_dir {
+            Some(&*relative)
+        } else {
+            relative.parent()
+        };
+        for ancestor in directory.into_iter().flat_map(Path::ancestors) {
+            if directories.insert(ancestor.to_path_buf()) {
+                entries.push(entry(ancestor, true));
+            }
+        }
+    }
+    // The entry point itself is "", which comes first
+    if directories.insert(PathBuf::new()) {
+        entries.push(entry(Path::new(""), true));
+    }
+    entries.sort_by(|a, b| Path::new(&a.relative).cmp(Path::new(&b.relative)));
+    entries
+}
+
+// Drop the "." components of a path, so "./a" and "a" are the same
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
+
+fn entry(relative: &Path, is_dir: bool) -> Entry {
+    Entry::new(
+        relative.to_string_lossy().into_owned(),
+        is_dir,
+        relative.components().count(),
+    )
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listing() {
+        let listed = |command: &str| -> Vec<(String, bool, usize)> {
+            list(command, Path::new("."), None)
+                .unwrap()
+                .unwrap()
+                .into_iter()
+                .map(|entry| (entry.relative, entry.is_dir, entry.depth))
+                .collect()
+        };
+        assert_eq!(
+            listed(r"printf ./a/b\nc/\na/d\n/no/such/path\n"),
+            vec![
+                ("".into(), true, 0),
+                ("a".into(), true, 1),
+                ("a/b".into(), false, 2),
+                ("a/d".into(), false, 2),
+                ("c".into(), true, 1),
+            ]
+        );
+        assert_eq!(listed(r"printf a\0b"), listed(r"printf a\nb"));
+        assert!(matches!(
+            list("false", Path::new("."), None),
+            Err(LaxError::Backend(..))
+        ));
+        assert!(list("lax_no_such_lister", Path::new("."), None).is_err());
+
+        // With a deadline, the output is the same, unless the command is still running by then
+        let soon = || Some(Instant::now() + Duration::from_millis(100));
+        let by_deadline = list(
+            r"printf a/b\nc",
+            Path::new("."),
+            Some(Instant::now() + Duration::from_secs(60)),
+        );
+        assert_eq!(
+            by_deadline.unwrap().unwrap().len(),
+            listed(r"printf a/b\nc").len()
+        );
+        assert!(matches!(
+            list("false", Path::new("."), soon()),
+            Err(LaxError::Backend(..))
+        ));
+        let start = Instant::now();
+        assert!(list("sleep 10", Path::new("."), soon()).unwrap().is_none());
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let candidates: Vec<String> = ["src/a", "./src/b/", "other/c", "/elsewhere/d"]
+            .map(String::from)
+            .into();
+        let relative = |entry_point: &str| -> Vec<(String, bool)> {
+            super::candidates(&candidates, Path::new(entry_point))
+                .into_iter()
+                .map(|entry| (entry.relative, entry.is_dir))
+                .collect()
+        };
+        assert_eq!(
+            relative("./src"),
+            vec![("".into(), true), ("a".into(), false), ("b".into(), true)]
+        );
+        assert_eq!(
+            relative("/elsewhere"),
+            vec![("".into(), true), ("d".into(), false)]
+        );
+    }
+}
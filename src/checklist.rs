@@ -0,0 +1,143 @@
+//! The state of a checklist menu, where the user moves between candidates, checks the ones they
+//! want, and confirms, rather than typing selectors.
+//!
+//! This only keeps track of the cursor and which candidates are checked. Drawing the list and
+//! reading keys is up to the [`SelectionMenu`](crate::SelectionMenu) using it, which passes each
+//! key to [`Checklist::press`] until it gives a [`MenuOutcome`].
+use crate::{Candidate, MenuOutcome};
+
+/// A key the user pressed in a checklist.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    /// Move the cursor to the previous candidate, wrapping around to the last.
+    Up,
+    /// Move the cursor to the next candidate, wrapping around to the first.
+    Down,
+    /// Check or uncheck the candidate under the cursor.
+    Toggle,
+    /// Check every candidate, or uncheck them all if they're already checked.
+    ToggleAll,
+    /// Load more matches, if the list was cut short.
+    More,
+    /// Use the checked candidates, or the one under the cursor if none are checked.
+    Confirm,
+    /// Give up.
+    Cancel,
+}
+
+/// Which candidates are checked, and which one the cursor is on.
+#[derive(Debug, Clone)]
+pub struct Checklist {
+    // Selector IDs of the candidates, in the order they're shown
+    ids: Vec<usize>,
+    checked: Vec<bool>,
+    cursor: usize,
+}
+
+impl Checklist {
+    /// Start a checklist of `candidates`, with nothing checked and the cursor on the first.
+    pub fn new(candidates: &[Candidate]) -> Self {
+        Checklist {
+            ids: candidates.iter().map(|candidate| candidate.id).collect(),
+            checked: vec![false; candidates.len()],
+            cursor: 0,
+        }
+    }
+
+    /// The position of the candidate the cursor is on.
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Whether the candidate at `position` is checked.
+    pub fn is_checked(&self, position: usize) -> bool {
+        self.checked.get(position).copied().unwrap_or(false)
+    }
+
+    /// Handle a key. Returns what to tell the expander once the user is done, which is the
+    /// checked candidates as selectors, `m` to load more, or that they gave up.
+    pub fn press(&mut self, key: Key) -> Option<MenuOutcome> {
+        let len = self.ids.len();
+        match key {
+            Key::Up if len > 0 => self.cursor = (self.cursor + len - 1) % len,
+            Key::Down if len > 0 => self.cursor = (self.cursor + 1) % len,
+            Key::Up | Key::Down => {}
+            Key::Toggle => {
+                if let Some(checked) = self.checked.get_mut(self.cursor) {
+                    *checked = !*checked;
+                }
+            }
+            Key::ToggleAll => {
+                let all = self.checked.iter().all(|&checked| checked);
+                self.checked.fill(!all);
+            }
+            Key::More => return Some(MenuOutcome::Selectors("m".into())),
+            Key::Confirm => {
+                let mut ids: Vec<String> = self
+                    .ids
+                    .iter()
+                    .zip(&self.checked)
+                    .filter(|(_, &checked)| checked)
+                    .map(|(id, _)| id.to_string())
+                    .collect();
+                if ids.is_empty() {
+                    ids.extend(self.ids.get(self.cursor).map(usize::to_string));
+                }
+                return Some(MenuOutcome::Selectors(ids.join(",")));
+            }
+            Key::Cancel => return Some(MenuOutcome::Cancelled),
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Match;
+
+    #[test]
+    fn checking() {
+        let candidates: Vec<Candidate> = [3, 1, 2]
+            .into_iter()
+            .map(|id| Candidate {
+                id,
+                matched: Match {
+                    path: format!("path{id}").into(),
+                    is_dir: false,
+                    size: 0,
+                    modified: None,
+                    depth: 1,
+                },
+                matched_part: 0..5,
+            })
+            .collect();
+        let selectors = |outcome| match outcome {
+            Some(MenuOutcome::Selectors(selectors)) => selectors,
+            outcome => panic!("Expected selectors, got {outcome:?}"),
+        };
+
+        // Without anything checked, the candidate under the cursor is used
+        let mut checklist = Checklist::new(&candidates);
+        assert_eq!(checklist.press(Key::Up), None);
+        assert_eq!(checklist.cursor(), 2);
+        assert_eq!(selectors(checklist.press(Key::Confirm)), "2");
+
+        let mut checklist = Checklist::new(&candidates);
+        checklist.press(Key::Toggle);
+        checklist.press(Key::Down);
+        checklist.press(Key::Down);
+        checklist.press(Key::Toggle);
+        assert!(checklist.is_checked(0) && !checklist.is_checked(1));
+        assert_eq!(selectors(checklist.press(Key::Confirm)), "3,2");
+
+        checklist.press(Key::ToggleAll);
+        assert_eq!(selectors(checklist.press(Key::Confirm)), "3,1,2");
+        checklist.press(Key::ToggleAll);
+        checklist.press(Key::Down);
+        assert_eq!(selectors(checklist.press(Key::Confirm)), "3");
+
+        assert_eq!(selectors(checklist.press(Key::More)), "m");
+        assert_eq!(checklist.press(Key::Cancel), Some(MenuOutcome::Cancelled));
+    }
+}
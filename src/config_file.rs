@@ -0,0 +1,400 @@
+//! Load [`Config`] settings from a configuration file.
+//!
+//! The file is a list of `key = value` pairs, optionally grouped under `[section]` headers. Blank
+//! lines and lines starting with '#' are ignored:
+//!
+//! ```text
+//! search_hidden = true
+//!
+//! [bookmarks]
+//! docs = ~/Documents
+//!
+//! [under ~/work]
+//! prune = target:node_modules
+//! ```
+use std::{env, fs, path::Path, path::PathBuf, time::Duration};
+
+use crate::{
+    errors::{LaxError, LaxResult as Result},
+    Access, Config, EntryPointRule, EntryType, Expander, MatchOrder, PathPart, PathStyle,
+    SymlinkParent,
+};
+
+/// Return the path of the user's configuration file: `$LAX_CONFIG` if set, otherwise
+/// `$XDG_CONFIG_HOME/lax/config` or `~/.config/lax/config`.
+pub fn default_config_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("LAX_CONFIG") {
+        return Some(path.into());
+    }
+    let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => dirs::home_dir()?.join(".config"),
+    };
+    Some(config_dir.join("lax").join("config"))
+}
+
+impl Config {
+    /// Apply the settings in a configuration file on top of this config.
+    pub fn load_file(&mut self, path: &Path) -> Result<()> {
+        let contents = fs::read_to_string(path).map_err(|err| {
+            LaxError::InvalidConfig(format!("Could not read config file {path:?}: {err}"))
+        })?;
+        self.load_str(&contents)
+            .map_err(|err| LaxError::InvalidConfig(format!("Invalid config file {path:?}: {err}")))
+    }
+
+    /// Apply settings in the configuration file format on top of this config.
+    pub fn load_str(&mut self, contents: &str) -> Result<()> {
+        let mut section = String::new();
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[') {
+                section = name
+                    .strip_suffix(']')
+                    .ok_or_else(|| {
+                        invalid(format!("Line {}: unterminated section header", number + 1))
+                    })?
+                    .trim()
+                    .into();
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| invalid(format!("Line {}: expected 'key = value'", number + 1)))?;
+            let (key, value) = (key.trim(), value.trim());
+            self.set(&section, key, value)
+                .map_err(|err| invalid(format!("Line {}: {err}", number + 1)))?;
+        }
+        Ok(())
+    }
+
+    fn set(&mut self, section: &str, key: &str, value: &str) -> Result<()> {
+        match (section, key) {
+            ("", "match_with_dirs") => self.match_with_dirs = parse_bool(value)?,
+            ("", "match_with_files") => self.match_with_files = parse_bool(value)?,
+            ("", "append_dir_slash") => self.append_dir_slash = parse_bool(value)?,
+            ("", "resolve_symlinks") => self.resolve_symlinks = parse_bool(value)?,
+            ("", "transform_files_to_dirs") => self.transform_files_to_dirs = parse_bool(value)?,
+            ("", "symlink_parent") => {
+                self.symlink_parent = match value {
+                    "link" => SymlinkParent::Link,
+                    "target" => SymlinkParent::Target,
+                    _ => {
+                        return Err(invalid(format!(
+                            "Expected 'link' or 'target', not '{value}'"
+                        )))
+                    }
+                }
+            }
+            ("", "path_style") => {
+                self.path_style = match value {
+                    "matched" => PathStyle::AsMatched,
+                    "absolute" => PathStyle::Absolute,
+                    "relative" => PathStyle::Relative,
+                    "canonical" => PathStyle::Canonical,
+                    _ => {
+                        return Err(invalid(format!(
+                        "Expected 'matched', 'absolute', 'relative', or 'canonical', not '{value}'"
+                    )))
+                    }
+                }
+            }
+            ("", "types") => {
+                self.entry_types = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .map(|name| {
+                        EntryType::parse(name).ok_or_else(|| {
+                            invalid(format!(
+                                "Expected types among 'f', 'd', 'l', 'x', and 'e', not '{name}'"
+                            ))
+                        })
+                    })
+                    .collect::<Result<_>>()?
+            }
+            ("", "extensions") => {
+                self.extensions = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|extension| !extension.is_empty())
+                    .map(String::from)
+                    .collect()
+            }
+            ("", "owner") => {
+                self.owner = (!value.is_empty()).then(|| value.into());
+            }
+            ("", "access") => {
+                self.access = if value.is_empty() {
+                    Access::default()
+                } else {
+                    Access::parse(value).ok_or_else(|| {
+                        invalid(format!("Expected some of 'r', 'w', and 'x', not '{value}'"))
+                    })?
+                }
+            }
+            ("", "path_part") => {
+                self.path_part = match value {
+                    "whole" => PathPart::Whole,
+                    "basename" => PathPart::Basename,
+                    "dirname" => PathPart::Dirname,
+                    _ => {
+                        return Err(invalid(format!(
+                            "Expected 'whole', 'basename', or 'dirname', not '{value}'"
+                        )))
+                    }
+                }
+            }
+            ("", "canonicalize_parent") => self.canonicalize_parent = parse_bool(value)?,
+            ("", "search_hidden") => self.search_hidden = parse_bool(value)?,
+            ("", "sort") => {
+                self.sort = MatchOrder::parse(value).ok_or_else(|| {
+                    invalid(format!(
+                        "Expected 'none', 'path', 'name', 'natural', 'mtime', or 'size', \
+                         not '{value}'"
+                    ))
+                })?
+            }
+            ("", "search_path") => self.search_path = env::split_paths(value).collect(),
+            ("", "daemon_roots") => self.daemon_roots = env::split_paths(value).collect(),
+            ("", "echo_selection") => self.echo_selection = parse_bool(value)?,
+            ("", "menu_default") => {
+                self.menu_default = match value {
+                    "" => None,
+                    value => {
+                        Expander::parse_selectors(value)?;
+                        Some(value.into())
+                    }
+                }
+            }
+            ("", "default_selector") => {
+                self.default_selectors = match value {
+                    "" => None,
+                    value => {
+                        // Catch mistakes now, rather than on the first ambiguous pattern
+                        Expander::parse_selectors(value)?;
+                        Some(value.into())
+                    }
+                }
+            }
+            ("", "max_results") => {
+                self.max_results = match value.parse() {
+                    Ok(0) | Err(_) => {
+                        return Err(invalid(format!(
+                            "Expected a positive number, not '{value}'"
+                        )))
+                    }
+                    Ok(max_results) => Some(max_results),
+                }
+            }
+            ("", "max_matches") => {
+                self.max_matches = match value.parse() {
+                    _ if value.is_empty() => None,
+                    Ok(0) | Err(_) => {
+                        return Err(invalid(format!(
+                            "Expected a positive number, not '{value}'"
+                        )))
+                    }
+                    Ok(max_matches) => Some(max_matches),
+                }
+            }
+            ("", "timeout") => {
+                self.timeout = match value {
+                    "" => None,
+                    value => Some(parse_duration(value).ok_or_else(|| {
+                        invalid(format!(
+                            "Expected a duration, like 5s or 500ms, not '{value}'"
+                        ))
+                    })?),
+                }
+            }
+            ("", "reuse_selections") => self.reuse_selections = parse_bool(value)?,
+            ("", "literal_marker") => {
+                self.literal_marker = match value {
+                    "" => None,
+                    value => Some(value.into()),
+                }
+            }
+            ("", "default_entry_point") => {
+                self.default_entry_point = match value {
+                    "" => None,
+                    value => Some(crate::expand_tilde(value).as_ref().into()),
+                }
+            }
+            ("", "root_markers") => {
+                self.root_markers = value
+                    .split(':')
+                    .filter(|marker| !marker.is_empty())
+                    .map(String::from)
+                    .collect()
+            }
+            ("", "history_file") => {
+                self.history_file = match value {
+                    "" => None,
+                    value => Some(crate::expand_tilde(value).as_ref().into()),
+                }
+            }
+            ("", "frecency_file") => {
+                self.frecency_file = match value {
+                    "" => None,
+                    value => Some(crate::expand_tilde(value).as_ref().into()),
+                }
+            }
+            ("", "cache_dir") => {
+                self.cache_dir = match value {
+                    "" => None,
+                    value => Some(crate::expand_tilde(value).as_ref().into()),
+                }
+            }
+            ("", "temp_dir") => {
+                self.temp_dir = match value {
+                    "" => None,
+                    value => Some(crate::expand_tilde(value).as_ref().into()),
+                }
+            }
+            ("bookmarks", name) => {
+                self.bookmarks.insert(name.into(), value.into());
+            }
+            (section, key) if section.starts_with("under ") => {
+                let path = PathBuf::from(section["under ".len()..].trim());
+                let rule = match self
+                    .entry_point_rules
+                    .iter_mut()
+                    .position(|rule| rule.path == path)
+                {
+                    Some(index) => &mut self.entry_point_rules[index],
+                    None => {
+                        self.entry_point_rules.push(EntryPointRule {
+                            path,
+                            ..Default::default()
+                        });
+                        self.entry_point_rules.last_mut().unwrap()
+                    }
+                };
+                match key {
+                    "search_hidden" => rule.search_hidden = Some(parse_bool(value)?),
+                    "prune" => rule.prune = value.split(':').map(String::from).collect(),
+                    "backend" => {
+                        rule.backend = match value {
+                            "" => None,
+                            value => Some(value.into()),
+                        }
+                    }
+                    _ => return Err(invalid(format!("Unknown setting '{key}' in '[{section}]'"))),
+                }
+            }
+            ("", key) => return Err(invalid(format!("Unknown setting '{key}'"))),
+            (section, _) => return Err(invalid(format!("Unknown section '[{section}]'"))),
+        }
+        Ok(())
+    }
+}
+
+/// Parse a positive duration like `5s`, `500ms`, `1.5m`, or `1h`. A bare number is in seconds.
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let (number, unit) = value.split_at(
+        value
+            .find(|c: char| c.is_ascii_alphabetic())
+            .unwrap_or(value.len()),
+    );
+    let seconds = match unit {
+        "ms" => 0.001,
+        "" | "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => return None,
+    };
+    let number: f64 = number.trim().parse().ok()?;
+    Duration::try_from_secs_f64(number * seconds)
+        .ok()
+        .filter(|duration| !duration.is_zero())
+}
+
+fn parse_bool(value: &str) -> Result<bool> {
+    match value {
+        "true" | "yes" | "on" => Ok(true),
+        "false" | "no" | "off" => Ok(false),
+        _ => Err(invalid(format!(
+            "Expected 'true' or 'false', not '{value}'"
+        ))),
+    }
+}
+
+// A config file error, with a message saying what's wrong
+fn invalid(message: String) -> LaxError {
+    LaxError::InvalidConfig(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_config() {
+        let mut config = Config::default();
+        config
+            .load_str(
+                "# Comment\n\
+                 search_hidden = true\n\
+                 default_selector = n\n\
+                 root_markers = .git:Cargo.toml\n\
+                 cache_dir = /tmp/lax\n\
+                 temp_dir = /tmp/lax/tmp\n\
+                 \n\
+                 [bookmarks]\n\
+                 docs = ~/Documents\n\
+                 [under ~/work]\n\
+                 prune = target:node_modules\n\
+                 [under /etc]\n\
+                 search_hidden = true\n\
+                 backend = git ls-files\n",
+            )
+            .unwrap();
+        assert!(config.search_hidden);
+        assert_eq!(config.default_selectors.as_deref(), Some("n"));
+        assert_eq!(config.root_markers, [".git", "Cargo.toml"]);
+        assert_eq!(config.cache_dir, Some(PathBuf::from("/tmp/lax")));
+        assert_eq!(config.temp_dir, Some(PathBuf::from("/tmp/lax/tmp")));
+        assert_eq!(config.bookmarks["docs"], PathBuf::from("~/Documents"));
+        assert_eq!(config.entry_point_rules[0].path, PathBuf::from("~/work"));
+        assert_eq!(
+            config.entry_point_rules[0].prune,
+            ["target", "node_modules"]
+        );
+        assert_eq!(config.entry_point_rules[1].search_hidden, Some(true));
+        assert_eq!(
+            config.entry_point_rules[1].backend.as_deref(),
+            Some("git ls-files")
+        );
+
+        assert!(Config::default().load_str("nonsense = true").is_err());
+        assert!(Config::default().load_str("search_hidden = maybe").is_err());
+        assert!(Config::default().load_str("default_selector = x").is_err());
+        assert!(Config::default().load_str("[nonsense]\nfoo = bar").is_err());
+        assert!(Config::default().load_str("[under /]\nfoo = bar").is_err());
+        assert!(Config::default().load_str("just some words").is_err());
+        assert!(matches!(
+            Config::default().load_str("max_results = 0"),
+            Err(LaxError::InvalidConfig(message)) if message.starts_with("Line 1: ")
+        ));
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("5s"), Some(Duration::from_secs(5)));
+        assert_eq!(parse_duration("5"), Some(Duration::from_secs(5)));
+        assert_eq!(parse_duration("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_duration("1.5m"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("1h"), Some(Duration::from_secs(3600)));
+        for value in ["", "0s", "-1s", "5x", "s", "inf", "nan"] {
+            assert_eq!(parse_duration(value), None, "{value}");
+        }
+        assert!(Config::default().load_str("timeout = soon").is_err());
+    }
+}
@@ -0,0 +1,345 @@
+//! A daemon that keeps trees indexed in memory, and answers other lax invocations' searches over
+//! a Unix socket, so expansion doesn't have to walk them at all.
+//!
+//! The daemon indexes the trees under [`Config::daemon_roots`], and any entry point under them
+//! that it's asked about. On Linux, it watches every directory it indexes with inotify, and
+//! forgets an index as soon as anything in it changes. Elsewhere, it checks each directory's
+//! modification time on every search instead, like the on-disk index. Searches outside its roots,
+//! or made while no daemon is running, are done without it.
+//!
+//! A request is one line: `1` if hidden entries are searched or `0` if not, the names of the
+//! directories to prune separated by ':', and the canonical entry point, separated by tabs. The
+//! response is `ok` followed by the entries, one per line in the index format, or just `miss`.
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver},
+    time::{Duration, Instant, SystemTime},
+};
+
+use log::{debug, info, warn};
+
+use crate::{
+    errors::{LaxError, LaxResult as Result},
+    expand_tilde,
+    history::{escape, unescape},
+    index::{self, Entry},
+    walk::WalkFilter,
+    Config,
+};
+
+use watch::Watcher;
+
+// How long the daemon waits on a client to send its request, or take the response, before moving
+// on to the next. Clients are answered one at a time, so one that's stuck mustn't hold up the rest
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(2);
+
+// The daemon's socket, in the cache directory
+fn socket_path(cache_dir: &Path) -> PathBuf {
+    cache_dir.join("daemon.sock")
+}
+
+// Ask the daemon for the entries under an entry point, walked with `filter`. Returns `None` if no
+// daemon is running, it doesn't index the entry point, or it stops answering by `deadline`
+pub(crate) fn query(
+    cache_dir: &Path,
+    entry_point: &Path,
+    filter: &WalkFilter,
+    deadline: Option<Instant>,
+) -> Option<Vec<Entry>> {
+    let canonical = entry_point.canonicalize().ok()?;
+    let mut stream = UnixStream::connect(socket_path(cache_dir)).ok()?;
+    if let Some(deadline) = deadline {
+        // A timeout of zero isn't allowed, and there's no time left to ask in anyway
+        let remaining = Some(deadline.saturating_duration_since(Instant::now()))
+            .filter(|remaining| !remaining.is_zero())?;
+        stream.set_read_timeout(Some(remaining)).ok()?;
+        stream.set_write_timeout(Some(remaining)).ok()?;
+    }
+    writeln!(
+        stream,
+        "{}\t{}\t{}",
+        u8::from(filter.search_hidden),
+        filter.prune.join(":"),
+        escape(&canonical.to_string_lossy())
+    )
+    .ok()?;
+
+    let mut lines = BufReader::new(stream).lines();
+    if lines.next()?.ok()? != "ok" {
+        return None;
+    }
+    info!("Searching the daemon's index of {}", entry_point.display());
+    lines.map(|line| Entry::parse(&line.ok()?)).collect()
+}
+
+/// Index the trees under [`Config::daemon_roots`], and answer searches under them until killed.
+/// The socket is kept in [`Config::cache_dir`], so both need to be set.
+pub fn serve(config: &Config) -> Result<()> {
+    let cache_dir = config
+        .cache_dir
+        .as_ref()
+        .ok_or_else(|| LaxError::InvalidConfig("The daemon needs `cache_dir` to be set".into()))?;
+    if config.daemon_roots.is_empty() {
+        return Err(LaxError::InvalidConfig(
+            "The daemon needs `daemon_roots` to be set".into(),
+        ));
+    }
+
+    let socket = socket_path(cache_dir);
+    if UnixStream::connect(&socket).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("A lax daemon is already listening on {}", socket.display()),
+        )
+        .into());
+    }
+    // Whoever left this behind isn't listening anymore
+    let _ = fs::remove_file(&socket);
+    fs::create_dir_all(cache_dir)?;
+    let listener = UnixListener::bind(&socket)?;
+
+    let (sender, changed) = mpsc::channel();
+    let mut daemon = Daemon {
+        roots: Vec::new(),
+        indexes: HashMap::new(),
+        watcher: Watcher::new(sender)?,
+        changed,
+    };
+    for root in &config.daemon_roots {
+        match PathBuf::from(expand_tilde(&root.to_string_lossy()).as_ref()).canonicalize() {
+            Ok(root) => daemon.roots.push(root),
+            Err(err) => warn!("Not indexing {}: {err}", root.display()),
+        }
+    }
+    // Index the roots now, rather than on the first search
+    let filter = WalkFilter {
+        search_hidden: config.search_hidden,
+        named: Vec::new(),
+        prune: Vec::new(),
+        backend: None,
+    };
+    for root in daemon.roots.clone() {
+        let filter = filter.for_entry_point(&root, &config.entry_point_rules);
+        daemon.entries(&root, filter);
+    }
+
+    info!("Listening on {}", socket.display());
+    for stream in listener.incoming() {
+        if let Err(err) = stream.and_then(|stream| daemon.answer(stream)) {
+            debug!("Couldn't answer a search: {err}");
+        }
+    }
+    Ok(())
+}
+
+struct Daemon {
+    roots: Vec<PathBuf>,
+    // Indexes by entry point and the settings they were walked with
+    indexes: HashMap<(PathBuf, String), Indexed>,
+    watcher: Watcher,
+    // Directories the watcher saw change
+    changed: Receiver<PathBuf>,
+}
+
+struct Indexed {
+    entries: Vec<Entry>,
+    // When the walk started
+    built: SystemTime,
+    // Whether every directory in it is watched, so it doesn't need checking
+    watched: bool,
+}
+
+impl Daemon {
+    fn answer(&mut self, mut stream: UnixStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+        stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+        let mut request = String::new();
+        BufReader::new(&stream).read_line(&mut request)?;
+        let fields: Vec<&str> = request.trim_end_matches('\n').splitn(3, '\t').collect();
+        let entry_point = match fields[..] {
+            [search_hidden, prune, entry_point] => {
+                let entry_point = PathBuf::from(unescape(entry_point));
+                let filter = WalkFilter {
+                    search_hidden: search_hidden == "1",
+                    named: Vec::new(),
+                    prune: prune
+                        .split(':')
+                        .filter(|name| !name.is_empty())
+                        .map(String::from)
+                        .collect(),
+                    backend: None,
+                };
+                Some((entry_point, filter))
+            }
+            _ => None,
+        };
+        let Some((entry_point, filter)) = entry_point
+            .filter(|(entry_point, _)| self.roots.iter().any(|root| entry_point.starts_with(root)))
+        else {
+            return stream.write_all(b"miss\n");
+        };
+
+        debug!("Searching {}", entry_point.display());
+        let mut response = String::from("ok\n");
+        for entry in self.entries(&entry_point, filter) {
+            response.push_str(&entry.to_line());
+            response.push('\n');
+        }
+        stream.write_all(response.as_bytes())
+    }
+
+    // Get the entries under an entry point, indexing it if it isn't already, or has changed
+    fn entries(&mut self, entry_point: &Path, filter: WalkFilter) -> &[Entry] {
+        for directory in self.changed.try_iter() {
+            self.indexes.retain(|(indexed, _), _| {
+                let unchanged = !directory.starts_with(indexed) && !indexed.starts_with(&directory);
+                if !unchanged {
+                    debug!("Forgetting index of {}", indexed.display());
+                }
+                unchanged
+            });
+        }
+
+        let key = (entry_point.to_owned(), filter.index_settings());
+        let fresh = self.indexes.get(&key).is_some_and(|indexed| {
+            indexed.watched || index::up_to_date(entry_point, &indexed.entries, indexed.built)
+        });
+        if !fresh {
+            info!("Indexing {}", entry_point.display());
+            let built = SystemTime::now();
+            let entries = index::build(entry_point, None, |entry| filter.allows(entry));
+            // Changes made before a directory was watched could have been missed
+            let watched = entries
+                .iter()
+                .filter(|entry| entry.is_dir)
+                .all(|entry| self.watcher.watch(&entry_point.join(&entry.relative)))
+                && index::up_to_date(entry_point, &entries, built);
+            self.indexes.insert(
+                key.clone(),
+                Indexed {
+                    entries,
+                    built,
+                    watched,
+                },
+            );
+        }
+        &self.indexes[&key].entries
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod watch {
+    use std::{
+        collections::HashMap,
+        ffi::CString,
+        io, mem,
+        os::unix::ffi::OsStrExt,
+        path::{Path, PathBuf},
+        ptr,
+        sync::{mpsc::Sender, Arc, Mutex},
+        thread,
+    };
+
+    // Entries being added, removed, or renamed, including the directory itself
+    const EVENTS: u32 = libc::IN_CREATE
+        | libc::IN_DELETE
+        | libc::IN_MOVED_FROM
+        | libc::IN_MOVED_TO
+        | libc::IN_DELETE_SELF
+        | libc::IN_MOVE_SELF
+        | libc::IN_ONLYDIR;
+
+    // Watches directories with inotify, and sends the ones that change
+    pub(super) struct Watcher {
+        fd: i32,
+        directories: Arc<Mutex<HashMap<i32, PathBuf>>>,
+    }
+
+    impl Watcher {
+        pub(super) fn new(changed: Sender<PathBuf>) -> io::Result<Self> {
+            // SAFETY: No pointers involved
+            let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let directories = Arc::new(Mutex::new(HashMap::new()));
+            let watched = Arc::clone(&directories);
+            thread::spawn(move || read_events(fd, &watched, &changed));
+            Ok(Watcher { fd, directories })
+        }
+
+        // Start watching a directory. Returns false if it can't be, like when there are too
+        // many watches
+        pub(super) fn watch(&self, directory: &Path) -> bool {
+            let Ok(path) = CString::new(directory.as_os_str().as_bytes()) else {
+                return false;
+            };
+            // SAFETY: `path` is a valid C string that outlives the call
+            let wd = unsafe { libc::inotify_add_watch(self.fd, path.as_ptr(), EVENTS) };
+            if wd < 0 {
+                return false;
+            }
+            self.directories
+                .lock()
+                .unwrap()
+                .insert(wd, directory.to_owned());
+            true
+        }
+    }
+
+    fn read_events(fd: i32, directories: &Mutex<HashMap<i32, PathBuf>>, changed: &Sender<PathBuf>) {
+        let mut buffer = [0u8; 4096];
+        loop {
+            // SAFETY: `buffer` is valid for writes of its length
+            let len = unsafe { libc::read(fd, buffer.as_mut_ptr().cast(), buffer.len()) };
+            if len < 0 && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            let Ok(len) = usize::try_from(len) else {
+                return;
+            };
+
+            let mut offset = 0;
+            while offset + mem::size_of::<libc::inotify_event>() <= len {
+                // SAFETY: The kernel only writes whole events, and the buffer might not be
+                // aligned for them
+                let event: libc::inotify_event =
+                    unsafe { ptr::read_unaligned(buffer.as_ptr().add(offset).cast()) };
+                offset += mem::size_of::<libc::inotify_event>() + event.len as usize;
+                // If events were dropped, anything could have changed
+                let directory = if event.mask & libc::IN_Q_OVERFLOW != 0 {
+                    Some(PathBuf::from("/"))
+                } else {
+                    directories.lock().unwrap().get(&event.wd).cloned()
+                };
+                if let Some(directory) = directory {
+                    if changed.send(directory).is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod watch {
+    use std::{io, path::Path, path::PathBuf, sync::mpsc::Sender};
+
+    // Without inotify, nothing is watched, so indexes are checked on every search instead
+    pub(super) struct Watcher;
+
+    impl Watcher {
+        pub(super) fn new(_changed: Sender<PathBuf>) -> io::Result<Self> {
+            Ok(Watcher)
+        }
+
+        pub(super) fn watch(&self, _directory: &Path) -> bool {
+            false
+        }
+    }
+}
@@ -0,0 +1,136 @@
+//! Run a command once per path, xargs-style, for `--each`.
+use std::{
+    io::{self, Write},
+    process::{Command, Output, Stdio},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+use crate::signals;
+
+/// Run a command template once per path, substituting each '{}' with the path, or appending the
+/// path if there's no '{}'. Up to `jobs` commands run at once.
+///
+/// Returns the exit code lax should exit with: 0 if every run succeeded, otherwise 123, like
+/// xargs. If the command can't be run at all, 127. If a run is interrupted with Ctrl-C, or lax is
+/// sent a signal like SIGTERM, no more are started, and the code is 128 + the signal number, like
+/// a shell.
+pub fn run(template: &str, paths: &[String], jobs: usize) -> i32 {
+    let words: Vec<&str> = template.split_whitespace().collect();
+    let Some(program) = words.first() else {
+        eprintln!("lax: --each needs a command to run");
+        return 1;
+    };
+    let has_placeholder = words.iter().any(|word| word.contains("{}"));
+    let command = |path: &str| {
+        let mut command = Command::new(program.replace("{}", path));
+        command.args(words[1..].iter().map(|word| word.replace("{}", path)));
+        if !has_placeholder {
+            command.arg(path);
+        }
+        command
+    };
+
+    if jobs <= 1 {
+        let mut exit_code = 0;
+        for path in paths {
+            if let Some(signal) = signals::received() {
+                return 128 + signal;
+            }
+            let status = command(path).spawn().and_then(|mut child| {
+                let _watch = signals::watch(child.id());
+                child.wait()
+            });
+            match status {
+                Ok(status) if status.success() => (),
+                Ok(status) => {
+                    if let Some(signal) = signals::interruption(&status).or_else(signals::received)
+                    {
+                        return 128 + signal;
+                    }
+                    exit_code = 123
+                }
+                Err(err) => {
+                    // If it couldn't run once, it won't run for the other paths either
+                    eprintln!("lax: '{}': {}", program, err);
+                    return 127;
+                }
+            }
+        }
+        return match signals::received() {
+            Some(signal) => 128 + signal,
+            None => exit_code,
+        };
+    }
+
+    // Each worker takes the next unclaimed path until they run out. Output is captured and
+    // written all at once, so concurrent commands' output doesn't interleave.
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let interrupted = Mutex::new(None);
+    let spawn_error = Mutex::new(None);
+    thread::scope(|scope| {
+        for _ in 0..jobs.min(paths.len()) {
+            scope.spawn(|| {
+                while let Some(path) = paths.get(next.fetch_add(1, Ordering::SeqCst)) {
+                    if signals::received().is_some() {
+                        break;
+                    }
+                    match run_captured(command(path)) {
+                        Ok(output) => {
+                            write_output(&output);
+                            if let Some(signal) = signals::interruption(&output.status) {
+                                *interrupted.lock().unwrap() = Some(signal);
+                                next.store(paths.len(), Ordering::SeqCst);
+                            }
+                            if !output.status.success() {
+                                failed.store(true, Ordering::SeqCst);
+                            }
+                        }
+                        Err(err) => {
+                            *spawn_error.lock().unwrap() = Some(err);
+                            // Stop handing out paths
+                            next.store(paths.len(), Ordering::SeqCst);
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    if let Some(err) = spawn_error.into_inner().unwrap() {
+        eprintln!("lax: '{}': {}", program, err);
+        return 127;
+    }
+    if let Some(signal) = interrupted.into_inner().unwrap().or_else(signals::received) {
+        return 128 + signal;
+    }
+    if failed.into_inner() {
+        123
+    } else {
+        0
+    }
+}
+
+// Like Command::output(), but with signals to lax forwarded to the command while it runs
+fn run_captured(mut command: Command) -> io::Result<Output> {
+    let child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let _watch = signals::watch(child.id());
+    child.wait_with_output()
+}
+
+fn write_output(output: &Output) {
+    // Hold both locks so another command's stderr can't slip in between
+    let mut stdout = io::stdout().lock();
+    let mut stderr = io::stderr().lock();
+    let _ = stdout.write_all(&output.stdout);
+    let _ = stdout.flush();
+    let _ = stderr.write_all(&output.stderr);
+}
@@ -0,0 +1,134 @@
+//! The errors the library returns.
+use std::{io, time::Duration};
+
+use thiserror::Error;
+
+/// A result with a [`LaxError`].
+pub type LaxResult<T> = Result<T, LaxError>;
+
+/// A failure of the library, by kind, so callers can handle some specially, such as by exiting
+/// with a distinct code.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum LaxError {
+    /// A pattern matched nothing. Also has similar patterns that would have matched, if any.
+    #[error("Could not match pattern: \"{0}\"{}", suggestion_list(.1))]
+    NoMatches(String, Vec<String>),
+    /// A selector asked for a match that doesn't exist.
+    #[error("Selector index out of range: {0}")]
+    SelectorOutOfRange(String),
+    /// A selector couldn't be parsed.
+    #[error("{0}")]
+    InvalidSelector(String),
+    /// A pattern couldn't be parsed.
+    #[error("{0}")]
+    InvalidPattern(String),
+    /// A pattern's entry point doesn't exist.
+    #[error(
+        "Entry point {0:?} doesn't exist.\n\t\
+         Reminder: the @pattern syntax is \"@[%][ENTRY_POINT/**/]GLOB_PATTERN[^SELECTOR]\".\n\t\
+         Make sure the bit before the first \"/**/\" is a valid directory"
+    )]
+    EntryPointMissing(String),
+    /// A pattern starts from the repository root, but we're not in a repository. Also has the
+    /// [root markers](crate::Config::root_markers) that were looked for.
+    #[error(
+        "Cannot get repository root - no directory above this one has any of: {}",
+        .0.join(", ")
+    )]
+    RepositoryRootNotFound(Vec<String>),
+    /// A pattern matched several paths, and there was no menu to ask the user which they meant.
+    #[error(
+        "Ambiguous pattern \"{0}\". Add a selector to choose from:{}",
+        candidate_list(.1)
+    )]
+    AmbiguousPattern(String, Vec<String>),
+    /// A pattern matched more than [`Config::max_matches`](crate::Config::max_matches) paths,
+    /// and the user didn't confirm going on.
+    #[error("Pattern \"{0}\" matches more than {1} paths. Narrow it down, or raise `max_matches`")]
+    TooManyMatches(String, usize),
+    /// Searching for a pattern's matches took longer than
+    /// [`Config::timeout`](crate::Config::timeout). Also has what it found before then.
+    #[error(
+        "Searching for \"{0}\" took longer than {1:?}{}",
+        found_so_far(.2)
+    )]
+    TimedOut(String, Duration, Vec<String>),
+    /// The user cancelled the menu instead of choosing.
+    #[error("Selection cancelled")]
+    Cancelled,
+    /// Selectors picked none of a pattern's matches.
+    #[error("{0}")]
+    NothingSelected(String),
+    /// A bookmark pattern named a bookmark that isn't in the config.
+    #[error("No such bookmark: '{0}'")]
+    UnknownBookmark(String),
+    /// [`Config::owner`](crate::Config::owner) named a user that doesn't exist.
+    #[error("No such user: '{0}'")]
+    UnknownUser(String),
+    /// An '@!' pattern was used, but history is disabled.
+    #[error("Cannot use '@!' patterns when history is disabled")]
+    HistoryDisabled,
+    /// An '@!' pattern asked for an invocation older than the history goes back.
+    #[error("No history entry #{0}")]
+    NoHistoryEntry(usize),
+    /// An '@recent:' pattern was used, but frecency is disabled.
+    #[error("Cannot use '@recent:' patterns when frecency is disabled")]
+    FrecencyDisabled,
+    /// zoxide couldn't be run, or found nothing for a 'z:' pattern's query.
+    #[error("{0}")]
+    Zoxide(String),
+    /// A file has no parent directory to transform it to.
+    #[error("Could not get parent of file: \"{0}\"")]
+    NoParent(String),
+    /// A symlink being resolved points to something that doesn't exist.
+    #[error("Symlink \"{0}\" points to a file that doesn't exist")]
+    BrokenSymlink(String),
+    /// An entry point's search backend couldn't be run, or failed.
+    #[error("Search backend `{0}` failed: {1}")]
+    Backend(String, String),
+    /// A config file couldn't be read or parsed.
+    #[error("{0}")]
+    InvalidConfig(String),
+    /// Walking the directories under an entry point failed.
+    #[error(transparent)]
+    Walk(#[from] walkdir::Error),
+    /// Reading the filesystem, or the history or frecency files, failed.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+fn suggestion_list(suggestions: &[String]) -> String {
+    if suggestions.is_empty() {
+        return String::new();
+    }
+    let suggestions: Vec<String> = suggestions
+        .iter()
+        .map(|suggestion| format!("\"{suggestion}\""))
+        .collect();
+    format!("\n\tDid you mean {}?", suggestions.join(" or "))
+}
+
+// Only the first of what a timed out search found are listed, as there may be a lot of them
+fn found_so_far(paths: &[String]) -> String {
+    const SHOWN: usize = 10;
+    if paths.is_empty() {
+        return String::new();
+    }
+    let mut list = format!(
+        ". Found so far:{}",
+        candidate_list(&paths[..paths.len().min(SHOWN)])
+    );
+    if paths.len() > SHOWN {
+        list.push_str(&format!("\n\t...and {} more", paths.len() - SHOWN));
+    }
+    list
+}
+
+fn candidate_list(candidates: &[String]) -> String {
+    candidates
+        .iter()
+        .enumerate()
+        .map(|(i, candidate)| format!("\n\t{}. {}", i + 1, candidate))
+        .collect()
+}
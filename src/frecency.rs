@@ -0,0 +1,190 @@
+//! A persistent database of how frequently and recently paths have been selected, used to rank
+//! menu candidates, zoxide-style.
+//!
+//! Each line of the database holds a path's rank, the time it was last selected (in seconds since
+//! the Unix epoch), and the canonicalized path, separated by tabs.
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::errors::LaxResult as Result;
+
+// Once the ranks add up to more than this, they're all scaled down so old entries fade away
+const MAX_TOTAL_RANK: f64 = 10000.0;
+
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+const WEEK: u64 = 7 * DAY;
+
+/// Return the default frecency database location: `$XDG_DATA_HOME/lax/frecency` or
+/// `~/.local/share/lax/frecency`.
+#[cfg(feature = "config")]
+pub fn default_frecency_path() -> Option<std::path::PathBuf> {
+    Some(crate::history::data_dir()?.join("frecency"))
+}
+
+struct Entry {
+    rank: f64,
+    last_access: u64,
+}
+
+impl Entry {
+    fn score(&self, now: u64) -> f64 {
+        let age = now.saturating_sub(self.last_access);
+        let weight = if age < HOUR {
+            4.0
+        } else if age < DAY {
+            2.0
+        } else if age < WEEK {
+            0.5
+        } else {
+            0.25
+        };
+        self.rank * weight
+    }
+}
+
+/// Sort paths so the most frecent ones come first. Paths that have never been selected keep
+/// their relative order.
+pub fn rank<T: AsRef<Path> + Clone>(path: &Path, paths: &mut [T]) -> Result<()> {
+    let entries = read(path)?;
+    if entries.is_empty() {
+        return Ok(());
+    }
+
+    let now = now();
+    let score = |candidate: &T| {
+        canonicalize(candidate)
+            .and_then(|candidate| entries.get(&candidate))
+            .map_or(0.0, |entry| entry.score(now))
+    };
+    let mut scored: Vec<(f64, T)> = paths.iter().map(|p| (score(p), p.clone())).collect();
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+    for (path, (_, scored)) in paths.iter_mut().zip(scored) {
+        *path = scored;
+    }
+    Ok(())
+}
+
+/// Get every path in the database, most frecent first.
+pub fn recent(path: &Path) -> Result<Vec<String>> {
+    let now = now();
+    let mut entries: Vec<(String, Entry)> = read(path)?.into_iter().collect();
+    entries.sort_by(|a, b| b.1.score(now).total_cmp(&a.1.score(now)));
+    Ok(entries.into_iter().map(|(path, _)| path).collect())
+}
+
+/// Record that paths have been selected.
+pub fn record(path: &Path, paths: &[String]) -> Result<()> {
+    let mut entries = read(path)?;
+    let now = now();
+    for selected in paths {
+        let Some(selected) = canonicalize(selected) else {
+            continue;
+        };
+        let entry = entries.entry(selected).or_insert(Entry {
+            rank: 0.0,
+            last_access: now,
+        });
+        entry.rank += 1.0;
+        entry.last_access = now;
+    }
+
+    let total: f64 = entries.values().map(|entry| entry.rank).sum();
+    if total > MAX_TOTAL_RANK {
+        entries.retain(|_, entry| {
+            entry.rank *= 0.9;
+            entry.rank >= 1.0
+        });
+    }
+
+    let mut contents = String::new();
+    for (path, entry) in &entries {
+        // Tabs and newlines would corrupt the database, and are rare enough to not bother with
+        if path.contains(['\t', '\n']) {
+            continue;
+        }
+        contents.push_str(&format!(
+            "{}\t{}\t{}\n",
+            entry.rank, entry.last_access, path
+        ));
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, contents)?;
+    Ok(())
+}
+
+fn read(path: &Path) -> io::Result<HashMap<String, Entry>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(err) => return Err(err),
+    };
+
+    let mut entries = HashMap::new();
+    for line in contents.lines() {
+        let mut fields = line.splitn(3, '\t');
+        let (Some(rank), Some(last_access), Some(path)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        let (Ok(rank), Ok(last_access)) = (rank.parse(), last_access.parse()) else {
+            continue;
+        };
+        entries.insert(path.into(), Entry { rank, last_access });
+    }
+    Ok(entries)
+}
+
+fn canonicalize(path: impl AsRef<Path>) -> Option<String> {
+    Some(fs::canonicalize(path).ok()?.to_string_lossy().into_owned())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn rank_selected_paths_first() {
+        let db = env::temp_dir().join(format!("lax_test_frecency_{}", std::process::id()));
+        let _ = fs::remove_file(&db);
+
+        let mut paths = vec![
+            "./tests/foobar/foo".to_string(),
+            "./tests/foobar/fox".to_string(),
+            "./src/".to_string(),
+        ];
+        rank(&db, &mut paths).unwrap();
+        assert_eq!(paths[0], "./tests/foobar/foo");
+
+        record(&db, &["./src".into()]).unwrap();
+        record(&db, &["./src".into(), "./tests/foobar/fox".into()]).unwrap();
+        rank(&db, &mut paths).unwrap();
+        assert_eq!(
+            paths,
+            vec!["./src/", "./tests/foobar/fox", "./tests/foobar/foo"]
+        );
+
+        let recent = recent(&db).unwrap();
+        assert_eq!(recent.len(), 2);
+        assert!(recent[0].ends_with("src"));
+        assert!(recent[1].ends_with("fox"));
+
+        fs::remove_file(&db).unwrap();
+    }
+}
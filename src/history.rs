@@ -0,0 +1,121 @@
+//! A persistent record of the paths each invocation expanded to, so they can be reused with `@!`.
+//!
+//! Each line of the history file holds one invocation's paths, separated by tabs, with the most
+//! recent invocation last.
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use crate::errors::{LaxError, LaxResult as Result};
+
+// Oldest entries are dropped once the history grows past this
+const MAX_ENTRIES: usize = 1000;
+
+/// Return the default history file location: `$XDG_DATA_HOME/lax/history` or
+/// `~/.local/share/lax/history`.
+#[cfg(feature = "config")]
+pub fn default_history_path() -> Option<std::path::PathBuf> {
+    Some(data_dir()?.join("history"))
+}
+
+// Directory for lax's persistent data
+#[cfg(feature = "config")]
+pub(crate) fn data_dir() -> Option<std::path::PathBuf> {
+    let data_dir = match std::env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => dir.into(),
+        _ => dirs::home_dir()?.join(".local").join("share"),
+    };
+    Some(data_dir.join("lax"))
+}
+
+/// Get the paths expanded by the `n`th most recent invocation, starting from 1.
+pub fn get(path: &Path, n: usize) -> Result<Vec<String>> {
+    let entries = read(path)?;
+    n.checked_sub(1)
+        .and_then(|index| entries.iter().rev().nth(index))
+        .cloned()
+        .ok_or(LaxError::NoHistoryEntry(n))
+}
+
+/// Record the paths expanded by an invocation.
+pub fn record(path: &Path, paths: &[String]) -> Result<()> {
+    let mut entries = read(path)?;
+    entries.push(paths.to_vec());
+    let skip = entries.len().saturating_sub(MAX_ENTRIES);
+
+    let mut contents = String::new();
+    for entry in &entries[skip..] {
+        let entry: Vec<String> = entry.iter().map(|path| escape(path)).collect();
+        contents.push_str(&entry.join("\t"));
+        contents.push('\n');
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, contents)?;
+    Ok(())
+}
+
+fn read(path: &Path) -> io::Result<Vec<Vec<String>>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    Ok(contents
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| line.split('\t').map(unescape).collect())
+        .collect())
+}
+
+// Tabs and newlines are the history file's delimiters, so they need escaping
+pub(crate) fn escape(path: &str) -> String {
+    path.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+pub(crate) fn unescape(path: &str) -> String {
+    let mut result = String::new();
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => result.push('\t'),
+            Some('n') => result.push('\n'),
+            Some(c) => result.push(c),
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn record_and_get() {
+        let path = env::temp_dir().join(format!("lax_test_history_{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        assert!(get(&path, 1).is_err());
+        record(&path, &["a".into(), "tab\there".into()]).unwrap();
+        record(&path, &["back\\slash\nnewline".into()]).unwrap();
+
+        assert_eq!(get(&path, 1).unwrap(), vec!["back\\slash\nnewline"]);
+        assert_eq!(get(&path, 2).unwrap(), vec!["a", "tab\there"]);
+        assert!(get(&path, 0).is_err());
+        assert!(get(&path, 3).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
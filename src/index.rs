@@ -0,0 +1,336 @@
+//! An on-disk index of the entries under an entry point, so huge trees don't have to be walked
+//! again for every expansion.
+//!
+//! An index records the modification time of each directory it holds, which changes whenever an
+//! entry is added to, removed from, or renamed in that directory. If any of them have changed,
+//! the index is stale, and the tree is walked again. Like git's index, directories modified
+//! shortly before the index was built can't be trusted, as a later change may have left them
+//! with the same time. Metadata of the matches themselves, like their size, is always read fresh.
+//!
+//! The first line of an index file holds the format version, when its walk started, the entry
+//! point, and the settings it was walked with. Each line after that holds one entry: `d` or `f`,
+//! its depth, its modification time (for directories), and its path relative to the entry point,
+//! separated by tabs.
+use std::{
+    collections::hash_map::DefaultHasher,
+    fs,
+    hash::{Hash, Hasher},
+    io,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use log::{debug, info};
+use walkdir::{DirEntry, WalkDir};
+
+use crate::history::{escape, unescape};
+
+// Bump this when the format changes, so old indexes are rebuilt rather than misread
+const VERSION: u32 = 2;
+
+// Directories modified this shortly before their index was built might be modified again without
+// their time changing, on filesystems with coarse timestamps
+const RACY: Duration = Duration::from_secs(2);
+
+// When this process first used an index. With `refresh`, indexes built before then are rebuilt
+static STARTED: OnceLock<SystemTime> = OnceLock::new();
+
+// An entry under the entry point
+pub(crate) struct Entry {
+    // Relative to the entry point, which is itself ""
+    pub(crate) relative: String,
+    pub(crate) is_dir: bool,
+    pub(crate) depth: usize,
+    // Only recorded for directories
+    modified: Option<SystemTime>,
+}
+
+impl Entry {
+    pub(crate) fn new(relative: String, is_dir: bool, depth: usize) -> Self {
+        Entry {
+            relative,
+            is_dir,
+            depth,
+            modified: None,
+        }
+    }
+
+    // Write an entry as a line of an index, without the newline
+    pub(crate) fn to_line(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}",
+            if self.is_dir { "d" } else { "f" },
+            self.depth,
+            format_time(self.modified),
+            escape(&self.relative)
+        )
+    }
+
+    // Read an entry from a line of an index
+    pub(crate) fn parse(line: &str) -> Option<Self> {
+        let fields: Vec<&str> = line.splitn(4, '\t').collect();
+        let [kind, depth, time, relative] = fields[..] else {
+            return None;
+        };
+        Some(Entry {
+            relative: unescape(relative),
+            is_dir: kind == "d",
+            depth: depth.parse().ok()?,
+            modified: parse_time(time),
+        })
+    }
+}
+
+/// Get the entries under an entry point from its index in `cache_dir`, in path order. If there's
+/// no index yet, or it's stale, the entry point is walked in full, descending only into what
+/// `filter` allows, and the index is saved for next time.
+///
+/// `settings` describes whatever else decides which entries are walked, so differently walked
+/// trees get their own indexes. With `refresh`, indexes built before this process started are
+/// walked again regardless. A walk still going at `deadline` stops there, and what it found so far
+/// is returned, but not saved.
+pub(crate) fn entries(
+    cache_dir: &Path,
+    entry_point: &Path,
+    settings: &str,
+    refresh: bool,
+    deadline: Option<Instant>,
+    filter: impl FnMut(&DirEntry) -> bool,
+) -> Vec<Entry> {
+    let started = *STARTED.get_or_init(SystemTime::now);
+    let path = index_path(cache_dir, entry_point, settings);
+    match load(&path, entry_point, settings, refresh.then_some(started)) {
+        Ok(Some(entries)) => {
+            info!("Searching the index of {}", entry_point.display());
+            return entries;
+        }
+        Ok(None) => {}
+        Err(err) => debug!("Ignoring unreadable index {}: {err}", path.display()),
+    }
+
+    info!("Indexing {}", entry_point.display());
+    let built = SystemTime::now();
+    let entries = build(entry_point, deadline, filter);
+    if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+        info!(
+            "Not saving the index of {}, which is unfinished",
+            entry_point.display()
+        );
+        return entries;
+    }
+    if let Err(err) = save(&path, built, entry_point, settings, &entries) {
+        debug!("Couldn't save index {}: {err}", path.display());
+    }
+    entries
+}
+
+// Where the index of an entry point, walked with some settings, is kept
+fn index_path(cache_dir: &Path, entry_point: &Path, settings: &str) -> PathBuf {
+    let mut hasher = DefaultHasher::new();
+    (entry_point, settings).hash(&mut hasher);
+    cache_dir
+        .join("index")
+        .join(format!("{:016x}", hasher.finish()))
+}
+
+// Walk an entry point in full, in path order, unless `deadline` passes first
+pub(crate) fn build(
+    entry_point: &Path,
+    deadline: Option<Instant>,
+    filter: impl FnMut(&DirEntry) -> bool,
+) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let walk = WalkDir::new(entry_point).sort_by_file_name();
+    for entry in walk.into_iter().filter_entry(filter) {
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            break;
+        }
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                debug!("Skipping unreadable entry: {err}");
+                continue;
+            }
+        };
+        // Paths that aren't UTF-8 can't be matched anyway
+        let Some(relative) = entry
+            .path()
+            .strip_prefix(entry_point)
+            .ok()
+            .and_then(Path::to_str)
+        else {
+            continue;
+        };
+        let is_dir = entry.file_type().is_dir();
+        entries.push(Entry {
+            relative: relative.into(),
+            is_dir,
+            depth: entry.depth(),
+            modified: if is_dir { modified(entry.path()) } else { None },
+        });
+    }
+    entries
+}
+
+// Read an index, unless it's missing, stale, for something else, or was built before
+// `not_before`
+fn load(
+    path: &Path,
+    entry_point: &Path,
+    settings: &str,
+    not_before: Option<SystemTime>,
+) -> io::Result<Option<Vec<Entry>>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed index");
+    let mut lines = contents.lines();
+
+    let header: Vec<&str> = lines.next().ok_or_else(invalid)?.split('\t').collect();
+    let [version, built, indexed, indexed_settings] = header[..] else {
+        return Err(invalid());
+    };
+    if version != VERSION.to_string()
+        || unescape(indexed) != entry_point.to_string_lossy()
+        || unescape(indexed_settings) != settings
+    {
+        debug!("Rebuilding index {}, which is outdated", path.display());
+        return Ok(None);
+    }
+    let built = parse_time(built).ok_or_else(invalid)?;
+    if not_before.is_some_and(|not_before| built < not_before) {
+        debug!("Rebuilding index of {}, as asked", entry_point.display());
+        return Ok(None);
+    }
+
+    let entries = lines
+        .map(Entry::parse)
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(invalid)?;
+    Ok(up_to_date(entry_point, &entries, built).then_some(entries))
+}
+
+// Check that no directory in an index, whose walk started at `built`, has changed since
+pub(crate) fn up_to_date(entry_point: &Path, entries: &[Entry], built: SystemTime) -> bool {
+    // A directory whose modification time can't be known can't be checked either
+    let trusted = |modified: SystemTime| modified + RACY < built;
+    for entry in entries.iter().filter(|entry| entry.is_dir) {
+        let path = entry_point.join(&entry.relative);
+        if !entry.modified.is_some_and(trusted) || modified(&path) != entry.modified {
+            debug!(
+                "Rebuilding index of {}, as {} changed",
+                entry_point.display(),
+                path.display()
+            );
+            return false;
+        }
+    }
+    true
+}
+
+fn save(
+    path: &Path,
+    built: SystemTime,
+    entry_point: &Path,
+    settings: &str,
+    entries: &[Entry],
+) -> io::Result<()> {
+    let mut contents = format!(
+        "{VERSION}\t{}\t{}\t{}\n",
+        format_time(Some(built)),
+        escape(&entry_point.to_string_lossy()),
+        escape(settings)
+    );
+    for entry in entries {
+        contents.push_str(&entry.to_line());
+        contents.push('\n');
+    }
+
+    // Write the index elsewhere first, so another lax never reads half of it
+    let Some(parent) = path.parent() else {
+        return Ok(());
+    };
+    fs::create_dir_all(parent)?;
+    let temporary = path.with_extension(format!("{}.tmp", std::process::id()));
+    fs::write(&temporary, contents)?;
+    fs::rename(&temporary, path)
+}
+
+// A path's modification time, without following symlinks, as the walk doesn't
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::symlink_metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+// Times are written in nanoseconds since the Unix epoch, or '-' if unknown
+fn format_time(time: Option<SystemTime>) -> String {
+    match time.and_then(|time| time.duration_since(UNIX_EPOCH).ok()) {
+        Some(duration) => duration.as_nanos().to_string(),
+        None => "-".into(),
+    }
+}
+
+fn parse_time(time: &str) -> Option<SystemTime> {
+    let nanos: u128 = time.parse().ok()?;
+    let duration = Duration::new(
+        (nanos / 1_000_000_000).try_into().ok()?,
+        (nanos % 1_000_000_000) as u32,
+    );
+    UNIX_EPOCH.checked_add(duration)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn invalidation() {
+        let cache_dir =
+            env::temp_dir().join(format!("lax_test_index_cache_{}", std::process::id()));
+        let tree = env::temp_dir().join(format!("lax_test_index_tree_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&cache_dir);
+        let _ = fs::remove_dir_all(&tree);
+        fs::create_dir_all(tree.join("sub")).unwrap();
+        fs::write(tree.join("sub").join("a"), "").unwrap();
+        std::thread::sleep(RACY);
+
+        let relative = |entries: Vec<Entry>| {
+            let mut relative: Vec<String> = entries
+                .into_iter()
+                .map(|entry| entry.relative.replace(std::path::MAIN_SEPARATOR, "/"))
+                .collect();
+            relative.sort();
+            relative
+        };
+        let entries = || entries(&cache_dir, &tree, "", false, None, |_| true);
+        assert_eq!(relative(entries()), vec!["", "sub", "sub/a"]);
+        let path = index_path(&cache_dir, &tree, "");
+        assert!(load(&path, &tree, "", None).unwrap().is_some());
+        assert!(load(&path, &tree, "other", None).unwrap().is_none());
+
+        assert!(load(&path, &tree, "", Some(SystemTime::now()))
+            .unwrap()
+            .is_none());
+
+        // Adding an entry changes its directory's modification time, which is too recent to
+        // trust for a while
+        fs::write(tree.join("sub").join("b"), "").unwrap();
+        assert_eq!(relative(entries()), vec!["", "sub", "sub/a", "sub/b"]);
+        assert!(load(&path, &tree, "", None).unwrap().is_none());
+
+        // A walk cut short by its deadline isn't saved, as it's missing entries
+        let _ = fs::remove_dir_all(&cache_dir);
+        let unfinished =
+            super::entries(&cache_dir, &tree, "", false, Some(Instant::now()), |_| true);
+        assert!(unfinished.is_empty());
+        assert!(load(&path, &tree, "", None).unwrap().is_none());
+
+        let _ = fs::remove_dir_all(&cache_dir);
+        fs::remove_dir_all(&tree).unwrap();
+    }
+}
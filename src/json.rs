@@ -0,0 +1,54 @@
+//! Just enough JSON to produce machine-readable output.
+use std::fmt::Write;
+
+/// Quote and escape a string as a JSON string literal.
+pub fn string(value: &str) -> String {
+    let mut result = String::with_capacity(value.len() + 2);
+    result.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(result, "\\u{:04x}", c as u32);
+            }
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+/// Format a list of already-serialized JSON values as an array.
+pub fn array<I: IntoIterator<Item = String>>(values: I) -> String {
+    format!("[{}]", values.into_iter().collect::<Vec<_>>().join(","))
+}
+
+/// Format a list of keys and already-serialized JSON values as an object.
+pub fn object<'a, I: IntoIterator<Item = (&'a str, String)>>(fields: I) -> String {
+    let fields: Vec<String> = fields
+        .into_iter()
+        .map(|(key, value)| format!("{}:{}", string(key), value))
+        .collect();
+    format!("{{{}}}", fields.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escaping() {
+        assert_eq!(string("plain"), "\"plain\"");
+        assert_eq!(string("a\"b\\c\nd\u{1}"), "\"a\\\"b\\\\c\\nd\\u0001\"");
+        assert_eq!(array([string("a"), "1".into()]), "[\"a\",1]");
+        assert_eq!(array(Vec::new()), "[]");
+        assert_eq!(
+            object([("a", string("b")), ("c", "null".into())]),
+            "{\"a\":\"b\",\"c\":null}"
+        );
+    }
+}
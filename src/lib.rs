@@ -1,442 +1,1892 @@
 //! Transform command line arguments by expanding '@' patterns.
 #![warn(missing_docs)]
 use std::{
-    env, fs,
+    borrow::Cow,
+    cmp::Ordering,
+    collections::HashMap,
+    env, fmt, fs, mem,
+    ops::Range,
     path::{Path, PathBuf},
+    process::Command,
+    time::{Duration, Instant, SystemTime},
 };
 
-use anyhow::{anyhow, bail, Result};
 use globset::GlobBuilder;
-use regex::Regex;
-use walkdir::{DirEntry, WalkDir};
+use log::info;
+
+use errors::{LaxError, LaxResult as Result};
+
+mod backend;
+pub mod checklist;
+#[cfg(feature = "config")]
+pub mod config_file;
+#[cfg(unix)]
+pub mod daemon;
+pub mod errors;
+pub mod frecency;
+pub mod history;
+mod index;
+mod parser;
+pub mod pattern;
+pub mod quote;
+mod selector;
+pub mod transform;
+mod walk;
+
+use parser::{
+    expand_braces, find_unescaped, named_components, normalize_separators, point_at, shift,
+    split_alternatives, split_exclusion, split_modifiers, Spanned,
+};
+use pattern::EntryKind;
+pub use selector::{Selector, SelectorGroup, SelectorRegex};
+use transform::{FilenameModifier, FilesToDirs, Pipeline, Transform};
+use walk::{owner_id, take_last, take_matches, timed_out, Walk, WalkFilter};
 
 /// Struct used to expand '@' patterns.
 pub struct Expander {
     /// Configuration object.
     pub config: Config,
-    /// A callback function that provides the user with a TUI/CLI menu when a glob pattern matches
-    /// more than one result, and no selector is given in the relevant '@' pattern.
+    /// The TUI/CLI menu the user chooses from when a glob pattern matches more than one result,
+    /// and no selector is given in the relevant '@' pattern.
+    pub selector_menu: Box<dyn SelectionMenu>,
+}
+
+impl Expander {
+    /// Start building an expander, with the default config and no menu.
+    pub fn builder() -> ExpanderBuilder {
+        ExpanderBuilder::default()
+    }
+}
+
+/// Builds an [`Expander`] with chained setters.
+#[derive(Default)]
+pub struct ExpanderBuilder {
+    config: Config,
+    selector_menu: Option<Box<dyn SelectionMenu>>,
+}
+
+impl ExpanderBuilder {
+    /// Use this config, rather than the default.
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Ask the user to choose with this menu when a pattern is ambiguous.
+    pub fn selector_menu(mut self, selector_menu: impl SelectionMenu + 'static) -> Self {
+        self.selector_menu = Some(Box::new(selector_menu));
+        self
+    }
+
+    /// Finish building the expander. Without a menu, ambiguous patterns fail with
+    /// [`LaxError::AmbiguousPattern`], as if [`Config::use_menu`] were off.
+    pub fn build(self) -> Expander {
+        let mut config = self.config;
+        let selector_menu = self.selector_menu.unwrap_or_else(|| {
+            config.use_menu = false;
+            Box::new(|_: &[Candidate], _, _| Ok(MenuOutcome::Cancelled))
+        });
+        Expander {
+            config,
+            selector_menu,
+        }
+    }
+}
+
+/// A menu that lets the user choose between the matches of an ambiguous '@' pattern.
+pub trait SelectionMenu {
+    /// Ask the user to choose from `candidates`, which are in the order they should be shown.
+    /// Selectors refer to them by their [`Candidate::id`], rather than by position.
     ///
-    /// This should return a selector string.
+    /// `attempt` counts the times the menu has been asked about this list. It's 0 when the list
+    /// is new to the user, which is on the first call for a particular '@' pattern and after
+    /// re-sorting or loading more, and goes up if they answer with a selector that doesn't select
+    /// anything. This can be used to show the list of matches only when there's something new
+    /// to show.
     ///
-    /// The first parameter is a list of potential matches.
-    /// The second parameter will be true if this is the first time this callback is called for a
-    /// particular '@' pattern, and false otherwise. This can be used to provide the user with the
-    /// list of matches on first call, but not on the following calls (eg the user enters an
-    /// invalid selector)
-    pub selector_menu: fn(paths: &[String], first_call: bool) -> String,
-}
-
-#[derive(PartialEq, Debug)]
-enum Selector {
-    All,
-    FromFront(usize),
-    FromBack(usize),
-    Regex(String),
-}
-#[derive(PartialEq, Debug)]
-struct SelectorGroup {
-    selectors: Vec<Selector>,
-}
-
-impl SelectorGroup {
-    // Select all paths that match the selector group.
-    fn select(&self, paths: &[String]) -> Result<Vec<String>> {
-        let mut selected_paths = Vec::<String>::new();
-        for selector in &self.selectors {
-            if paths.is_empty() {
-                return Err(anyhow!("No paths to select!"));
-            }
-            match selector {
-                Selector::All => {
-                    selected_paths.extend(paths.to_owned());
-                }
-                Selector::FromFront(offset) => {
-                    if *offset >= paths.len() {
-                        return Err(anyhow!("Selector index out of range: {}", offset + 1));
-                    }
-                    selected_paths.push(paths[*offset].clone());
-                }
-                Selector::FromBack(offset) => {
-                    if *offset >= paths.len() {
-                        return Err(anyhow!("Selector index out of range: -{}", offset + 1));
-                    }
-                    selected_paths.push(paths[paths.len() - 1 - offset].clone());
-                }
-                Selector::Regex(regex) => {
-                    let regex = Regex::new(regex)?;
-                    selected_paths.extend(paths.iter().filter(|v| regex.is_match(v)).cloned());
-                }
+    /// `more` is true if the list was cut short by [`Config::max_results`], and more matches can
+    /// be loaded with `m`.
+    fn select(&mut self, candidates: &[Candidate], attempt: u32, more: bool)
+        -> Result<MenuOutcome>;
+
+    /// Ask whether to go on with a pattern that matched more than `limit` paths, which is
+    /// [`Config::max_matches`]. If not, expansion fails with [`LaxError::TooManyMatches`]. The
+    /// default doesn't go on.
+    fn confirm_many(&mut self, _pattern: &str, _limit: usize) -> Result<bool> {
+        Ok(false)
+    }
+}
+
+/// Closures can be used as menus, including ones that capture state, like a list of scripted
+/// answers.
+impl<F> SelectionMenu for F
+where
+    F: FnMut(&[Candidate], u32, bool) -> Result<MenuOutcome>,
+{
+    fn select(
+        &mut self,
+        candidates: &[Candidate],
+        attempt: u32,
+        more: bool,
+    ) -> Result<MenuOutcome> {
+        self(candidates, attempt, more)
+    }
+}
+
+/// What the user chose in a [`SelectionMenu`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MenuOutcome {
+    /// A selector string, or `s KEY` to re-sort the matches by `name`, `mtime` (newest first),
+    /// `size` (largest first), or `depth` (shallowest first), `m` to load more matches, or
+    /// `e GLOB` to search again with a different glob pattern, from the same entry point.
+    Selectors(String),
+    /// These paths, chosen directly, as from a GUI list.
+    Paths(Vec<String>),
+    /// The user gave up. Expansion fails with [`LaxError::Cancelled`].
+    Cancelled,
+}
+
+/// A potential match offered by the menu.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    /// The number selectors use for this path. It stays the same when the menu is re-sorted or
+    /// more matches are loaded.
+    pub id: usize,
+    /// The match itself.
+    pub matched: Match,
+    /// Where in the path is the part the pattern named, after any `**`, like the file name of a
+    /// match of `@src/**/*.rs`, so menus can highlight it. This is a byte range of the path as
+    /// shown by [`Path::display`].
+    pub matched_part: Range<usize>,
+}
+
+/// A path an '@' pattern matched, along with the metadata found while matching it, so menus and
+/// selectors can use it without fetching it again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    /// The path itself. Directories end with a separator, unless [`Config::append_dir_slash`] is
+    /// off.
+    pub path: PathBuf,
+    /// Whether the path is a directory.
+    pub is_dir: bool,
+    /// The size of the path, in bytes.
+    pub size: u64,
+    /// When the path was last modified, if the platform or filesystem knows.
+    pub modified: Option<SystemTime>,
+    /// How far below its entry point the path was found, where the entry point itself is 0.
+    /// Paths that weren't found by walking the filesystem, like executables in $PATH, are 0.
+    pub depth: usize,
+}
+
+impl Match {
+    // A match whose metadata has already been fetched
+    fn new(path: String, metadata: &fs::Metadata, depth: usize) -> Self {
+        Match {
+            path: path.into(),
+            is_dir: metadata.is_dir(),
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+            depth,
+        }
+    }
+
+    // A match that's only known as a path, without reading its metadata
+    fn without_metadata(path: String, is_dir: bool, depth: usize) -> Self {
+        Match {
+            path: path.into(),
+            is_dir,
+            size: 0,
+            modified: None,
+            depth,
+        }
+    }
+
+    // Fetch a path's metadata, for matches that don't come from a walk. A path that can't be
+    // read, like a deleted history entry, still matches, just without metadata
+    fn stat(path: String) -> Self {
+        match fs::metadata(&path) {
+            Ok(metadata) => Match::new(path, &metadata, 0),
+            Err(_) => {
+                let is_dir = path.ends_with(std::path::MAIN_SEPARATOR);
+                Match::without_metadata(path, is_dir, 0)
             }
         }
+    }
+
+    // The path as a string, as selectors and the expanded arguments use it
+    fn to_path_string(&self) -> String {
+        self.path.to_string_lossy().into_owned()
+    }
+}
 
-        Ok(selected_paths)
+impl AsRef<Path> for Match {
+    fn as_ref(&self) -> &Path {
+        &self.path
     }
+}
 
-    // Return highest index we will select, with no knowledge of how long the list of paths will
-    // be. None implies infinity
-    fn highest_index(&self) -> Option<usize> {
-        let mut highest_index = 0;
-        for selector in &self.selectors {
-            match selector {
-                Selector::FromFront(offset) => {
-                    highest_index = std::cmp::max(*offset, highest_index);
-                }
-                Selector::FromBack(_) | Selector::All | Selector::Regex(_) => {
-                    return None;
+/// A record of how a single '@' pattern was expanded, for explaining or debugging expansions.
+#[derive(Debug, Clone)]
+pub struct PatternReport {
+    /// The '@' pattern, as given.
+    pub pattern: String,
+    /// The alternative of the pattern that was expanded, parsed into its parts, with any glob
+    /// the menu searched again with.
+    pub expanded_as: Option<pattern::Pattern>,
+    /// The paths selected from the pattern's matches, before post-transforms.
+    pub selected: Vec<String>,
+    /// The paths the pattern expanded to, after selection and post-transforms.
+    pub paths: Vec<String>,
+    /// Time spent in each phase of the expansion.
+    pub timings: Timings,
+    /// How much searching the expansion took.
+    pub stats: SearchStats,
+}
+
+/// The result of [`Expander::expand_arguments_lossy`].
+#[derive(Debug, Default)]
+pub struct LossyExpansion {
+    /// The transformed arguments, with any '@' pattern that failed left as it was given.
+    pub args: Vec<String>,
+    /// What each argument expanded to, or why it couldn't be expanded, in the same order as the
+    /// arguments. Arguments that aren't '@' patterns expand to themselves.
+    pub results: Vec<Result<Vec<String>>>,
+}
+
+/// Time spent in each phase of expanding an '@' pattern.
+#[derive(Debug, Clone, Default)]
+pub struct Timings {
+    /// Parsing the pattern and its selectors.
+    pub parse: Duration,
+    /// Compiling the glob pattern, and any exclusion.
+    pub glob_compile: Duration,
+    /// Finding the repository root, bookmark, or other directories the search starts from.
+    pub root_detection: Duration,
+    /// Walking the filesystem for matches.
+    pub walk: Duration,
+    /// Narrowing matches down with selectors, including time spent in the menu.
+    pub selection: Duration,
+    /// Applying post-selector transformations.
+    pub post_transform: Duration,
+}
+
+/// How much of the filesystem was searched to expand an '@' pattern. Patterns that were already
+/// searched for an earlier argument don't search again, and count nothing.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SearchStats {
+    /// Directories walked, including the entry points.
+    pub directories: usize,
+    /// Files (and other entries that aren't directories) walked.
+    pub files: usize,
+    /// Paths that matched the glob pattern.
+    pub matched: usize,
+}
+
+/// Whether an '@' pattern would resolve without asking the user, as found by
+/// [`Expander::probe`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Probe {
+    /// The pattern resolves to these paths without a menu.
+    Unambiguous(Vec<String>),
+    /// The pattern matches these paths, and the user would be asked to choose between them.
+    Ambiguous(Vec<String>),
+    /// The pattern (or its selectors) matches nothing.
+    Unmatched,
+}
+
+// What the menu's matches can be re-sorted by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SortKey {
+    Path,
+    Name,
+    Natural,
+    Modified,
+    Size,
+    Depth,
+}
+
+impl SortKey {
+    // Sort matches themselves, rather than the order they're shown in
+    fn sort_matches(self, matches: &mut Vec<Match>) {
+        let mut order: Vec<usize> = (0..matches.len()).collect();
+        self.sort(matches, &mut order);
+        let mut unsorted: Vec<Option<Match>> = mem::take(matches).into_iter().map(Some).collect();
+        matches.extend(order.into_iter().filter_map(|index| unsorted[index].take()));
+    }
+
+    fn parse(key: &str) -> Option<Self> {
+        Some(match key {
+            "name" => SortKey::Name,
+            "natural" => SortKey::Natural,
+            "mtime" => SortKey::Modified,
+            "size" => SortKey::Size,
+            "depth" => SortKey::Depth,
+            _ => return None,
+        })
+    }
+
+    // Sort the order matches are shown in, keeping ties in their current order. This uses the
+    // metadata found while matching, so re-sorting never has to hit a slow (e.g. network)
+    // filesystem again
+    fn sort(self, matches: &[Match], order: &mut [usize]) {
+        match self {
+            SortKey::Path => order.sort_by(|&a, &b| matches[a].path.cmp(&matches[b].path)),
+            SortKey::Name => order.sort_by_cached_key(|&index| {
+                let path = &matches[index].path;
+                (path.file_name().map(|name| name.to_owned()), path.clone())
+            }),
+            SortKey::Natural => {
+                order.sort_by(|&a, &b| natural_path_cmp(&matches[a].path, &matches[b].path))
+            }
+            // Newest first
+            SortKey::Modified => {
+                order.sort_by_key(|&index| std::cmp::Reverse(matches[index].modified))
+            }
+            // Largest first
+            SortKey::Size => order.sort_by_key(|&index| std::cmp::Reverse(matches[index].size)),
+            // Shallowest first
+            SortKey::Depth => {
+                order.sort_by_cached_key(|&index| matches[index].path.components().count())
+            }
+        }
+    }
+}
+
+// Compare paths component by component, like `Path`'s own ordering, but with runs of digits
+// compared as numbers, so "patch-2.diff" comes before "patch-10.diff"
+fn natural_path_cmp(a: &Path, b: &Path) -> Ordering {
+    let mut a = a.components();
+    let mut b = b.components();
+    loop {
+        match (a.next(), b.next()) {
+            (Some(a), Some(b)) => match natural_cmp(
+                &a.as_os_str().to_string_lossy(),
+                &b.as_os_str().to_string_lossy(),
+            ) {
+                Ordering::Equal => continue,
+                ordering => return ordering,
+            },
+            (a, b) => return a.is_some().cmp(&b.is_some()),
+        }
+    }
+}
+
+// Compare strings with runs of digits compared as numbers. Numbers that are equal but written
+// with different numbers of leading zeros, like "7" and "007", go shortest first
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    let mut zeros = Ordering::Equal;
+    loop {
+        let (Some(x), Some(y)) = (a.chars().next(), b.chars().next()) else {
+            return a.len().cmp(&b.len()).then(zeros);
+        };
+        if !x.is_ascii_digit() || !y.is_ascii_digit() {
+            match x.cmp(&y) {
+                Ordering::Equal => {
+                    a = &a[x.len_utf8()..];
+                    b = &b[y.len_utf8()..];
+                    continue;
                 }
+                ordering => return ordering,
             }
         }
-        Some(highest_index)
+
+        let split = |s: &str| s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (x, rest_a) = a.split_at(split(a));
+        let (y, rest_b) = b.split_at(split(b));
+        let (x_digits, y_digits) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+        // A longer number is bigger, and numbers of the same length compare like strings
+        match x_digits
+            .len()
+            .cmp(&y_digits.len())
+            .then_with(|| x_digits.cmp(y_digits))
+        {
+            Ordering::Equal => zeros = zeros.then(x.len().cmp(&y.len())),
+            ordering => return ordering,
+        }
+        (a, b) = (rest_a, rest_b);
+    }
+}
+
+// Which root to use when repositories are nested, like a git submodule inside a monorepo
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+enum RootSearch {
+    // The closest root going up, as in '@%'
+    Nearest,
+    // The furthest root going up, as in '@%%'
+    Outermost,
+}
+
+// Where the search for an '@' pattern starts from, before the entry point is applied.
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+enum Origin {
+    // The current directory (or the search path)
+    CurrentDirectory,
+    // The root of the repository (git, svn, etc.) we're in
+    RepositoryRoot(RootSearch),
+    // A named bookmark from the config
+    Bookmark(String),
+    // The paths expanded by the nth most recent invocation
+    History(usize),
+    // The directory zoxide picks for a query
+    Zoxide(String),
+    // The directories in $PATH, searched for executables
+    ExecutableSearchPath,
+    // Recently selected paths, from the frecency database
+    Recent,
+}
+
+impl Origin {
+    // Whether matches from here come in an order of their own, which sorting would lose, like
+    // the precedence of $PATH
+    fn is_ordered(&self) -> bool {
+        matches!(
+            self,
+            Origin::History(_) | Origin::ExecutableSearchPath | Origin::Recent
+        )
+    }
+}
+
+// Matches already fetched during a single call to `Expander::expand_arguments`, so that repeated
+// patterns only walk the filesystem once.
+#[derive(Default)]
+struct MatchCache {
+    entries: HashMap<Search, CachedMatches>,
+    // Final selections, and the alternatives they came from, keyed by the full '@' pattern, so
+    // the user is only prompted once
+    selections: HashMap<String, (Vec<String>, String)>,
+}
+
+// What a single search looks for: where it starts, under which entry point, what its glob
+// pattern is, and which kind of entries it matches, if the pattern says
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+struct Search {
+    origin: Origin,
+    entry_point: String,
+    glob: String,
+    only: Option<EntryKind>,
+}
+
+// What a pattern's selectors, or the menu, made of its matches
+enum Selection {
+    Paths(Vec<String>),
+    // Search again with this glob pattern instead, as asked from the menu
+    Refine(String),
+}
+
+// (matches, the rest of the walk, selector group, components named) for one alternative of a
+// pattern
+type AlternativeMatches = (Vec<Match>, Option<Walk>, Option<SelectorGroup>, usize);
+
+struct CachedMatches {
+    paths: Vec<Match>,
+    // False if the walk quit early because the selectors didn't need any more matches
+    complete: bool,
+}
+
+impl MatchCache {
+    // Look up previously fetched matches. Incomplete walks can only be reused if they contain
+    // every index the selector group might ask for.
+    fn get(&self, key: &Search, quit_after_index: Option<usize>) -> Option<Vec<Match>> {
+        let cached = self.entries.get(key)?;
+        let usable =
+            cached.complete || quit_after_index.is_some_and(|index| index < cached.paths.len());
+        usable.then(|| cached.paths.clone())
     }
 }
 
 impl Expander {
     /// Expand a entry point/glob pattern pair into all its potential matches.
+    ///
+    /// If `stop_after` is given, stop once that many matches have been found. When the matches
+    /// come from walking the filesystem, the rest of the walk is returned so it can be resumed.
     fn fetch_matches(
         &self,
-        from_repository_root: bool,
-        entry_point: &str,
-        mut pattern: &str,
-        paths: &mut Vec<String>,
-        selector_group: &Option<SelectorGroup>,
-    ) -> Result<()> {
+        search: &Search,
+        paths: &mut Vec<Match>,
+        stop_after: Option<usize>,
+        timings: &mut Timings,
+    ) -> Result<Option<Walk>> {
+        let origin = &search.origin;
+        let entry_point = search.entry_point.as_str();
+        let mut pattern = search.glob.as_str();
+        let start = Instant::now();
+        let exclusion;
+        (pattern, exclusion) = split_exclusion(pattern)?;
+        let excluded = |path: &Match| {
+            exclusion
+                .as_ref()
+                .is_some_and(|exclusion| exclusion.is_match(&path.path))
+        };
+        if *origin == Origin::ExecutableSearchPath {
+            Self::fetch_executables(pattern, paths)?;
+            paths.retain(|path| !excluded(path));
+            return Ok(None);
+        }
+        if *origin == Origin::Recent {
+            self.fetch_recent(entry_point, pattern, paths)?;
+            paths.retain(|path| !excluded(path));
+            return Ok(None);
+        }
         if pattern.is_empty() {
-            // This way we can `cd @%` to cd to the repository root
-            if from_repository_root {
-                paths.push(get_repository_root()?.to_string_lossy().into_owned());
-                return Ok(());
+            // This way we can `cd @%` to cd to the repository root, or `cd @:name` to cd to a
+            // bookmark
+            if let Origin::History(n) = origin {
+                let history_file = self
+                    .config
+                    .history_file
+                    .as_ref()
+                    .ok_or(LaxError::HistoryDisabled)?;
+                paths.extend(history::get(history_file, *n)?.into_iter().map(Match::stat));
+                return Ok(None);
+            }
+            if let Some(root) = self.origin_directory(origin)? {
+                let path = if entry_point == "." {
+                    root
+                } else {
+                    root.join(entry_point)
+                };
+                paths.push(Match::stat(path.to_string_lossy().into_owned()));
+                timings.root_detection += start.elapsed();
+                return Ok(None);
             }
 
-            return Err(anyhow!(
+            return Err(LaxError::InvalidPattern(
                 "No glob pattern specified. \
                                Please see Lax's README for syntax"
+                    .into(),
             ));
         }
 
+        // A pattern's own `f:` or `d:` overrides the config
+        let (match_with_dirs, mut match_with_files) = match search.only {
+            None => (self.config.match_with_dirs, self.config.match_with_files),
+            Some(EntryKind::Files) => (false, true),
+            Some(EntryKind::Directories) => (true, false),
+        };
         // Match only with dirs if we end with '/'
-        let match_with_dirs = self.config.match_with_dirs;
-        let mut match_with_files = self.config.match_with_files;
         if &pattern[pattern.len() - 1..] == "/" {
             pattern = &pattern[0..pattern.len() - 1];
             match_with_files = false;
 
             if !match_with_dirs {
-                return Err(anyhow!(
+                return Err(LaxError::InvalidPattern(
                     "Matching is configured to only match with \
                                    files, yet glob pattern ends with '/', \
                                    implying a search for directories"
+                        .into(),
                 ));
             }
         }
 
+        // Hidden entries are still searched if the pattern explicitly names them, like
+        // `.github/workflows/*.yml`, even though generic globs like `*` skip them
+        let hidden_components = pattern
+            .split('/')
+            .filter(|component| {
+                component.starts_with('.') && *component != "." && *component != ".."
+            })
+            .map(|component| {
+                GlobBuilder::new(component)
+                    .literal_separator(true)
+                    .build()
+                    .map(|glob| glob.compile_matcher())
+                    .map_err(|err| LaxError::InvalidPattern(err.to_string()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
         let pattern = "./**/".to_string() + pattern;
         let glob = GlobBuilder::new(pattern.as_str())
             .literal_separator(true)
-            .build()?
+            .build()
+            .map_err(|err| LaxError::InvalidPattern(err.to_string()))?
             .compile_matcher();
+        timings.glob_compile += start.elapsed();
 
-        // Filter out hidden directories like ".git"/".svn"
-        let matcher = match self.config.search_hidden {
-            true => |_: &DirEntry| true,
-            false => |entry: &DirEntry| {
-                let file_name = entry.file_name().to_str();
-                let is_hidden = file_name
-                    .map(|s| s.starts_with('.') && s != "." && s != "..")
-                    .unwrap_or(false);
-                !is_hidden
-            },
+        let filter = WalkFilter {
+            search_hidden: self.config.search_hidden,
+            named: hidden_components,
+            prune: Vec::new(),
+            backend: None,
         };
 
-        let entry_point = shellexpand::tilde(entry_point);
+        let start = Instant::now();
+        let entry_point = expand_tilde(entry_point);
         let entry_point = entry_point.as_ref();
 
-        // Possibly need to find the git/svn root
-        let entry_point = if from_repository_root {
-            let root = get_repository_root()?;
-            if entry_point != "." && entry_point != "/" {
-                root.join(entry_point)
-            } else {
-                root
-            }
+        // An entry point like "{src,tests}" stands for several
+        let alternatives: Vec<PathBuf> = expand_braces(entry_point)
+            .into_iter()
+            .map(PathBuf::from)
+            .collect();
+
+        // Possibly need to find the repository root, look up a bookmark, or query zoxide
+        let entry_points = if let Some(root) = self.origin_directory(origin)? {
+            alternatives
+                .iter()
+                .map(|entry_point| {
+                    if entry_point != Path::new(".") && entry_point != Path::new("/") {
+                        root.join(entry_point)
+                    } else {
+                        root.clone()
+                    }
+                })
+                .collect()
         } else {
-            PathBuf::from(entry_point)
+            // Relative entry points are also tried relative to each directory in the search path,
+            // like CDPATH
+            let mut entry_points = alternatives.clone();
+            for dir in &self.config.search_path {
+                let dir = PathBuf::from(expand_tilde(&dir.to_string_lossy()).as_ref());
+                for entry_point in alternatives.iter().filter(|path| path.is_relative()) {
+                    entry_points.push(if entry_point == Path::new(".") {
+                        dir.clone()
+                    } else {
+                        dir.join(entry_point)
+                    });
+                }
+            }
+            entry_points
         };
 
-        if !entry_point.exists() {
-            return Err(anyhow!("Entry point {:?} doesn't exist.\n\t\
-                                               Reminder: the \
-                                               @pattern syntax is \
-                                               \"@[%][ENTRY_POINT/**/]GLOB_PATTERN[^SELECTOR]\".\n\tMake sure \
-                                               the bit before the first \"/**/\" is a valid \
-                                               directory", entry_point));
-        }
+        // Don't walk the same directory twice if it's reachable from multiple search path entries.
+        // Candidates' entry points needn't exist at all
+        let mut walked = Vec::new();
+        let entry_points: Vec<PathBuf> = entry_points
+            .into_iter()
+            .filter(|entry_point| {
+                let key = if self.config.candidates.is_some() {
+                    Ok(entry_point.clone())
+                } else {
+                    entry_point.canonicalize()
+                };
+                match key {
+                    Ok(canonical) if !walked.contains(&canonical) => {
+                        walked.push(canonical);
+                        true
+                    }
+                    _ => false,
+                }
+            })
+            .collect();
+        timings.root_detection += start.elapsed();
 
-        // Go to the entry point
-        let cwd = env::current_dir()?;
-        env::set_current_dir(&entry_point)?;
+        if entry_points.is_empty() {
+            return Err(LaxError::EntryPointMissing(entry_point.into()));
+        }
 
-        // We have an opportunity to quit early in some cases when selectors are provided.
-        let quit_after_index = match selector_group {
-            Some(selector_group) => selector_group.highest_index(),
-            None => None,
+        let start = Instant::now();
+        let mut walk = Walk {
+            entry_points: entry_points.into_iter(),
+            current: None,
+            glob,
+            exclusion,
+            filter,
+            rules: self.config.entry_point_rules.clone(),
+            match_with_dirs,
+            match_with_files,
+            types: self.config.entry_types.clone(),
+            extensions: self.config.extensions.clone(),
+            owner: owner_id(self.config.owner.as_deref())?,
+            access: self.config.access,
+            append_dir_slash: self.config.append_dir_slash,
+            cache_dir: self.config.cache_dir.clone(),
+            refresh_cache: self.config.refresh_cache,
+            candidates: self.config.candidates.clone(),
+            stats: SearchStats::default(),
+            sorted: self.config.sort != MatchOrder::Walk,
+            timeout: self.config.timeout,
+            walked: Duration::ZERO,
         };
-        let mut current_index = 0;
-
-        let walker = WalkDir::new(".").into_iter();
-        for e in walker.filter_entry(matcher).filter_map(|e| e.ok()) {
-            if let Some(path_name) = e.path().to_str() {
-                if glob.is_match(path_name) {
-                    // String comparison is a lot faster than fetching the metadata, so keep this
-                    // in the inner if block
-                    let metadata = e.metadata()?;
-
-                    let matched = (match_with_dirs && (match_with_files || metadata.is_dir()))
-                        || (match_with_files && metadata.is_file());
-
-                    if matched {
-                        let path_name = match path_name.strip_prefix("./") {
-                            Some(path_name) => path_name,
-                            None => path_name,
-                        };
-                        let mut result = entry_point.join(path_name).to_string_lossy().to_string();
-                        if metadata.is_dir() {
-                            result.push('/')
-                        }
-                        paths.push(result);
+        take_matches(&mut walk, paths, stop_after)?;
+        timings.walk += start.elapsed();
 
-                        if let Some(quit_after_index) = quit_after_index {
-                            if quit_after_index == current_index {
-                                return Ok(());
-                            }
+        Ok(Some(walk))
+    }
 
-                            current_index += 1;
-                        }
-                    }
+    // Find executables in $PATH whose names match a glob pattern. Like a shell, only the first
+    // executable with a given name is used.
+    fn fetch_executables(pattern: &str, paths: &mut Vec<Match>) -> Result<()> {
+        let glob = GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .build()
+            .map_err(|err| LaxError::InvalidPattern(err.to_string()))?
+            .compile_matcher();
+        let search_path = env::var_os("PATH").unwrap_or_default();
+
+        let mut names = Vec::new();
+        for directory in env::split_paths(&search_path) {
+            let Ok(entries) = fs::read_dir(&directory) else {
+                continue;
+            };
+            let mut found = Vec::new();
+            for entry in entries.filter_map(|entry| entry.ok()) {
+                let name = entry.file_name();
+                if !glob.is_match(&name) || names.contains(&name) || !is_executable(&entry.path()) {
+                    continue;
                 }
+                names.push(name);
+                found.push(entry.path().to_string_lossy().into_owned());
             }
+            // Directory order is arbitrary, so at least be consistent within each directory
+            found.sort();
+            paths.extend(found.into_iter().map(Match::stat));
         }
-
-        // Head back to our original directory
-        env::set_current_dir(cwd)?;
-
         Ok(())
     }
 
-    // Build a selector group from string.
-    //
-    // Selectors can be:
-    // 1 to N: Select path number #n
-    // -N to -1: Select path number #n in reverse order
-    // 'a': Select all paths
-    // 'l': Select last path
-    //
-    // Multiple selectors are delimited by commas.
-    fn parse_selectors(raw_selectors: &str) -> Result<SelectorGroup> {
-        let mut selectors = vec![];
+    // Find recently selected paths under an entry point that match a glob pattern, most frecent
+    // first. No filesystem walk needed.
+    fn fetch_recent(&self, entry_point: &str, pattern: &str, paths: &mut Vec<Match>) -> Result<()> {
+        let frecency_file = self
+            .config
+            .frecency_file
+            .as_ref()
+            .ok_or(LaxError::FrecencyDisabled)?;
 
-        for selector in raw_selectors.trim().split(',') {
-            if selector == "a" {
-                selectors.push(Selector::All);
-                continue;
-            }
+        // Match only with dirs if we end with '/'
+        let (pattern, match_with_files) = match pattern.strip_suffix('/') {
+            Some(pattern) => (pattern, false),
+            None => (pattern, self.config.match_with_files),
+        };
+        // `@recent:` alone matches everything
+        let pattern = if pattern.is_empty() { "*" } else { pattern };
+        let glob = GlobBuilder::new(&format!("**/{pattern}"))
+            .literal_separator(true)
+            .build()
+            .map_err(|err| LaxError::InvalidPattern(err.to_string()))?
+            .compile_matcher();
+        let entry_point = fs::canonicalize(expand_tilde(entry_point).as_ref())?;
 
-            if let Some(selector) = selector.strip_prefix('/') {
-                selectors.push(Selector::Regex(selector.into()));
+        for path in frecency::recent(frecency_file)? {
+            let Ok(relative) = Path::new(&path).strip_prefix(&entry_point) else {
+                continue;
+            };
+            if !glob.is_match(relative) {
                 continue;
             }
-
-            // This was added before you could specify negative selectors. Consider deprecation.
-            if selector == "l" {
-                selectors.push(Selector::FromBack(0));
+            // Recent paths may well have been deleted since
+            let Ok(metadata) = fs::metadata(&path) else {
                 continue;
+            };
+            let depth = relative.components().count();
+            if metadata.is_dir() && self.config.match_with_dirs {
+                let path = if self.config.append_dir_slash {
+                    format!("{}{}", path, std::path::MAIN_SEPARATOR)
+                } else {
+                    path
+                };
+                paths.push(Match::new(path, &metadata, depth));
+            } else if metadata.is_file() && match_with_files {
+                paths.push(Match::new(path, &metadata, depth));
             }
+        }
+        Ok(())
+    }
 
-            let index: isize = selector
-                .parse()
-                .map_err(|_| anyhow!("Invalid selector: '{selector}'"))?;
+    // Patterns without an entry point search from the configured default, if there is one
+    fn default_entry_point<'a>(&self, origin: &Origin, entry_point: Cow<'a, str>) -> Cow<'a, str> {
+        match &self.config.default_entry_point {
+            Some(directory) if *origin == Origin::CurrentDirectory && entry_point == "." => {
+                Cow::Owned(directory.to_string_lossy().into_owned())
+            }
+            _ => entry_point,
+        }
+    }
 
-            // Selectors are 1-indexed
-            if index == 0 {
-                return Err(anyhow!("Selectors are 1-indexed and cannot be zero"));
+    // Get the directory a search starts from, if it doesn't start from the current directory
+    fn origin_directory(&self, origin: &Origin) -> Result<Option<PathBuf>> {
+        Ok(match origin {
+            Origin::RepositoryRoot(search) => {
+                Some(get_repository_root(&self.config.root_markers, *search)?)
             }
+            Origin::Bookmark(name) => Some(self.bookmark(name)?),
+            Origin::Zoxide(query) => Some(zoxide_query(query)?),
+            Origin::CurrentDirectory
+            | Origin::History(_)
+            | Origin::ExecutableSearchPath
+            | Origin::Recent => None,
+        })
+    }
 
-            if index < 0 {
-                selectors.push(Selector::FromBack(index.unsigned_abs() - 1));
-            } else {
-                selectors.push(Selector::FromFront(index.unsigned_abs() - 1));
+    // Look up a bookmark's directory by name
+    fn bookmark(&self, name: &str) -> Result<PathBuf> {
+        let path = self
+            .config
+            .bookmarks
+            .get(name)
+            .ok_or_else(|| LaxError::UnknownBookmark(name.into()))?;
+        Ok(PathBuf::from(
+            expand_tilde(&path.to_string_lossy()).as_ref(),
+        ))
+    }
+
+    // Expand an '@' pattern into all its matches, which are narrowed down by either the '@'
+    // pattern's selectors, or selectors given from a CLI/TUI menu.
+    //
+    // If `program` is set, the pattern is in the program position, and plain patterns are
+    // resolved against $PATH instead of the filesystem.
+    fn expand_pattern(
+        &mut self,
+        pattern: &str,
+        program: bool,
+        cache: &mut MatchCache,
+        timings: &mut Timings,
+        stats: &mut SearchStats,
+    ) -> Result<(Vec<String>, String)> {
+        // A pattern may have alternatives, like "@Cargo.toml|pyproject.toml". The first one that
+        // matches anything wins, like the fallbacks for programs
+        let normalized = normalize_separators(pattern);
+        let mut alternatives: Vec<String> = split_alternatives(&normalized)
+            .into_iter()
+            .map(Cow::into_owned)
+            .collect();
+        loop {
+            let mut remaining = alternatives.iter();
+            let mut matched = remaining.next().cloned().unwrap_or_default();
+            let mut matches = self.match_alternative(&matched, program, cache, timings, stats);
+            for alternative in remaining {
+                match &matches {
+                    Err(err) if falls_through(err) => {
+                        info!("{pattern}: {err}, so trying {alternative}")
+                    }
+                    _ => break,
+                }
+                matches = self.match_alternative(alternative, program, cache, timings, stats);
+                matched.clone_from(alternative);
+            }
+            let (paths, walk, selector_group, named) = matches?;
+
+            let start = Instant::now();
+            let selection = self.select_paths(pattern, paths, selector_group, walk, named);
+            timings.selection += start.elapsed();
+            match selection? {
+                Selection::Paths(selected_paths) => return Ok((selected_paths, matched)),
+                // Search again from the same place, keeping the pattern's selectors
+                Selection::Refine(glob) => {
+                    let mut refined: pattern::Pattern = matched.parse()?;
+                    refined.glob = glob;
+                    info!("{pattern}: searching again as {refined}");
+                    alternatives = vec![refined.to_string()];
+                }
             }
         }
-        Ok(SelectorGroup { selectors })
     }
 
-    // Parse an @ pattern into its subcomponents
-    //
-    // '@' patterns are in the form:
-    // @[%][ENTRY_POINT/**/]GLOB_PATTERN[^SELECTOR_GROUP]
-    //
-    // Where [%][ENTRY_POINT/**/]GLOB_PATTERN expands into multiple paths, and a selector
-    // group(possibly SELECTOR_GROUP) is used to narrow them down
-    fn parse_pattern(pattern: &str) -> Result<(bool, &str, &str, Option<&str>)> {
-        // Git rid of '@' symbol
-        let pattern = &pattern[1..];
+    // Find the matches for a single alternative of a pattern, failing if there are none. Also
+    // returns what's left of the walk, for the menu, the selectors to narrow them down with, and
+    // how many components at the end of each match the pattern named.
+    fn match_alternative(
+        &mut self,
+        pattern: &str,
+        program: bool,
+        cache: &mut MatchCache,
+        timings: &mut Timings,
+        stats: &mut SearchStats,
+    ) -> Result<AlternativeMatches> {
+        let start = Instant::now();
+        let (mut origin, entry_point, glob_pattern, selector_group, only) =
+            Self::parse_pattern(pattern)?;
+        let selector_group = self.selector_group(pattern, &origin, selector_group)?;
+        if program && origin == Origin::CurrentDirectory && entry_point == "." {
+            origin = Origin::ExecutableSearchPath;
+        }
+        let entry_point = self.default_entry_point(&origin, entry_point);
+        timings.parse += start.elapsed();
+        info!("{pattern}: searching {origin:?} under {entry_point:?} for {glob_pattern:?}");
+
+        // Get list of all matches, unless an identical pattern has already been walked. The menu
+        // only shows the first `max_results`, and can ask for more later
+        let quit_after_index = selector_group
+            .as_ref()
+            .and_then(SelectorGroup::highest_index);
+        let uses_menu = match &selector_group {
+            Some(selector_group) => selector_group.selectors.is_empty(),
+            None => true,
+        };
+        // Matches can only be sorted by anything but their path once they've all been found
+        let sort_key = self.sort_key(&origin, selector_group.as_ref());
+        // Selectors that pick from the back, like `^-1` or `^1,-1`, still need the whole walk, but
+        // only the matches at the ends have to be kept. Default selectors might fall back to the
+        // menu, which needs them all
+        let back_window = selector_group
+            .as_ref()
+            .filter(|selector_group| !selector_group.is_default)
+            .and_then(SelectorGroup::window)
+            .filter(|window| window.back > 0 && sort_key.is_none());
+        let stop_after = match (quit_after_index, back_window) {
+            _ if sort_key.is_some() => None,
+            (Some(index), _) => Some(index + 1),
+            (None, Some(window)) => Some(window.front),
+            (None, None) if uses_menu => self.config.max_results,
+            (None, None) => None,
+        };
+        // Don't walk much further than `max_matches` before asking whether to go on. The menu
+        // doesn't put every match on the command line, so it's exempt
+        let limit = self
+            .config
+            .max_matches
+            .filter(|_| !uses_menu)
+            .filter(|&max_matches| !matches!(stop_after, Some(len) if len <= max_matches))
+            .map(|max_matches| max_matches + 1);
+        let search = Search {
+            origin,
+            entry_point: entry_point.into_owned(),
+            glob: glob_pattern.into_owned(),
+            only,
+        };
+        let cached = cache.get(&search, quit_after_index.filter(|_| sort_key.is_none()));
+        let (mut paths, walk) = match cached {
+            Some(paths) => {
+                info!("{pattern}: reusing the matches of an identical search");
+                (paths, None)
+            }
+            None => {
+                let mut paths = Vec::new();
+                let mut walk = self
+                    .fetch_matches(&search, &mut paths, limit.or(stop_after), timings)
+                    .map_err(|err| timed_out(err, pattern, &paths))?;
+                if let Some(limit) = limit.filter(|&limit| paths.len() >= limit) {
+                    let max_matches = limit - 1;
+                    info!("{pattern}: matched more than {max_matches} paths");
+                    let confirmed = self.config.use_menu
+                        && self.selector_menu.confirm_many(pattern, max_matches)?;
+                    if !confirmed {
+                        return Err(LaxError::TooManyMatches(pattern.into(), max_matches));
+                    }
+                    if let Some(rest) = &mut walk {
+                        let start = Instant::now();
+                        take_matches(rest, &mut paths, stop_after)
+                            .map_err(|err| timed_out(err, pattern, &paths))?;
+                        timings.walk += start.elapsed();
+                    }
+                }
+                let mut complete = match stop_after {
+                    Some(len) => paths.len() < len,
+                    None => true,
+                };
+                let mut dropped = 0;
+                let mut cached = None;
+                if let (Some(window), Some(rest)) = (back_window, &mut walk) {
+                    if !complete {
+                        let start = Instant::now();
+                        let front = paths.len();
+                        dropped = take_last(rest, &mut paths, window.back)
+                            .map_err(|err| timed_out(err, pattern, &paths))?;
+                        timings.walk += start.elapsed();
+                        // Only the front of the list can be reused if the middle was dropped
+                        complete = dropped == 0;
+                        if !complete {
+                            cached = Some(paths[..front].to_vec());
+                        }
+                    }
+                }
+                stats.matched += paths.len() + dropped;
+                if let Some(walk) = &walk {
+                    stats.directories += walk.stats.directories;
+                    stats.files += walk.stats.files;
+                }
+                cache.entries.insert(
+                    search.clone(),
+                    CachedMatches {
+                        paths: cached.unwrap_or_else(|| paths.clone()),
+                        complete,
+                    },
+                );
+                (paths, walk.filter(|_| uses_menu && !complete))
+            }
+        };
+        // The cache keeps matches in the order they were found, as patterns can sort differently
+        if let Some(sort_key) = sort_key {
+            sort_key.sort_matches(&mut paths);
+        }
 
-        if pattern.is_empty() {
-            bail!("Empty pattern - nothing specified after '@' symbol");
+        info!("{pattern}: matched {} paths", paths.len());
+        if paths.is_empty() {
+            let suggestions = self.suggest(pattern, &search);
+            return Err(LaxError::NoMatches(search.glob, suggestions));
+        }
+        Ok((paths, walk, selector_group, named_components(&search.glob)))
+    }
+
+    // How a pattern's matches are sorted once they've been found, if they need to be. Its
+    // selectors can ask for a different order than the config
+    fn sort_key(&self, origin: &Origin, selector_group: Option<&SelectorGroup>) -> Option<SortKey> {
+        if origin.is_ordered() {
+            return None;
         }
+        let order = selector_group
+            .and_then(|selector_group| selector_group.sort)
+            .unwrap_or(self.config.sort);
+        order.sort_key(self.config.sort != MatchOrder::Walk)
+    }
 
-        // The "from repository root" modifier. This enables us to start the search from the git/svn root.
-        let (pattern, repository_root) = if let Some(pattern) = pattern.strip_prefix('%') {
-            (pattern, true)
-        // Faux "escape modifier" modifier, so we can escape what would otherwise be considered a
-        // modifier
-        } else if let Some(pattern) = pattern.strip_prefix('\\') {
-            (pattern, false)
-        } else {
-            (pattern, false)
+    // Suggest patterns like one that matched nothing, but with the end of its glob pattern
+    // swapped for the closest paths that are there, by edit distance. Only the first
+    // `SUGGESTION_SCAN_LIMIT` paths are looked at, so huge trees don't slow the failure down.
+    fn suggest(&self, pattern: &str, search: &Search) -> Vec<String> {
+        let Ok((glob_pattern, _)) = split_exclusion(&search.glob) else {
+            return Vec::new();
         };
+        let (target, everything) = match glob_pattern.strip_suffix('/') {
+            Some(target) => (target, "*/"),
+            None => (glob_pattern, "*"),
+        };
+        let components = target.split('/').count();
+        let max_distance = (target.chars().count() / 4).max(1);
 
-        let pattern = &mut pattern.split('^');
-
-        let (pattern, selectors) = (
-            pattern
-                .next()
-                .ok_or_else(|| anyhow!("Empty patterns are not allowed"))?,
-            pattern.next(),
+        let mut paths = Vec::new();
+        let everything = Search {
+            glob: everything.into(),
+            ..search.clone()
+        };
+        let _ = self.fetch_matches(
+            &everything,
+            &mut paths,
+            Some(SUGGESTION_SCAN_LIMIT),
+            &mut Timings::default(),
         );
+        let mut scored: Vec<(usize, String)> = paths
+            .iter()
+            .filter_map(|path| {
+                let path = path.to_path_string();
+                let path = path.trim_end_matches('/');
+                let mut start = path.len();
+                for _ in 0..components {
+                    start = path[..start].rfind('/')?;
+                }
+                let tail = path[start + 1..].to_string();
+                let distance = edit_distance(target, &tail);
+                (distance <= max_distance).then_some((distance, tail))
+            })
+            .collect();
+        scored.sort();
+        scored.dedup_by(|(_, a), (_, b)| a == b);
+
+        scored
+            .into_iter()
+            .take(MAX_SUGGESTIONS)
+            .map(|(_, tail)| match pattern.rfind(target) {
+                Some(index) => format!(
+                    "{}{tail}{}",
+                    &pattern[..index],
+                    &pattern[index + target.len()..]
+                ),
+                None => format!("@{tail}"),
+            })
+            .collect()
+    }
 
-        // Extract entry_point and glob pattern
-        let mut pattern = pattern.splitn(2, "/**/");
-
-        let (entry_point, glob_pattern) = match (pattern.next(), pattern.next()) {
-            (Some(glob_pattern), None) => (".", glob_pattern),
-            (Some(entry_point), Some(glob_pattern)) => (
-                // Root is an expected default in this case, even if it's not very useful
-                if entry_point.is_empty() {
-                    "/"
-                } else {
-                    entry_point
-                },
-                // If no glob pattern is given, we should match all directories, since we end with
-                // '/**/'
-                if glob_pattern.is_empty() {
-                    "*/"
-                } else {
-                    glob_pattern
-                },
-            ),
-            // .splitn(2,_) will produce at least one value, even on an empty string
-            (None, _) => unreachable!(),
+    // Narrow down matches with the pattern's selectors, or, failing that, the menu. `walk` is
+    // the rest of a paused walk, which the menu can load more matches from, and `named` is how many
+    // components at the end of each match the menu highlights.
+    fn select_paths(
+        &mut self,
+        pattern: &str,
+        paths: Vec<Match>,
+        selector_group: Option<SelectorGroup>,
+        walk: Option<Walk>,
+        named: usize,
+    ) -> Result<Selection> {
+        let to_parents = selector_group
+            .as_ref()
+            .is_some_and(|selector_group| selector_group.to_parents);
+        let selected_paths = match selector_group {
+            Some(selector_group) if !selector_group.selectors.is_empty() => {
+                info!(
+                    "{pattern}: selecting with {}'{selector_group}'",
+                    if selector_group.is_default {
+                        "the default selectors "
+                    } else {
+                        ""
+                    }
+                );
+                match selector_group.select(&paths) {
+                    Ok(selected_paths) if !selected_paths.is_empty() => {
+                        Selection::Paths(selected_paths)
+                    }
+                    _ if selector_group.is_default => {
+                        info!("{pattern}: the default selectors picked nothing");
+                        self.prompt(pattern, paths, walk, named)?
+                    }
+                    selected_paths => Selection::Paths(selected_paths?),
+                }
+            }
+            _ => self.prompt(pattern, paths, walk, named)?,
+        };
+        let selected_paths = match selected_paths {
+            Selection::Paths(selected_paths) if to_parents => selected_paths,
+            selection => return Ok(selection),
         };
 
-        Ok((repository_root, entry_point, glob_pattern, selectors))
+        let mut parents = Vec::new();
+        for path in selected_paths {
+            let parent = FilesToDirs::from_config(&self.config).apply(path)?;
+            if !parents.contains(&parent) {
+                parents.push(parent);
+            }
+        }
+        Ok(Selection::Paths(parents))
     }
 
-    // Expand an '@' pattern into all its matches, which are narrowed down by either the '@'
-    // pattern's selectors, or selectors given from a CLI/TUI menu.
-    fn expand_pattern(&self, pattern: &str) -> Result<Vec<String>> {
-        let (repository_root, entry_point, glob_pattern, selector_group) =
-            Self::parse_pattern(pattern)?;
-        let selector_group = selector_group.map(Self::parse_selectors).transpose()?;
+    // Let the user pick from the matches, unless there's only one.
+    fn prompt(
+        &mut self,
+        pattern: &str,
+        mut paths: Vec<Match>,
+        walk: Option<Walk>,
+        named: usize,
+    ) -> Result<Selection> {
+        let mut walk = walk.map(Iterator::peekable);
+        let more = |walk: &mut Option<std::iter::Peekable<Walk>>| {
+            walk.as_mut().is_some_and(|walk| walk.peek().is_some())
+        };
 
-        // Get list of all matches
-        let mut paths = Vec::new();
-        self.fetch_matches(
-            repository_root,
-            entry_point,
-            glob_pattern,
-            &mut paths,
-            &selector_group,
-        )?;
+        // One match - no need to bother the user.
+        if paths.len() == 1 && !more(&mut walk) {
+            return Ok(Selection::Paths(vec![paths.remove(0).to_path_string()]));
+        }
 
-        if paths.is_empty() {
-            return Err(anyhow!("Could not match pattern: \"{}\"", glob_pattern));
+        // Put the paths the user is most likely to want first. This is only a nicety, so
+        // don't fail if the database is unreadable.
+        if let Some(frecency_file) = &self.config.frecency_file {
+            let _ = frecency::rank(frecency_file, &mut paths);
         }
 
-        if let Some(selector_group) = selector_group {
-            selector_group.select(&paths)
-        } else {
-            // One match - no need to bother the user.
-            if paths.len() == 1 {
-                return Ok(vec![paths.remove(0)]);
+        // Without a menu, there's no way to tell which the user meant
+        if !self.config.use_menu {
+            if let Some(walk) = &mut walk {
+                take_matches(walk, &mut paths, None)
+                    .map_err(|err| timed_out(err, pattern, &paths))?;
             }
+            let paths = paths.iter().map(Match::to_path_string).collect();
+            return Err(LaxError::AmbiguousPattern(pattern.into(), paths));
+        }
 
-            // No selector - given. Break into CLI or TUI menu
-            let mut first_call = true;
-            loop {
-                let option = (self.selector_menu)(&paths, first_call);
-                first_call = false;
+        // No selector - given. Break into CLI or TUI menu
+        info!("{pattern}: asking the menu which matches to use");
+        //
+        // Selectors always refer to the paths in their original order, which is each candidate's
+        // ID. Re-sorting only changes the order they're shown in, and loading more only adds new
+        // IDs, so a number the user reads off the menu always means the same path.
+        let mut order: Vec<usize> = (0..paths.len()).collect();
+        let mut attempt = 0;
+        let selected_paths = loop {
+            let candidates: Vec<Candidate> = order
+                .iter()
+                .map(|&index| Candidate {
+                    id: index + 1,
+                    matched_part: last_components(&paths[index].to_path_string(), named),
+                    matched: paths[index].clone(),
+                })
+                .collect();
+            let more = more(&mut walk);
+            let option = match self.selector_menu.select(&candidates, attempt, more)? {
+                MenuOutcome::Selectors(option) => option,
+                MenuOutcome::Paths(selected_paths) => break selected_paths,
+                MenuOutcome::Cancelled => return Err(LaxError::Cancelled),
+            };
+            attempt += 1;
+            let option = match &self.config.menu_default {
+                _ if !option.trim().is_empty() => option,
+                Some(default) => default.clone(),
+                None => continue,
+            };
+
+            if let Some(key) = option.trim().strip_prefix("s ") {
+                if let Some(key) = SortKey::parse(key.trim()) {
+                    key.sort(&paths, &mut order);
+                    // Show the list again in its new order
+                    attempt = 0;
+                }
+                continue;
+            }
 
-                let selected_paths = Self::parse_selectors(&option)?.select(&paths);
+            if let Some(glob) = option.trim().strip_prefix("e ") {
+                let glob = glob.trim();
+                if !glob.is_empty() {
+                    return Ok(Selection::Refine(glob.into()));
+                }
+                continue;
+            }
 
-                if let Ok(selected_paths) = selected_paths {
-                    return Ok(selected_paths);
+            if option.trim() == "m" {
+                if let Some(walk) = &mut walk {
+                    let len = self.config.max_results.map(|max| paths.len() + max);
+                    let loaded = paths.len();
+                    take_matches(walk, &mut paths, len)
+                        .map_err(|err| timed_out(err, pattern, &paths))?;
+                    order.extend(loaded..paths.len());
+                    // Show the list again with the new matches
+                    attempt = 0;
                 }
+                continue;
+            }
+
+            let selected_paths = Self::parse_selectors(&option)?.select(&paths);
+
+            if let Ok(selected_paths) = selected_paths {
+                break selected_paths;
             }
+        };
+
+        // However the menu picked them
+        if self.config.echo_selection {
+            eprintln!("{}", selection_summary(&selected_paths));
         }
+        Ok(Selection::Paths(selected_paths))
     }
 
-    /// Apply post-selector transformations
+    /// Apply post-selector transformations: the ones the config's settings ask for, then any
+    /// extra ones, then the pattern's own filename modifiers
     ///
     /// # Returns
     /// The transformed and expanded pattern
-    fn apply_post_transforms(&self, mut expanded_pattern: Vec<String>) -> Result<Vec<String>> {
-        // Transform files to directories
-        if self.config.transform_files_to_dirs {
-            let res: Result<Vec<String>> = expanded_pattern
-                .into_iter()
-                .map(|path| {
-                    if fs::metadata(&path)?.is_dir() {
-                        Ok(path)
-                    } else {
-                        if let Some(parent) = Path::new(&path).parent() {
-                            return Ok(parent.display().to_string());
-                        };
-                        Err(anyhow!("Could not get parent of file: \"{}\"", path))
-                    }
-                })
-                .collect();
-            expanded_pattern = res?;
-        };
-
-        Ok(expanded_pattern)
+    fn apply_post_transforms(
+        &self,
+        expanded_pattern: Vec<String>,
+        modifiers: &[FilenameModifier],
+    ) -> Result<Vec<String>> {
+        let transformed = Pipeline::from_config(&self.config).apply(expanded_pattern)?;
+        apply_modifiers(self.config.post_transforms.apply(transformed)?, modifiers)
     }
 
     /// Transform a list of arguments containing 0 or more '@' patterns.
     ///
     /// # Returns
     /// The transformed argument list.
-    pub fn expand_arguments(&self, args: &[String]) -> Result<Vec<String>> {
-        let mut transformed_args: Vec<String> = Vec::new();
-        for arg in args {
-            if arg.starts_with('@') {
-                let expanded_pattern = self.expand_pattern(arg)?;
-                transformed_args.append(&mut self.apply_post_transforms(expanded_pattern)?);
-            } else {
-                // Allow '@' to be escaped
-                let new_arg = if arg.starts_with("\\@") {
-                    arg[1..].to_string()
-                } else {
-                    arg.to_string()
-                };
-                transformed_args.push(new_arg);
+    pub fn expand_arguments(&mut self, args: &[String]) -> Result<Vec<String>> {
+        Ok(self.expand_arguments_with_reports(args)?.0)
+    }
+
+    /// Transform a list of arguments like [`Expander::expand_arguments`], and also report how each
+    /// '@' pattern was expanded.
+    pub fn expand_arguments_with_reports(
+        &mut self,
+        args: &[String],
+    ) -> Result<(Vec<String>, Vec<PatternReport>)> {
+        let (transformed_args, reports) = self.expand(args)?;
+        self.record(&reports);
+        Ok((transformed_args, reports))
+    }
+
+    /// Transform a list of arguments like [`Expander::expand_arguments_with_reports`], but without
+    /// recording history. Pass the reports to [`Expander::record`] afterwards, maybe along with
+    /// those of other expansions, so they're recorded as a single invocation.
+    pub fn expand_arguments_unrecorded(
+        &mut self,
+        args: &[String],
+    ) -> Result<(Vec<String>, Vec<PatternReport>)> {
+        self.expand(args)
+    }
+
+    /// Transform a list of arguments like [`Expander::expand_arguments`], but without failing the
+    /// whole list when one '@' pattern fails. Patterns that fail are left as they are in the
+    /// transformed arguments, and their errors are reported alongside the others' paths.
+    pub fn expand_arguments_lossy(&mut self, args: &[String]) -> LossyExpansion {
+        let mut expansion = LossyExpansion::default();
+        let mut reports = Vec::new();
+        let mut cache = MatchCache::default();
+        let (expanded, literal) = self.split_literal(args);
+        for (index, arg) in expanded.iter().enumerate() {
+            match self.expand_argument(index, arg, &mut cache) {
+                Ok((paths, report)) => {
+                    expansion.args.extend_from_slice(&paths);
+                    expansion.results.push(Ok(paths));
+                    reports.extend(report);
+                }
+                Err(err) => {
+                    expansion.args.push(arg.clone());
+                    expansion.results.push(Err(err));
+                }
             }
         }
-
-        Ok(transformed_args)
+        if let Some(literal) = literal {
+            // The marker expands to nothing, and what follows it to itself
+            expansion.results.push(Ok(Vec::new()));
+            for arg in literal {
+                expansion.args.push(arg.clone());
+                expansion.results.push(Ok(vec![arg.clone()]));
+            }
+        }
+        self.record(&reports);
+        expansion
     }
-}
 
-/// Struct used for configuring an instance of Expander.
+    /// Record what '@' patterns expanded to in the history and frecency files, if they're enabled,
+    /// as one invocation.
+    pub fn record(&self, reports: &[PatternReport]) {
+        if let Some(history_file) = &self.config.history_file {
+            let expanded_paths: Vec<String> = reports
+                .iter()
+                .flat_map(|report| report.paths.iter().cloned())
+                .collect();
+            if !expanded_paths.is_empty() {
+                // History is a convenience - failing to record it shouldn't stop the user from
+                // running their command
+                let _ = history::record(history_file, &expanded_paths);
+            }
+        }
+        if let Some(frecency_file) = &self.config.frecency_file {
+            let selected_paths: Vec<String> = reports
+                .iter()
+                .flat_map(|report| report.selected.iter().cloned())
+                .collect();
+            if !selected_paths.is_empty() {
+                let _ = frecency::record(frecency_file, &selected_paths);
+            }
+        }
+    }
+
+    /// Expand a list of arguments like [`Expander::expand_arguments`], but without recording
+    /// history, and report how each '@' pattern was expanded.
+    pub fn explain_arguments(&mut self, args: &[String]) -> Result<Vec<PatternReport>> {
+        Ok(self.expand(args)?.1)
+    }
+
+    /// Find an '@' pattern's matches lazily, as the filesystem is walked, so they can be shown as
+    /// they're found, or the search stopped early. Like expansion, the first of the pattern's
+    /// alternatives that matches anything is used. Its selectors aren't applied, and nothing is
+    /// recorded.
+    pub fn matches(&self, pattern: &str) -> impl Iterator<Item = Result<Match>> {
+        let (pattern, _) = split_modifiers(pattern);
+        let (found, walk) = self
+            .first_matches(pattern)
+            .unwrap_or_else(|err| (vec![Err(err)], None));
+        let pattern = pattern.to_string();
+        found
+            .into_iter()
+            .chain(walk.into_iter().flatten())
+            .map(move |path| path.map_err(|err| timed_out(err, &pattern, &[])))
+    }
+
+    // Start matching each of a pattern's alternatives in turn, until one finds anything. Returns
+    // what it found so far, and the rest of its walk, if it walks the filesystem.
+    fn first_matches(&self, pattern: &str) -> Result<(Vec<Result<Match>>, Option<Walk>)> {
+        let pattern = normalize_separators(pattern);
+        let mut started = Ok((Vec::new(), None));
+        for alternative in split_alternatives(&pattern) {
+            started = self.start_matching(&alternative);
+            match &started {
+                Ok((found, _)) if found.is_empty() => {}
+                Err(err) if falls_through(err) => {}
+                _ => break,
+            }
+        }
+        started
+    }
+
+    // Find a single alternative's first match, keeping the rest of the walk for later
+    fn start_matching(&self, pattern: &str) -> Result<(Vec<Result<Match>>, Option<Walk>)> {
+        let (origin, entry_point, glob_pattern, _, only) = Self::parse_pattern(pattern)?;
+        let entry_point = self.default_entry_point(&origin, entry_point);
+        let search = Search {
+            origin,
+            entry_point: entry_point.into_owned(),
+            glob: glob_pattern.into_owned(),
+            only,
+        };
+        let mut paths = Vec::new();
+        let walk = self.fetch_matches(&search, &mut paths, Some(1), &mut Timings::default())?;
+        Ok((paths.into_iter().map(Ok).collect(), walk))
+    }
+
+    /// Find out how an '@' pattern would resolve, without prompting the user or recording
+    /// anything.
+    pub fn probe(&self, pattern: &str) -> Result<Probe> {
+        let (pattern, _) = split_modifiers(pattern);
+        let pattern = normalize_separators(pattern);
+        let mut paths = Vec::new();
+        let mut selector_group = None;
+        let alternatives = split_alternatives(&pattern);
+        for (index, alternative) in alternatives.iter().enumerate() {
+            let (origin, entry_point, glob_pattern, selectors, only) =
+                Self::parse_pattern(alternative)?;
+            let entry_point = self.default_entry_point(&origin, entry_point);
+            selector_group = self.selector_group(alternative, &origin, selectors)?;
+            let search = Search {
+                origin,
+                entry_point: entry_point.into_owned(),
+                glob: glob_pattern.into_owned(),
+                only,
+            };
+            // Don't stop early, as ambiguous patterns should report every match
+            let fetched = self.fetch_matches(&search, &mut paths, None, &mut Timings::default());
+            // Like expansion, an alternative that can't be searched falls back to the next one
+            match fetched {
+                Err(err) if falls_through(&err) && index + 1 < alternatives.len() => continue,
+                fetched => fetched?,
+            };
+            if let Some(sort_key) = self.sort_key(&search.origin, selector_group.as_ref()) {
+                sort_key.sort_matches(&mut paths);
+            }
+            if !paths.is_empty() {
+                break;
+            }
+        }
+
+        let selected = match selector_group {
+            _ if paths.is_empty() => return Ok(Probe::Unmatched),
+            Some(selector_group) if !selector_group.selectors.is_empty() => {
+                selector_group.select(&paths).unwrap_or_default()
+            }
+            _ if paths.len() > 1 => {
+                return Ok(Probe::Ambiguous(
+                    paths.iter().map(Match::to_path_string).collect(),
+                ))
+            }
+            _ => paths.iter().map(Match::to_path_string).collect(),
+        };
+        Ok(if selected.is_empty() {
+            Probe::Unmatched
+        } else {
+            Probe::Unambiguous(selected)
+        })
+    }
+
+    // Transform a list of arguments, returning the transformed arguments and a report for each
+    // '@' pattern
+    fn expand(&mut self, args: &[String]) -> Result<(Vec<String>, Vec<PatternReport>)> {
+        let mut transformed_args: Vec<String> = Vec::new();
+        let mut reports: Vec<PatternReport> = Vec::new();
+        let mut cache = MatchCache::default();
+        let (expanded, literal) = self.split_literal(args);
+        for (index, arg) in expanded.iter().enumerate() {
+            let (paths, report) = self.expand_argument(index, arg, &mut cache)?;
+            transformed_args.extend(paths);
+            reports.extend(report);
+        }
+        transformed_args.extend(literal.into_iter().flatten().cloned());
+
+        Ok((transformed_args, reports))
+    }
+
+    /// Split arguments at the first [`Config::literal_marker`], into those that are expanded and,
+    /// if there's a marker, those after it, which are passed on as they are. The marker itself is
+    /// in neither.
+    pub fn split_literal<'a>(&self, args: &'a [String]) -> (&'a [String], Option<&'a [String]>) {
+        let marker = self.config.literal_marker.as_deref();
+        match args.iter().position(|arg| Some(arg.as_str()) == marker) {
+            Some(index) => (&args[..index], Some(&args[index + 1..])),
+            None => (args, None),
+        }
+    }
+
+    // Transform the argument at `index`, returning what it becomes, and a report if it's an '@'
+    // pattern
+    fn expand_argument(
+        &mut self,
+        index: usize,
+        arg: &str,
+        cache: &mut MatchCache,
+    ) -> Result<(Vec<String>, Option<PatternReport>)> {
+        if !is_pattern(arg) {
+            // Allow '@' to be escaped, as "@@" or "\@"
+            let new_arg = if arg.starts_with("@@") || arg.starts_with("\\@") {
+                arg[1..].to_string()
+            } else {
+                arg.to_string()
+            };
+            return Ok((vec![new_arg], None));
+        }
+
+        let mut timings = Timings::default();
+        let mut stats = SearchStats::default();
+        let program = index == 0 && self.config.resolve_program_from_path;
+        // The same pattern with different modifiers is still the same selection
+        let (pattern, modifiers) = split_modifiers(arg);
+        let (selected, matched) = match cache.selections.get(pattern) {
+            Some(selection) if self.config.reuse_selections && !program => {
+                info!("{arg}: reusing the earlier selection");
+                selection.clone()
+            }
+            _ => {
+                let selection =
+                    self.expand_pattern(pattern, program, cache, &mut timings, &mut stats)?;
+                if !program {
+                    cache.selections.insert(pattern.into(), selection.clone());
+                }
+                selection
+            }
+        };
+
+        let start = Instant::now();
+        let paths = if program {
+            apply_modifiers(selected.clone(), &modifiers)?
+        } else {
+            self.apply_post_transforms(selected.clone(), &modifiers)?
+        };
+        timings.post_transform += start.elapsed();
+
+        let expanded_as = matched
+            .parse::<pattern::Pattern>()
+            .ok()
+            .map(|mut expanded_as| {
+                expanded_as.filename_modifiers = modifiers;
+                expanded_as
+            });
+        let report = PatternReport {
+            pattern: arg.into(),
+            expanded_as,
+            selected,
+            paths: paths.clone(),
+            timings,
+            stats,
+        };
+        Ok((paths, Some(report)))
+    }
+}
+
+/// Which directory is considered the parent of a symlink to a file, when transforming files into
+/// their parent directories.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SymlinkParent {
+    /// The directory the link itself is in.
+    #[default]
+    Link,
+    /// The directory the link's (fully resolved) target is in.
+    Target,
+}
+
+/// How the paths '@' patterns expand to are written, once they've been selected and transformed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PathStyle {
+    /// As they were matched: relative to the current directory, like `./src/lib.rs`, or under
+    /// whichever directory the pattern searched.
+    #[default]
+    AsMatched,
+    /// Absolute, without resolving symlinks.
+    Absolute,
+    /// Relative to the current directory, going up with `..` if need be, without resolving
+    /// symlinks.
+    Relative,
+    /// Absolute, with every symlink resolved.
+    Canonical,
+}
+
+/// Which part of each expanded path is kept, after it's been written in its [`PathStyle`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PathPart {
+    /// The whole path.
+    #[default]
+    Whole,
+    /// Just the last component, like `basename`. Directories keep their trailing separator.
+    Basename,
+    /// Everything but the last component, like `dirname`. Unlike transforming files into their
+    /// parent directories, directories are replaced by their parents too.
+    Dirname,
+}
+
+/// The access to an entry that matches can be limited to with [`Config::access`], for the user
+/// running lax. Nothing is required by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Access {
+    /// The entry must be readable.
+    pub read: bool,
+    /// The entry must be writable.
+    pub write: bool,
+    /// The entry must be executable, or searchable if it's a directory.
+    pub execute: bool,
+}
+
+impl Access {
+    /// Parse some of 'r', 'w', and 'x', optionally after a '-', like `rw` or `-w`.
+    pub fn parse(access: &str) -> Option<Self> {
+        let letters = access.strip_prefix('-').unwrap_or(access);
+        if letters.is_empty() {
+            return None;
+        }
+        let mut parsed = Access::default();
+        for letter in letters.chars() {
+            match letter {
+                'r' => parsed.read = true,
+                'w' => parsed.write = true,
+                'x' => parsed.execute = true,
+                _ => return None,
+            }
+        }
+        Some(parsed)
+    }
+
+    // Whether we have this access to a path, following symlinks
+    #[cfg(unix)]
+    fn allows(self, path: &Path) -> bool {
+        use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+        let mode = [
+            (self.read, libc::R_OK),
+            (self.write, libc::W_OK),
+            (self.execute, libc::X_OK),
+        ]
+        .into_iter()
+        .filter(|(required, _)| *required)
+        .fold(0, |mode, (_, bit)| mode | bit);
+        if mode == 0 {
+            return true;
+        }
+        let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+            return false;
+        };
+        // SAFETY: `path` is a valid C string that outlives the call
+        unsafe { libc::access(path.as_ptr(), mode) == 0 }
+    }
+
+    #[cfg(not(unix))]
+    fn allows(self, _path: &Path) -> bool {
+        true
+    }
+}
+
+/// A type of entry that matches can be limited to with [`Config::entry_types`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryType {
+    /// Regular files.
+    File,
+    /// Directories.
+    Directory,
+    /// Symlinks, which aren't followed.
+    Symlink,
+    /// Files, or symlinks to files, that can be executed.
+    Executable,
+    /// Empty files, and directories with nothing in them.
+    Empty,
+}
+
+impl EntryType {
+    /// Parse a type as it's written in the config file or on the command line, like `fd` does:
+    /// `f`, `d`, `l`, `x`, or `e`, or their full names.
+    pub fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "f" | "file" => EntryType::File,
+            "d" | "directory" => EntryType::Directory,
+            "l" | "symlink" => EntryType::Symlink,
+            "x" | "executable" => EntryType::Executable,
+            "e" | "empty" => EntryType::Empty,
+            _ => return None,
+        })
+    }
+
+    // Whether an entry is of this type. Candidates of filter mode have no metadata, so they can
+    // only be told apart as files or directories
+    fn matches(self, path: &Path, metadata: Option<&fs::Metadata>, is_dir: bool) -> bool {
+        let Some(metadata) = metadata else {
+            return match self {
+                EntryType::File => !is_dir,
+                EntryType::Directory => is_dir,
+                _ => false,
+            };
+        };
+        match self {
+            EntryType::File => metadata.is_file(),
+            EntryType::Directory => metadata.is_dir(),
+            EntryType::Symlink => metadata.file_type().is_symlink(),
+            EntryType::Executable => is_executable(path),
+            EntryType::Empty if metadata.is_dir() => {
+                fs::read_dir(path).is_ok_and(|mut entries| entries.next().is_none())
+            }
+            EntryType::Empty => metadata.is_file() && metadata.len() == 0,
+        }
+    }
+}
+
+/// The order an '@' pattern's matches are put in before selectors pick from them, so `^1` means
+/// the same path everywhere. Matches that don't come from walking the filesystem, like
+/// executables in `$PATH` or recent selections, keep their own order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MatchOrder {
+    /// The order the filesystem lists directories' entries in, which varies between filesystems.
+    Walk,
+    /// By path, with each directory's contents right after it.
+    #[default]
+    Path,
+    /// By file name, then by path.
+    Name,
+    /// By path, with runs of digits compared as numbers, so `patch-2.diff` comes before
+    /// `patch-10.diff`.
+    Natural,
+    /// Newest first, then by path.
+    Modified,
+    /// Largest first, then by path.
+    Size,
+}
+
+impl MatchOrder {
+    /// Parse an order as it's written in the config file, on the command line, or in an `s:`
+    /// selector: `none`, `path`, `name`, `natural`, `mtime`, or `size`.
+    pub fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "none" => MatchOrder::Walk,
+            "path" => MatchOrder::Path,
+            "name" => MatchOrder::Name,
+            "natural" => MatchOrder::Natural,
+            "mtime" => MatchOrder::Modified,
+            "size" => MatchOrder::Size,
+            _ => return None,
+        })
+    }
+
+    // How matches are sorted after the walk, which is already in path order if `walk_sorted`
+    fn sort_key(self, walk_sorted: bool) -> Option<SortKey> {
+        match self {
+            MatchOrder::Walk => None,
+            MatchOrder::Path if walk_sorted => None,
+            MatchOrder::Path => Some(SortKey::Path),
+            MatchOrder::Name => Some(SortKey::Name),
+            MatchOrder::Natural => Some(SortKey::Natural),
+            MatchOrder::Modified => Some(SortKey::Modified),
+            MatchOrder::Size => Some(SortKey::Size),
+        }
+    }
+}
+
+impl fmt::Display for MatchOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MatchOrder::Walk => "none",
+            MatchOrder::Path => "path",
+            MatchOrder::Name => "name",
+            MatchOrder::Natural => "natural",
+            MatchOrder::Modified => "mtime",
+            MatchOrder::Size => "size",
+        })
+    }
+}
+
+/// Settings that only apply when a search's entry point is under a particular directory.
+#[derive(Debug, Clone, Default)]
+pub struct EntryPointRule {
+    /// The directory this rule applies under. `~` is expanded
+    pub path: PathBuf,
+    /// Search hidden files/directories, overriding [`Config::search_hidden`]
+    pub search_hidden: Option<bool>,
+    /// Names of directories to skip, such as `target` or `node_modules`
+    pub prune: Vec<String>,
+    /// A command that lists the entries under the entry point instead of walking it, like
+    /// `fd --hidden` or `git ls-files`. It's run in the entry point, and split on whitespace
+    pub backend: Option<String>,
+}
+
+/// Struct used for configuring an instance of Expander.
+///
+/// New options may be added in any release, so build it with [`Config::builder`] or from
+/// [`Config::default`], rather than by listing every field.
+#[non_exhaustive]
 pub struct Config {
     /// Do '@' patterns match with directories?
     pub match_with_dirs: bool,
     /// Do '@' patterns match with files?
     pub match_with_files: bool,
-    /// Transform files into their parent directories after selectors are applied
+    /// Only match entries of at least one of these types. If this is empty, every type matches
+    pub entry_types: Vec<EntryType>,
+    /// Only match entries with one of these extensions, like `rs` or `tar.gz`, ignoring case. If
+    /// this is empty, any extension matches
+    pub extensions: Vec<String>,
+    /// Only match entries owned by this user, given by name or ID. Ownership is only known on
+    /// Unix, so this is ignored elsewhere
+    pub owner: Option<String>,
+    /// Only match entries we can read, write, or execute, as asked. This is ignored on platforms
+    /// other than Unix
+    pub access: Access,
+    /// End matched directories with a separator, like `src/`. Some tools, like `rsync`, treat a
+    /// trailing separator differently, so this can be turned off
+    pub append_dir_slash: bool,
+    /// Replace symlinks with the paths they point to after selectors are applied, before any
+    /// other transform. This takes effect through the pipeline built by
+    /// [`Pipeline::from_config`]
+    pub resolve_symlinks: bool,
+    /// Transform files into their parent directories after selectors are applied. This takes
+    /// effect through the pipeline built by [`Pipeline::from_config`]
     pub transform_files_to_dirs: bool,
+    /// When transforming files into their parent directories, which parent to use for symlinks
+    pub symlink_parent: SymlinkParent,
+    /// When transforming files into their parent directories, canonicalize the resulting
+    /// directory
+    pub canonicalize_parent: bool,
+    /// How expanded paths are written. This takes effect through the pipeline built by
+    /// [`Pipeline::from_config`], after transforming files into their parent directories
+    pub path_style: PathStyle,
+    /// Which part of each expanded path is kept. Like `path_style`, this takes effect through the
+    /// pipeline built by [`Pipeline::from_config`], after the path style is applied
+    pub path_part: PathPart,
+    /// Extra transformations applied, in order, to each path after selectors are applied. These
+    /// run after the ones the settings above ask for, which are built in
+    pub post_transforms: Pipeline,
     /// Should we search hidden files/directories?
     pub search_hidden: bool,
+    /// The order matches are put in before selectors pick from them. Orders other than
+    /// [`MatchOrder::Walk`] and [`MatchOrder::Path`] need every match before any can be
+    /// selected, so they can't stop the walk early
+    pub sort: MatchOrder,
+    /// Directories that relative entry points are also searched from, in order, like `CDPATH`.
+    pub search_path: Vec<PathBuf>,
+    /// Resolve an '@' pattern in the first (program) position against the executables in
+    /// `$PATH`, rather than the filesystem, unless it has a modifier or entry point
+    pub resolve_program_from_path: bool,
+    /// Ask the user to choose with `selector_menu` when a pattern without selectors matches more
+    /// than one path. Otherwise, that's a [`LaxError::AmbiguousPattern`] error
+    pub use_menu: bool,
+    /// After choosing from the menu, print a one-line summary of the selection to stderr
+    pub echo_selection: bool,
+    /// Selectors used when the menu is answered with nothing, like by pressing Enter at its
+    /// prompt. Defaults to `1`, the first match. If this is `None`, the menu asks again
+    pub menu_default: Option<String>,
+    /// Selectors, like `1` or `a`, used for patterns that don't have any of their own, instead of
+    /// the menu
+    pub default_selectors: Option<String>,
+    /// Show at most this many matches in the menu at first. The rest are found on demand, if the
+    /// user asks for more
+    pub max_results: Option<usize>,
+    /// Stop searching once a pattern with selectors matches more than this many paths, and ask
+    /// whether to go on with [`SelectionMenu::confirm_many`], so a sloppy pattern doesn't become
+    /// a giant command line. Without the menu, that's a [`LaxError::TooManyMatches`] error.
+    /// Defaults to [`DEFAULT_MAX_MATCHES`]
+    pub max_matches: Option<usize>,
+    /// Give up on a pattern once its search has taken longer than this, with a
+    /// [`LaxError::TimedOut`] error listing what it found so far. Time spent in the menu doesn't
+    /// count. A backend still running then is killed, an index still being built isn't saved,
+    /// and the daemon isn't waited on any longer
+    pub timeout: Option<Duration>,
+    /// Reuse the selection made for a pattern when the identical pattern appears again, instead
+    /// of prompting the user a second time
+    pub reuse_selections: bool,
+    /// An argument after which nothing is expanded, so later arguments starting with '@' are
+    /// passed on as they are. The marker itself is dropped. Defaults to [`LITERAL_MARKER`]. If
+    /// this is `None`, every argument is expanded
+    pub literal_marker: Option<String>,
+    /// Settings for searches whose entry point is under particular directories. Every matching
+    /// rule applies, in order
+    pub entry_point_rules: Vec<EntryPointRule>,
+    /// Where patterns without an entry point, like `@foo`, search from instead of the current
+    /// directory
+    pub default_entry_point: Option<PathBuf>,
+    /// Files or directories that mark the root `@%` searches from. The closest directory above
+    /// the current one that contains any of them is the root. Defaults to
+    /// [`REPOSITORY_MARKERS`], but could also be project files, like `Cargo.toml`
+    pub root_markers: Vec<String>,
+    /// Named directories that can be used as the start of a pattern, as in `@:name`
+    pub bookmarks: HashMap<String, PathBuf>,
+    /// File to record expanded paths to, so they can be reused with '@!'. History is disabled if
+    /// this is `None`
+    pub history_file: Option<PathBuf>,
+    /// Database of how frequently and recently paths were selected, used to rank the menu's
+    /// candidates. Ranking is disabled if this is `None`
+    pub frecency_file: Option<PathBuf>,
+    /// Directory to keep an index of each searched entry point in, so huge trees aren't walked
+    /// again while nothing in them changes. Indexing is disabled if this is `None`
+    pub cache_dir: Option<PathBuf>,
+    /// Rebuild the index of each entry point searched, even if it looks up to date
+    pub refresh_cache: bool,
+    /// Directory to write temporary files to, like the list of matches `--edit` opens in
+    /// `$EDITOR`. The system's temporary directory is used if this is `None`
+    pub temp_dir: Option<PathBuf>,
+    /// Trees the daemon keeps indexed in memory. While it's running, searches under them are
+    /// answered by it, rather than walked or read from `cache_dir`
+    pub daemon_roots: Vec<PathBuf>,
+    /// Paths to match patterns against instead of the filesystem, which isn't read at all. A
+    /// pattern's entry point picks which of them are searched, and relative candidates are
+    /// relative to the current directory. Matches have no metadata, and are only directories if
+    /// they end with a separator or hold other candidates
+    pub candidates: Option<Vec<String>>,
 }
 
 impl Default for Config {
@@ -444,100 +1894,1420 @@ impl Default for Config {
         Config {
             match_with_dirs: true,
             match_with_files: true,
+            entry_types: Vec::new(),
+            extensions: Vec::new(),
+            owner: None,
+            access: Access::default(),
+            append_dir_slash: true,
+            resolve_symlinks: false,
             transform_files_to_dirs: false,
+            symlink_parent: SymlinkParent::Link,
+            canonicalize_parent: false,
+            path_style: PathStyle::AsMatched,
+            path_part: PathPart::Whole,
+            post_transforms: Pipeline::new(),
             search_hidden: false,
+            sort: MatchOrder::Path,
+            search_path: Vec::new(),
+            resolve_program_from_path: false,
+            use_menu: true,
+            echo_selection: false,
+            menu_default: Some("1".into()),
+            default_selectors: None,
+            max_results: None,
+            max_matches: Some(DEFAULT_MAX_MATCHES),
+            timeout: None,
+            reuse_selections: true,
+            literal_marker: Some(LITERAL_MARKER.into()),
+            entry_point_rules: Vec::new(),
+            default_entry_point: None,
+            root_markers: REPOSITORY_MARKERS
+                .iter()
+                .map(|marker| marker.to_string())
+                .collect(),
+            bookmarks: HashMap::new(),
+            history_file: None,
+            frecency_file: None,
+            cache_dir: None,
+            refresh_cache: false,
+            temp_dir: None,
+            daemon_roots: Vec::new(),
+            candidates: None,
         }
     }
 }
 
-fn get_repository_root() -> Result<PathBuf> {
-    let mut cwd = env::current_dir()?;
-    while !cwd.join(".git").exists() && !cwd.join(".svn").exists() {
-        cwd = match cwd.parent() {
-            Some(parent) => parent.into(),
-            None => {
-                return Err(anyhow!(
-                    "Cannot get repository root - this is not a git/svnc repo"
-                ));
+impl Config {
+    /// Start building a config from the defaults.
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+}
+
+/// Builds a [`Config`] with chained setters, starting from the defaults.
+#[derive(Default)]
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+// A chained setter for each of the config's fields
+macro_rules! setters {
+    ($($field:ident: $type:ty),* $(,)?) => {
+        $(
+            #[doc = concat!("Set [`Config::", stringify!($field), "`].")]
+            pub fn $field(mut self, $field: $type) -> Self {
+                self.config.$field = $field;
+                self
             }
+        )*
+    };
+}
+
+impl ConfigBuilder {
+    setters! {
+        match_with_dirs: bool,
+        match_with_files: bool,
+        entry_types: Vec<EntryType>,
+        extensions: Vec<String>,
+        owner: Option<String>,
+        access: Access,
+        append_dir_slash: bool,
+        resolve_symlinks: bool,
+        transform_files_to_dirs: bool,
+        symlink_parent: SymlinkParent,
+        canonicalize_parent: bool,
+        path_style: PathStyle,
+        path_part: PathPart,
+        post_transforms: Pipeline,
+        search_hidden: bool,
+        sort: MatchOrder,
+        search_path: Vec<PathBuf>,
+        resolve_program_from_path: bool,
+        use_menu: bool,
+        echo_selection: bool,
+        menu_default: Option<String>,
+        default_selectors: Option<String>,
+        max_results: Option<usize>,
+        max_matches: Option<usize>,
+        timeout: Option<Duration>,
+        reuse_selections: bool,
+        literal_marker: Option<String>,
+        entry_point_rules: Vec<EntryPointRule>,
+        default_entry_point: Option<PathBuf>,
+        root_markers: Vec<String>,
+        bookmarks: HashMap<String, PathBuf>,
+        history_file: Option<PathBuf>,
+        frecency_file: Option<PathBuf>,
+        cache_dir: Option<PathBuf>,
+        refresh_cache: bool,
+        temp_dir: Option<PathBuf>,
+        daemon_roots: Vec<PathBuf>,
+        candidates: Option<Vec<String>>,
+    }
+
+    /// Finish building the config.
+    pub fn build(self) -> Config {
+        self.config
+    }
+}
+
+// Summarize a selection in one line, e.g. "selected 3 paths: a, b, … (+1 more)"
+fn selection_summary(paths: &[String]) -> String {
+    const SHOWN: usize = 2;
+    let noun = if paths.len() == 1 { "path" } else { "paths" };
+    let mut summary = format!("selected {} {}: ", paths.len(), noun);
+    summary.push_str(&paths[..paths.len().min(SHOWN)].join(", "));
+    if paths.len() > SHOWN {
+        summary.push_str(&format!(", … (+{} more)", paths.len() - SHOWN));
+    }
+    summary
+}
+
+// Expand a leading '~' to the home directory
+#[cfg(feature = "tilde")]
+pub(crate) fn expand_tilde(path: &str) -> Cow<'_, str> {
+    shellexpand::tilde(path)
+}
+
+#[cfg(not(feature = "tilde"))]
+pub(crate) fn expand_tilde(path: &str) -> Cow<'_, str> {
+    Cow::Borrowed(path)
+}
+
+/// Is an argument an '@' pattern? Arguments starting with "@@" are a literal '@' instead.
+pub fn is_pattern(arg: &str) -> bool {
+    arg.starts_with('@') && !arg.starts_with("@@")
+}
+
+// The byte range of the last `components` components of a path, without its trailing separator
+fn last_components(path: &str, components: usize) -> Range<usize> {
+    let separators = ['/', std::path::MAIN_SEPARATOR];
+    let end = path.trim_end_matches(separators).len();
+    let mut start = end;
+    for _ in 0..components.max(1) {
+        let Some(separator) = path[..start].rfind(separators) else {
+            return 0..end;
+        };
+        start = separator;
+    }
+    start + 1..end
+}
+
+fn apply_modifiers(mut paths: Vec<String>, modifiers: &[FilenameModifier]) -> Result<Vec<String>> {
+    for modifier in modifiers {
+        paths = paths
+            .into_iter()
+            .map(|path| modifier.apply(path))
+            .collect::<Result<_>>()?;
+    }
+    Ok(paths)
+}
+
+// Whether the next of a pattern's alternatives should be tried after this error. Only an
+// alternative that matched nothing, or can't be searched, falls through. Anything else, like an
+// invalid selector or an unknown bookmark, is a mistake the user should hear about
+fn falls_through(err: &LaxError) -> bool {
+    matches!(
+        err,
+        LaxError::NoMatches(..) | LaxError::EntryPointMissing(_)
+    )
+}
+
+// How many paths are looked at for suggestions when a pattern matches nothing, and how many
+// suggestions are made
+const SUGGESTION_SCAN_LIMIT: usize = 5000;
+const MAX_SUGGESTIONS: usize = 3;
+
+// The number of edits (insertions, deletions, or substitutions of a character) needed to turn one
+// string into another
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
         }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path)
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|metadata| metadata.is_file())
+}
+
+// Ask zoxide which directory best matches a query
+fn zoxide_query(query: &str) -> Result<PathBuf> {
+    let output = Command::new("zoxide")
+        .args(["query", "--", query])
+        .output()
+        .map_err(|err| LaxError::Zoxide(format!("Could not run zoxide: {err}")))?;
+    if !output.status.success() {
+        return Err(LaxError::Zoxide(format!(
+            "zoxide found no directory matching '{query}'"
+        )));
     }
+    let directory = String::from_utf8(output.stdout)
+        .map_err(|err| LaxError::Zoxide(format!("zoxide printed an invalid path: {err}")))?;
+    Ok(PathBuf::from(directory.trim_end_matches(['\n', '\r'])))
+}
 
-    Ok(cwd)
+fn get_repository_root(markers: &[String], search: RootSearch) -> Result<PathBuf> {
+    find_root(&env::current_dir()?, markers, search)
+        .ok_or_else(|| LaxError::RepositoryRootNotFound(markers.to_vec()))
+}
+
+/// What marks the root of a repository for each version control system `@%` knows about by
+/// default: git, Subversion, Mercurial, Jujutsu, Fossil (the checkout file is `_FOSSIL_` on
+/// Windows), and Bazaar. See [`Config::root_markers`].
+pub const REPOSITORY_MARKERS: &[&str] = &[
+    ".git",
+    ".svn",
+    ".hg",
+    ".jj",
+    ".fslckout",
+    "_FOSSIL_",
+    ".bzr",
+];
+
+/// The default for [`Config::max_matches`]. Command lines this long are rarely intended, and
+/// not much longer ones start running into the system's limit on argument length.
+pub const DEFAULT_MAX_MATCHES: usize = 10_000;
+
+/// The default for [`Config::literal_marker`]. Unlike `--`, which lax's own flags stop at, this
+/// is meant to come after the program's name.
+pub const LITERAL_MARKER: &str = "--no-expand";
+
+// Find the closest (or furthest) directory, starting from `start` and going up, that contains any
+// of `markers`
+fn find_root(start: &Path, markers: &[impl AsRef<Path>], search: RootSearch) -> Option<PathBuf> {
+    let mut roots = start
+        .ancestors()
+        .filter(|directory| markers.iter().any(|marker| directory.join(marker).exists()));
+    match search {
+        RootSearch::Nearest => roots.next(),
+        RootSearch::Outermost => roots.last(),
+    }
+    .map(Path::to_path_buf)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::walk::has_extension;
+
+    // A menu that answers with whatever selector string a function gives
+    struct Answer(fn(&[Candidate], u32, bool) -> String);
+
+    impl SelectionMenu for Answer {
+        fn select(
+            &mut self,
+            candidates: &[Candidate],
+            attempt: u32,
+            more: bool,
+        ) -> Result<MenuOutcome> {
+            Ok(MenuOutcome::Selectors((self.0)(candidates, attempt, more)))
+        }
+    }
 
     fn setup() -> Expander {
         Expander {
             config: Config::default(),
-            selector_menu: |_, _| panic!("Oh god a choice!"),
+            selector_menu: Box::new(Answer(|_, _, _| panic!("Oh god a choice!"))),
         }
     }
 
+    #[test]
+    fn builders() {
+        let config = Config::builder()
+            .max_results(Some(5))
+            .search_hidden(true)
+            .build();
+        assert_eq!(config.max_results, Some(5));
+        assert!(config.search_hidden);
+        assert!(config.use_menu);
+
+        let mut exp = Expander::builder().config(config).build();
+        assert!(!exp.config.use_menu);
+        let arguments = vec!["@fo[ox]".to_string()];
+        assert!(matches!(
+            exp.expand_arguments(&arguments).unwrap_err(),
+            LaxError::AmbiguousPattern(..)
+        ));
+
+        let mut exp = Expander::builder()
+            .selector_menu(|_: &[Candidate], _, _| Ok(MenuOutcome::Selectors("1".into())))
+            .build();
+        assert_eq!(exp.expand_arguments(&arguments).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn repository_root() {
+        let root = env::temp_dir().join(format!("lax_repository_root_{}", std::process::id()));
+        let nested = root.join("a/b");
+        fs::create_dir_all(&nested).unwrap();
+        assert_eq!(find_root(&nested, &[".hg"], RootSearch::Nearest), None);
+        assert_eq!(find_root(&nested, &[".hg"], RootSearch::Outermost), None);
+
+        fs::create_dir(root.join(".hg")).unwrap();
+        fs::write(root.join("a/.fslckout"), "").unwrap();
+        assert_eq!(
+            find_root(&nested, REPOSITORY_MARKERS, RootSearch::Nearest),
+            Some(root.join("a"))
+        );
+        assert_eq!(
+            find_root(&nested, REPOSITORY_MARKERS, RootSearch::Outermost),
+            Some(root.clone())
+        );
+        assert_eq!(
+            find_root(&nested, &[".hg"], RootSearch::Nearest),
+            Some(root.clone())
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+
+        // The error says what was looked for, which is up to the config
+        let mut exp = setup();
+        exp.config.root_markers = vec!["no_such_marker".into(), "Nope.toml".into()];
+        let err = exp.expand_arguments(&["@%foo".to_string()]).unwrap_err();
+        assert!(matches!(&err, LaxError::RepositoryRootNotFound(_)));
+        assert!(err
+            .to_string()
+            .ends_with("any of: no_such_marker, Nope.toml"));
+    }
+
     #[test]
     fn basic() {
+        let mut exp = setup();
+
+        let arguments = vec!["@foo".to_string()];
+        let expanded = exp.expand_arguments(&arguments).unwrap();
+        assert_eq!(expanded, vec!["./tests/foobar/foo"]);
+    }
+
+    // Expansion never changes the working directory, even when it fails or the menu panics, so
+    // later patterns and the command itself still run where the user is
+    #[test]
+    fn working_directory_untouched() {
+        let cwd = env::current_dir().unwrap();
+        let mut exp = setup();
+        for pattern in [
+            "@tests/**/foo^9",
+            "@no_such_directory/**/foo",
+            "@tests/**/foo",
+        ] {
+            let _ = exp.expand_arguments(&[pattern.to_string()]);
+            assert_eq!(env::current_dir().unwrap(), cwd);
+        }
+        let menu = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            exp.expand_arguments(&["@fo[ox]".to_string()])
+        }));
+        assert!(menu.is_err());
+        assert_eq!(env::current_dir().unwrap(), cwd);
+    }
+
+    // Nothing after the literal marker is expanded, and the marker itself is dropped
+    #[test]
+    fn literal_marker() {
+        let mut exp = setup();
+        let arguments: Vec<String> = ["grep", "@foo", "--no-expand", "--", "@foo", "--no-expand"]
+            .map(String::from)
+            .into();
+        assert_eq!(
+            exp.expand_arguments(&arguments).unwrap(),
+            ["grep", "./tests/foobar/foo", "--", "@foo", "--no-expand"]
+        );
+        let expansion = exp.expand_arguments_lossy(&arguments);
+        assert_eq!(
+            expansion.args,
+            ["grep", "./tests/foobar/foo", "--", "@foo", "--no-expand"]
+        );
+        assert_eq!(expansion.results.len(), arguments.len());
+        assert!(expansion.results[2].as_ref().unwrap().is_empty());
+
+        exp.config.literal_marker = None;
+        assert_eq!(
+            exp.expand_arguments(&arguments[1..3]).unwrap(),
+            ["./tests/foobar/foo", "--no-expand"]
+        );
+    }
+
+    // A failing pattern doesn't stop the rest from being expanded
+    #[test]
+    fn lossy_expansion() {
+        let mut exp = setup();
+        let arguments: Vec<String> = ["echo", "@foo", "@no_such_file", "@fo[ox]^9"]
+            .map(String::from)
+            .into();
+        let expansion = exp.expand_arguments_lossy(&arguments);
+        assert_eq!(
+            expansion.args,
+            ["echo", "./tests/foobar/foo", "@no_such_file", "@fo[ox]^9"]
+        );
+        assert_eq!(expansion.results.len(), 4);
+        assert_eq!(expansion.results[0].as_ref().unwrap(), &["echo"]);
+        assert_eq!(
+            expansion.results[1].as_ref().unwrap(),
+            &["./tests/foobar/foo"]
+        );
+        assert!(matches!(
+            expansion.results[2].as_ref().unwrap_err(),
+            LaxError::NoMatches(..)
+        ));
+        assert!(matches!(
+            expansion.results[3].as_ref().unwrap_err(),
+            LaxError::SelectorOutOfRange(_)
+        ));
+    }
+
+    #[test]
+    fn selector_parsing() {
+        assert_eq!(
+            Expander::parse_selectors("1,1,l,a,1,33").unwrap().selectors,
+            vec![
+                Selector::FromFront(0),
+                Selector::FromFront(0),
+                Selector::FromBack(0),
+                Selector::All,
+                Selector::FromFront(0),
+                Selector::FromFront(32),
+            ]
+        );
+        assert_eq!(
+            Expander::parse_selectors("a,!2,2..-1").unwrap().selectors,
+            vec![
+                Selector::All,
+                Selector::Exclude(Box::new(Selector::FromFront(1))),
+                Selector::Range(
+                    Box::new(Selector::FromFront(1)),
+                    Box::new(Selector::FromBack(0))
+                ),
+            ]
+        );
+        assert_eq!(
+            Expander::parse_selectors("n").unwrap().selectors,
+            vec![Selector::Newest]
+        );
+        let selector_group = Expander::parse_selectors("s:natural,1,D").unwrap();
+        assert_eq!(selector_group.selectors, vec![Selector::FromFront(0)]);
+        assert_eq!(selector_group.sort, Some(MatchOrder::Natural));
+        assert_eq!(selector_group.to_string(), "1,s:natural,D");
+        assert!(Expander::parse_selectors("s:random").is_err());
+        assert!(Expander::parse_selectors("!!1").is_err());
+        assert!(Expander::parse_selectors("1..").is_err());
+        assert!(matches!(
+            Expander::parse_selectors("1,/[a").unwrap_err(),
+            LaxError::InvalidSelector(_)
+        ));
+    }
+
+    // Patterns that match nothing suggest similar paths that are there
+    #[test]
+    fn suggestions() {
+        let mut exp = setup();
+        let arguments = vec!["@tests/**/foz^1".to_string()];
+        assert!(matches!(
+            exp.expand_arguments(&arguments).unwrap_err(),
+            LaxError::NoMatches(_, suggestions)
+                if suggestions == ["@tests/**/foo^1", "@tests/**/fox^1"]
+        ));
+        let arguments = vec!["@tests/**/nothing_like_it".to_string()];
+        assert!(matches!(
+            exp.expand_arguments(&arguments).unwrap_err(),
+            LaxError::NoMatches(_, suggestions) if suggestions.is_empty()
+        ));
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    // Parse errors point at the part of the pattern that's at fault
+    #[test]
+    fn error_spans() {
+        let message = |pattern: &str| Expander::parse_pattern(pattern).unwrap_err().to_string();
+        assert_eq!(message("@!x"), "Invalid history index: 'x'\n\t@!x\n\t  ^");
+        assert_eq!(
+            message("@$LAX_NO_SUCH_VARIABLE/**/foo"),
+            "Environment variable 'LAX_NO_SUCH_VARIABLE' is not set\n\t\
+             @$LAX_NO_SUCH_VARIABLE/**/foo\n\t \
+             ^^^^^^^^^^^^^^^^^^^^^"
+        );
+        assert_eq!(
+            message("@src/**/[ab.rs"),
+            "Invalid glob pattern: unclosed character class; missing ']'\n\t\
+             @src/**/[ab.rs\n\t        ^^^^^^"
+        );
+        assert!(message("@*.rs!{a,b").ends_with("\n\t@*.rs!{a,b\n\t      ^^^^"));
+        assert!(message("@src/**/[z-a]").ends_with("\n\t@src/**/[z-a]\n\t         ^^^"));
+
+        let mut exp = setup();
+        let arguments = vec!["@src/**/*.rs^1,x".to_string()];
+        assert_eq!(
+            exp.expand_arguments(&arguments).unwrap_err().to_string(),
+            "Invalid selector: 'x'\n\t@src/**/*.rs^1,x\n\t               ^"
+        );
+        let arguments = vec!["@src/**/*.rs^1..".to_string()];
+        assert_eq!(
+            exp.expand_arguments(&arguments).unwrap_err().to_string(),
+            "Invalid range: '1..'. Expected a number at each end, as in 1..3\n\t\
+             @src/**/*.rs^1..\n\t             ^^^"
+        );
+        #[cfg(feature = "regex")]
+        {
+            let arguments = vec!["@src/**/*.rs^/[a".to_string()];
+            assert_eq!(
+                exp.expand_arguments(&arguments).unwrap_err().to_string(),
+                "Invalid regex selector: unclosed character class\n\t\
+                 @src/**/*.rs^/[a\n\t             ^^^"
+            );
+        }
+    }
+
+    #[test]
+    fn selecting() {
+        let paths: Vec<Match> = ["a", "b", "c", "d", "e"]
+            .map(|path| Match::stat(path.into()))
+            .into();
+        let select = |selectors| {
+            Expander::parse_selectors(selectors)
+                .unwrap()
+                .select(&paths)
+                .unwrap()
+                .join("")
+        };
+        assert_eq!(select("1,-1"), "ae");
+        assert_eq!(select("2..4"), "bcd");
+        assert_eq!(select("-1..-3"), "edc");
+        assert_eq!(select("a,!2,!5"), "acd");
+        assert_eq!(select("!1..3"), "de");
+        #[cfg(feature = "regex")]
+        assert_eq!(select("/[a-c],!/b"), "ac");
+        assert!(Expander::parse_selectors("!a")
+            .unwrap()
+            .select(&paths)
+            .is_err());
+        assert!(Expander::parse_selectors("1..9")
+            .unwrap()
+            .select(&paths)
+            .is_err());
+    }
+
+    #[test]
+    fn parent_selector() {
+        let mut exp = setup();
+        let arguments = vec!["@fo[ox]^a,D".to_string()];
+        let expanded = exp.expand_arguments(&arguments).unwrap();
+        assert_eq!(expanded, vec!["./tests/foobar"]);
+
+        // Without other selectors, the menu still picks the paths
+        exp.selector_menu = Box::new(Answer(|_, _, _| "1".into()));
+        let arguments = vec!["@fo[ox]^D".to_string()];
+        let expanded = exp.expand_arguments(&arguments).unwrap();
+        assert_eq!(expanded, vec!["./tests/foobar"]);
+    }
+
+    #[test]
+    fn probing() {
         let exp = setup();
+        assert_eq!(
+            exp.probe("@foo").unwrap(),
+            Probe::Unambiguous(vec!["./tests/foobar/foo".into()])
+        );
+        assert!(matches!(
+            exp.probe("@fo[ox]").unwrap(),
+            Probe::Ambiguous(paths) if paths.len() == 2
+        ));
+        #[cfg(feature = "regex")]
+        assert_eq!(
+            exp.probe("@fo[ox]^/fox").unwrap(),
+            Probe::Unambiguous(vec!["./tests/foobar/fox".into()])
+        );
+        assert_eq!(exp.probe("@fo[ox]^9").unwrap(), Probe::Unmatched);
+        assert_eq!(exp.probe("@no_such_file").unwrap(), Probe::Unmatched);
+        assert!(exp.probe("@foo^x").is_err());
+    }
+
+    #[test]
+    fn lazy_matches() {
+        let exp = setup();
+        let mut matches: Vec<String> = exp
+            .matches("@fo[ox]")
+            .map(|matched| matched.unwrap().to_path_string())
+            .collect();
+        matches.sort();
+        assert_eq!(matches, vec!["./tests/foobar/foo", "./tests/foobar/fox"]);
+        assert_eq!(exp.matches("@fo[ox]^1").take(1).count(), 1);
+        assert_eq!(exp.matches("@no_such_file|fox").count(), 1);
+        assert_eq!(exp.matches("@no_such_file").count(), 0);
+
+        let mut matches = exp.matches("@no_such_directory/**/foo");
+        assert!(matches!(
+            matches.next().unwrap().unwrap_err(),
+            LaxError::EntryPointMissing(_)
+        ));
+        assert!(matches.next().is_none());
+    }
+
+    #[test]
+    fn natural_order() {
+        let mut names = vec![
+            "patch-10.diff",
+            "patch-2.diff",
+            "patch-02.diff",
+            "patch-1.diff",
+            "patch.diff",
+            "v1.10/a",
+            "v1.9/b",
+        ];
+        names.sort_by(|a, b| natural_path_cmp(Path::new(a), Path::new(b)));
+        assert_eq!(
+            names,
+            [
+                "patch-1.diff",
+                "patch-2.diff",
+                "patch-02.diff",
+                "patch-10.diff",
+                "patch.diff",
+                "v1.9/b",
+                "v1.10/a",
+            ]
+        );
+        assert_eq!(natural_cmp("a1b", "a1b"), Ordering::Equal);
+        assert_eq!(natural_cmp("a", "a1"), Ordering::Less);
+
+        let root = env::temp_dir().join(format!("lax_natural_order_{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        for name in ["patch-10.diff", "patch-2.diff", "patch-1.diff"] {
+            fs::write(root.join(name), "").unwrap();
+        }
+        let mut exp = setup();
+        let pattern = format!("@{}/**/patch-*^s:natural,2", root.display());
+        let expanded = exp.expand_arguments(&[pattern]).unwrap();
+        assert_eq!(expanded, [root.join("patch-2.diff").to_string_lossy()]);
+
+        exp.config.sort = MatchOrder::Natural;
+        let pattern = format!("@{}/**/patch-*^-1", root.display());
+        let expanded = exp.expand_arguments(&[pattern]).unwrap();
+        assert_eq!(expanded, [root.join("patch-10.diff").to_string_lossy()]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn sort_keys() {
+        let paths: Vec<Match> = ["./src/lib.rs", "./Cargo.toml", "./src/"]
+            .map(|path| Match::stat(path.into()))
+            .into();
+        let mut order = vec![0, 1, 2];
+        SortKey::parse("name").unwrap().sort(&paths, &mut order);
+        assert_eq!(order, vec![1, 0, 2]);
+        SortKey::parse("depth").unwrap().sort(&paths, &mut order);
+        assert_eq!(order, vec![1, 2, 0]);
+        assert!(SortKey::parse("colour").is_none());
+    }
+
+    // Re-sorting during a menu session uses the metadata found while matching
+    #[test]
+    fn sort_metadata_cached() {
+        let directory = env::temp_dir().join(format!("lax_sort_cache_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let names: Vec<String> = ["small", "large"]
+            .map(|name| directory.join(name).to_string_lossy().into_owned())
+            .into();
+        fs::write(&names[0], "a").unwrap();
+        fs::write(&names[1], "abc").unwrap();
+
+        let paths: Vec<Match> = names.iter().cloned().map(Match::stat).collect();
+        let mut order = vec![0, 1];
+        SortKey::Size.sort(&paths, &mut order);
+        assert_eq!(order, vec![1, 0]);
+
+        fs::write(&names[0], "abcdef").unwrap();
+        SortKey::Size.sort(&paths, &mut order);
+        assert_eq!(order, vec![1, 0]);
+        let fresh: Vec<Match> = names.iter().cloned().map(Match::stat).collect();
+        SortKey::Size.sort(&fresh, &mut order);
+        assert_eq!(order, vec![0, 1]);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn menu_sorting() {
+        let mut exp = setup();
+        exp.selector_menu = Box::new(Answer(|candidates, attempt, _| {
+            if attempt == 0 && candidates[0].matched.path.ends_with("fox") {
+                "s name".into()
+            } else {
+                // Re-sorting doesn't change which number means which path
+                candidates[0].id.to_string()
+            }
+        }));
+        let arguments = vec!["@fo[ox]".to_string()];
+        let expanded = exp.expand_arguments(&arguments).unwrap();
+        assert_eq!(expanded, vec!["./tests/foobar/foo"]);
+    }
+
+    // The menu sees the metadata the walk found
+    #[test]
+    fn match_metadata() {
+        let mut exp = setup();
+        exp.selector_menu = Box::new(|candidates: &[Candidate], _, _| {
+            let matched = &candidates[0].matched;
+            assert!(!matched.is_dir);
+            assert_eq!(matched.depth, 3);
+            assert_eq!(matched.size, 0);
+            assert!(matched.modified.is_some());
+            let path = matched.path.to_str().unwrap();
+            assert_eq!(
+                &path[candidates[0].matched_part.clone()],
+                "tests/foobar/foo"
+            );
+            Ok(MenuOutcome::Selectors("1".into()))
+        });
+        let arguments = vec!["@tests/foobar/fo[ox]".to_string()];
+        exp.expand_arguments(&arguments).unwrap();
+    }
+
+    #[test]
+    fn menu_outcomes() {
+        let mut exp = setup();
+        let arguments = vec!["@fo[ox]".to_string()];
+        exp.selector_menu =
+            Box::new(|_: &[Candidate], _, _| Ok(MenuOutcome::Paths(vec!["fox".into()])));
+        assert_eq!(exp.expand_arguments(&arguments).unwrap(), vec!["fox"]);
+
+        // A closure can script several answers
+        let mut answers = vec![MenuOutcome::Selectors("9".into()), MenuOutcome::Cancelled];
+        exp.selector_menu = Box::new(move |_: &[Candidate], _, _| Ok(answers.remove(0)));
+        let err = exp.expand_arguments(&arguments).unwrap_err();
+        assert!(matches!(err, LaxError::Cancelled));
+
+        // Answering with nothing picks the default, or asks again without one
+        exp.selector_menu =
+            Box::new(|_: &[Candidate], _, _| Ok(MenuOutcome::Selectors("\n".into())));
+        assert_eq!(
+            exp.expand_arguments(&arguments).unwrap(),
+            vec!["./tests/foobar/foo"]
+        );
+        exp.config.menu_default = None;
+        let mut answers = vec![MenuOutcome::Selectors("".into()), MenuOutcome::Cancelled];
+        exp.selector_menu = Box::new(move |_: &[Candidate], _, _| Ok(answers.remove(0)));
+        let err = exp.expand_arguments(&arguments).unwrap_err();
+        assert!(matches!(err, LaxError::Cancelled));
+    }
+
+    // `e GLOB` searches again with another glob, keeping the rest of the pattern
+    #[test]
+    fn refine() {
+        let mut exp = setup();
+        let mut answers = vec!["e f*", "e fox"];
+        exp.selector_menu = Box::new(move |candidates: &[Candidate], _, _| {
+            assert!(candidates.len() > 1);
+            Ok(MenuOutcome::Selectors(answers.remove(0).into()))
+        });
+        let arguments = vec!["@fo[ox]:t".to_string()];
+        assert_eq!(exp.expand_arguments(&arguments).unwrap(), vec!["fox"]);
+    }
+
+    #[test]
+    fn max_results() {
+        let mut exp = setup();
+        exp.config.max_results = Some(1);
+        exp.selector_menu = Box::new(Answer(|candidates, _, more| {
+            assert_eq!(candidates.len(), if more { 1 } else { 2 });
+            if more {
+                "m".into()
+            } else {
+                "2".into()
+            }
+        }));
+        let arguments = vec!["@fo[ox]".to_string()];
+        let expanded = exp.expand_arguments(&arguments).unwrap();
+        assert_eq!(expanded, vec!["./tests/foobar/fox"]);
+
+        // Selectors still see every match
+        let arguments = vec!["@fo[ox]^a".to_string()];
+        assert_eq!(exp.expand_arguments(&arguments).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn no_menu() {
+        let mut exp = setup();
+        exp.config.use_menu = false;
+        let arguments = vec!["@fo[ox]".to_string()];
+        let err = exp.expand_arguments(&arguments).unwrap_err();
+        assert!(matches!(
+            err,
+            LaxError::AmbiguousPattern(pattern, candidates)
+                if pattern == "@fo[ox]" && candidates.len() == 2
+        ));
+
+        // Unambiguous patterns don't need the menu
+        let arguments = vec!["@foo".to_string()];
+        assert!(exp.expand_arguments(&arguments).is_ok());
+    }
+
+    #[test]
+    fn entry_point_rules() {
+        let mut exp = setup();
+        exp.config.entry_point_rules = vec![
+            EntryPointRule {
+                path: "tests".into(),
+                prune: vec!["foobar".into()],
+                ..Default::default()
+            },
+            EntryPointRule {
+                path: ".".into(),
+                search_hidden: Some(true),
+                ..Default::default()
+            },
+        ];
+        let arguments = vec!["@tests/**/foo".to_string()];
+        assert!(exp.expand_arguments(&arguments).is_err());
+
+        // Rules only apply to searches from under their directory
+        let arguments = vec!["@foo".to_string()];
+        assert_eq!(
+            exp.expand_arguments(&arguments).unwrap(),
+            vec!["./tests/foobar/foo"]
+        );
+        let arguments = vec!["@rust.yml".to_string()];
+        assert_eq!(
+            exp.expand_arguments(&arguments).unwrap(),
+            vec!["./.github/workflows/rust.yml"]
+        );
+    }
+
+    #[test]
+    fn default_selectors() {
+        let mut exp = setup();
+        exp.config.default_selectors = Some("a".into());
+        let arguments = vec!["@fo[ox]".to_string()];
+        assert_eq!(exp.expand_arguments(&arguments).unwrap().len(), 2);
+
+        // A pattern's own selectors take precedence
+        let arguments = vec!["@fo[ox]^-1".to_string()];
+        assert_eq!(
+            exp.expand_arguments(&arguments).unwrap(),
+            vec!["./tests/foobar/fox"]
+        );
+        let arguments = vec!["@fo[ox]^D".to_string()];
+        assert_eq!(
+            exp.expand_arguments(&arguments).unwrap(),
+            vec!["./tests/foobar"]
+        );
+
+        // Fall back to the menu if the defaults don't select anything
+        #[cfg(feature = "regex")]
+        {
+            exp.config.default_selectors = Some("/nothingmatchesthis".into());
+            exp.selector_menu = Box::new(Answer(|_, _, _| "/foo$".into()));
+            let arguments = vec!["@fo[ox]".to_string()];
+            assert_eq!(
+                exp.expand_arguments(&arguments).unwrap(),
+                vec!["./tests/foobar/foo"]
+            );
+        }
+    }
 
-        let arguments = vec!["@foo".to_string()];
+    #[test]
+    fn explicitly_hidden() {
+        let mut exp = setup();
+        let arguments = vec!["@.github/workflows/rust.yml".to_string()];
         let expanded = exp.expand_arguments(&arguments).unwrap();
-        assert_eq!(expanded, vec!["./tests/foobar/foo"]);
+        assert_eq!(expanded, vec!["./.github/workflows/rust.yml"]);
+
+        // Generic globs still skip hidden entries
+        let arguments = vec!["@rust.yml".to_string()];
+        assert!(exp.expand_arguments(&arguments).is_err());
     }
 
     #[test]
-    fn selector_parsing() {
+    fn summarize_selection() {
+        let paths: Vec<String> = ["a", "b", "c", "d"].map(String::from).into();
+        assert_eq!(selection_summary(&paths[..1]), "selected 1 path: a");
+        assert_eq!(selection_summary(&paths[..2]), "selected 2 paths: a, b");
         assert_eq!(
-            Expander::parse_selectors("1,1,l,a,1,33").unwrap().selectors,
-            vec![
-                Selector::FromFront(0),
-                Selector::FromFront(0),
-                Selector::FromBack(0),
-                Selector::All,
-                Selector::FromFront(0),
-                Selector::FromFront(32),
-            ]
+            selection_summary(&paths),
+            "selected 4 paths: a, b, … (+2 more)"
         );
     }
 
     #[test]
     fn pattern_parsing() {
         let res = Expander::parse_pattern("@fish").unwrap();
-        assert_eq!(res, (false, ".", "fish", None));
+        assert_eq!(
+            res,
+            (
+                Origin::CurrentDirectory,
+                ".".into(),
+                "fish".into(),
+                None,
+                None
+            )
+        );
 
         let res = Expander::parse_pattern("@fish^tail").unwrap();
-        assert_eq!(res, (false, ".", "fish", Some("tail")));
+        assert_eq!(
+            res,
+            (
+                Origin::CurrentDirectory,
+                ".".into(),
+                "fish".into(),
+                Some("tail"),
+                None
+            )
+        );
 
         let res = Expander::parse_pattern("@%head/**/fish^tail").unwrap();
-        assert_eq!(res, (true, "head", "fish", Some("tail")));
+        assert_eq!(
+            res,
+            (
+                Origin::RepositoryRoot(RootSearch::Nearest),
+                "head".into(),
+                "fish".into(),
+                Some("tail"),
+                None
+            )
+        );
+
+        let res = Expander::parse_pattern("@%%fish").unwrap();
+        assert_eq!(
+            res,
+            (
+                Origin::RepositoryRoot(RootSearch::Outermost),
+                ".".into(),
+                "fish".into(),
+                None,
+                None
+            )
+        );
 
         let res = Expander::parse_pattern("@/**/fish").unwrap();
-        assert_eq!(res, (false, "/", "fish", None));
+        assert_eq!(
+            res,
+            (
+                Origin::CurrentDirectory,
+                "/".into(),
+                "fish".into(),
+                None,
+                None
+            )
+        );
 
         let res = Expander::parse_pattern("@//**/fish").unwrap();
-        assert_eq!(res, (false, "/", "fish", None));
+        assert_eq!(
+            res,
+            (
+                Origin::CurrentDirectory,
+                "/".into(),
+                "fish".into(),
+                None,
+                None
+            )
+        );
 
         let res = Expander::parse_pattern("@./**/fish").unwrap();
-        assert_eq!(res, (false, ".", "fish", None));
+        assert_eq!(
+            res,
+            (
+                Origin::CurrentDirectory,
+                ".".into(),
+                "fish".into(),
+                None,
+                None
+            )
+        );
 
         let res = Expander::parse_pattern("@head/**/fish/**/tail").unwrap();
-        assert_eq!(res, (false, "head", "fish/**/tail", None));
+        assert_eq!(
+            res,
+            (
+                Origin::CurrentDirectory,
+                "head".into(),
+                "fish/**/tail".into(),
+                None,
+                None
+            )
+        );
+
+        let res = Expander::parse_pattern("@f:%head/**/fish").unwrap();
+        assert_eq!(
+            res,
+            (
+                Origin::RepositoryRoot(RootSearch::Nearest),
+                "head".into(),
+                "fish".into(),
+                None,
+                Some(EntryKind::Files)
+            )
+        );
+
+        let res = Expander::parse_pattern("@d:fish^1").unwrap();
+        assert_eq!(
+            res,
+            (
+                Origin::CurrentDirectory,
+                ".".into(),
+                "fish".into(),
+                Some("1"),
+                Some(EntryKind::Directories)
+            )
+        );
 
         let res = Expander::parse_pattern("@head/**/").unwrap();
-        assert_eq!(res, (false, "head", "*/", None));
+        assert_eq!(
+            res,
+            (
+                Origin::CurrentDirectory,
+                "head".into(),
+                "*/".into(),
+                None,
+                None
+            )
+        );
+
+        let res = Expander::parse_pattern("@:docs^1").unwrap();
+        assert_eq!(
+            res,
+            (
+                Origin::Bookmark("docs".into()),
+                ".".into(),
+                "".into(),
+                Some("1"),
+                None
+            )
+        );
+
+        let res = Expander::parse_pattern("@:docs/head/**/fish").unwrap();
+        assert_eq!(
+            res,
+            (
+                Origin::Bookmark("docs".into()),
+                "head".into(),
+                "fish".into(),
+                None,
+                None
+            )
+        );
+
+        let res = Expander::parse_pattern("@:docs/**/fish").unwrap();
+        assert_eq!(
+            res,
+            (
+                Origin::Bookmark("docs".into()),
+                ".".into(),
+                "**/fish".into(),
+                None,
+                None
+            )
+        );
+
+        let res = Expander::parse_pattern("@z:proj/**/*.rs").unwrap();
+        assert_eq!(
+            res,
+            (
+                Origin::Zoxide("proj".into()),
+                ".".into(),
+                "**/*.rs".into(),
+                None,
+                None
+            )
+        );
+
+        let res = Expander::parse_pattern("@!").unwrap();
+        assert_eq!(res, (Origin::History(1), ".".into(), "".into(), None, None));
+
+        let res = Expander::parse_pattern("@!3^a").unwrap();
+        assert_eq!(
+            res,
+            (Origin::History(3), ".".into(), "".into(), Some("a"), None)
+        );
+
+        assert!(Expander::parse_pattern("@!x").is_err());
+
+        let res = Expander::parse_pattern("@\\:docs").unwrap();
+        assert_eq!(
+            res,
+            (
+                Origin::CurrentDirectory,
+                ".".into(),
+                ":docs".into(),
+                None,
+                None
+            )
+        );
+
+        // Alternation groups aren't split up
+        let res = Expander::parse_pattern("@{src,tests}/**/*.rs").unwrap();
+        assert_eq!(
+            res,
+            (
+                Origin::CurrentDirectory,
+                "{src,tests}".into(),
+                "*.rs".into(),
+                None,
+                None
+            )
+        );
+
+        let res = Expander::parse_pattern("@{src/**/a,b}^1").unwrap();
+        assert_eq!(
+            res,
+            (
+                Origin::CurrentDirectory,
+                ".".into(),
+                "{src/**/a,b}".into(),
+                Some("1"),
+                None
+            )
+        );
+
+        // Exclusions stay with the glob pattern, even if they contain "/**/"
+        let res = Expander::parse_pattern("@src/**/*.rs!gen/**/*^1").unwrap();
+        assert_eq!(
+            res,
+            (
+                Origin::CurrentDirectory,
+                "src".into(),
+                "*.rs!gen/**/*".into(),
+                Some("1"),
+                None
+            )
+        );
+
+        let res = Expander::parse_pattern("@[!a]*").unwrap();
+        assert_eq!(
+            res,
+            (
+                Origin::CurrentDirectory,
+                ".".into(),
+                "[!a]*".into(),
+                None,
+                None
+            )
+        );
+
+        // Escaped metacharacters are literal, anywhere in the pattern
+        let res = Expander::parse_pattern(r"@a\^b^1").unwrap();
+        assert_eq!(
+            res,
+            (
+                Origin::CurrentDirectory,
+                ".".into(),
+                "a^b".into(),
+                Some("1"),
+                None
+            )
+        );
+
+        let res = Expander::parse_pattern(r"@\%head/**/fish").unwrap();
+        assert_eq!(
+            res,
+            (
+                Origin::CurrentDirectory,
+                "%head".into(),
+                "fish".into(),
+                None,
+                None
+            )
+        );
+
+        let res = Expander::parse_pattern(r"@head\/**/fish/**/\*").unwrap();
+        assert_eq!(
+            res,
+            (
+                Origin::CurrentDirectory,
+                "head/**/fish".into(),
+                r"\*".into(),
+                None,
+                None
+            )
+        );
+
+        let res = Expander::parse_pattern(r"@\/**/fish").unwrap();
+        assert_eq!(
+            res,
+            (
+                Origin::CurrentDirectory,
+                ".".into(),
+                "/**/fish".into(),
+                None,
+                None
+            )
+        );
+    }
+
+    #[test]
+    fn environment_variables() {
+        env::set_var("LAX_TEST_ENTRY_POINT", "tests/foobar");
+        assert_eq!(
+            Expander::parse_pattern("@$LAX_TEST_ENTRY_POINT/**/${LAX_TEST_ENTRY_POINT}_*").unwrap(),
+            (
+                Origin::CurrentDirectory,
+                "tests/foobar".into(),
+                "tests/foobar_*".into(),
+                None,
+                None
+            )
+        );
+        // Escaped, or not followed by a name, '$' is literal
+        assert_eq!(
+            Expander::parse_pattern(r"@\$LAX_TEST_ENTRY_POINT$1$").unwrap(),
+            (
+                Origin::CurrentDirectory,
+                ".".into(),
+                "$LAX_TEST_ENTRY_POINT$1$".into(),
+                None,
+                None
+            )
+        );
+        assert!(Expander::parse_pattern("@$LAX_TEST_NO_SUCH_VARIABLE/**/fox").is_err());
+        assert!(Expander::parse_pattern("@${LAX_TEST_ENTRY_POINT").is_err());
+
+        let mut exp = setup();
+        let arguments = vec!["@$LAX_TEST_ENTRY_POINT/**/fox".to_string()];
+        assert_eq!(
+            exp.expand_arguments(&arguments).unwrap(),
+            vec!["tests/foobar/fox"]
+        );
+
+        // In the glob, a value's metacharacters match themselves
+        env::set_var("LAX_TEST_GLOB", "fo?");
+        assert_eq!(
+            Expander::parse_pattern("@tests/**/$LAX_TEST_GLOB*")
+                .unwrap()
+                .2,
+            "fo[?]*"
+        );
+        let arguments = vec!["@tests/**/$LAX_TEST_GLOB".to_string()];
+        assert!(matches!(
+            exp.expand_arguments(&arguments).unwrap_err(),
+            LaxError::NoMatches(..)
+        ));
+        env::set_var("LAX_TEST_GLOB", r"[a\b]{c}");
+        let escaped = if cfg!(windows) {
+            r"[[]a\b[]][{]c[}]"
+        } else {
+            r"[[]a\\b[]][{]c[}]"
+        };
+        assert_eq!(
+            Expander::parse_pattern("@${LAX_TEST_GLOB}!$LAX_TEST_GLOB")
+                .unwrap()
+                .2,
+            format!("{escaped}!{escaped}")
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn windows_separators() {
+        assert_eq!(
+            normalize_separators(r"@src\**\*.rs^/\.rs$"),
+            r"@src/**/*.rs^/\.rs$"
+        );
+        assert_eq!(normalize_separators(r"@\:docs"), r"@\:docs");
+        assert_eq!(
+            Expander::parse_pattern("@z:/Users/**/fish").unwrap(),
+            (
+                Origin::CurrentDirectory,
+                "z:/Users".into(),
+                "fish".into(),
+                None,
+                None
+            )
+        );
+    }
+
+    #[test]
+    fn brace_alternation() {
+        assert_eq!(expand_braces("{src,tests}"), ["src", "tests"]);
+        assert_eq!(
+            expand_braces("a/{b,c{d,e}}/f"),
+            ["a/b/f", "a/cd/f", "a/ce/f"]
+        );
+        assert_eq!(expand_braces("{a,b}{c,d}"), ["ac", "ad", "bc", "bd"]);
+        assert_eq!(expand_braces("{unclosed"), ["{unclosed"]);
+        assert_eq!(expand_braces(r"\{a,b}"), [r"\{a,b}"]);
+
+        let mut exp = setup();
+        let arguments = vec!["@{tests/foobar,src}/**/fo{o,x}^a".to_string()];
+        let mut expanded = exp.expand_arguments(&arguments).unwrap();
+        expanded.sort();
+        assert_eq!(expanded, vec!["tests/foobar/foo", "tests/foobar/fox"]);
+    }
+
+    #[test]
+    fn pattern_alternatives() {
+        assert_eq!(split_alternatives("@a|%b/**/c^1"), ["@a^1", "@%b/**/c^1"]);
+        assert_eq!(
+            split_alternatives(r"@a\|b|{c|d}^/x|y"),
+            [r"@a\|b^/x|y", "@{c|d}^/x|y"]
+        );
+        assert_eq!(split_alternatives("@a"), ["@a"]);
+
+        // The first alternative with matches wins, even if the others can't be searched at all
+        let mut exp = setup();
+        let arguments = vec!["@no_such_file|foo|fox".to_string()];
+        let expanded = exp.expand_arguments(&arguments).unwrap();
+        assert_eq!(expanded, vec!["./tests/foobar/foo"]);
+        let arguments = vec!["@no_such_directory/**/foo|fox".to_string()];
+        let expanded = exp.expand_arguments(&arguments).unwrap();
+        assert_eq!(expanded, vec!["./tests/foobar/fox"]);
+        assert_eq!(
+            exp.probe("@no_such_file|foo").unwrap(),
+            Probe::Unambiguous(vec!["./tests/foobar/foo".into()])
+        );
+
+        let arguments = vec!["@no_such_file|no_such_file_either".to_string()];
+        let err = exp.expand_arguments(&arguments).unwrap_err();
+        assert!(matches!(
+            err,
+            LaxError::NoMatches(pattern, _) if pattern == "no_such_file_either"
+        ));
+
+        // Other errors are mistakes in the pattern, which the next alternative doesn't hide
+        let arguments = vec!["@:no_such_bookmark/foo|foo".to_string()];
+        let err = exp.expand_arguments(&arguments).unwrap_err();
+        assert!(matches!(err, LaxError::UnknownBookmark(_)));
+        assert!(matches!(
+            exp.probe("@:no_such_bookmark/foo|foo"),
+            Err(LaxError::UnknownBookmark(_))
+        ));
+        assert!(matches!(
+            exp.matches("@:no_such_bookmark/foo|foo").next(),
+            Some(Err(LaxError::UnknownBookmark(_)))
+        ));
+    }
+
+    #[test]
+    fn exclusions() {
+        let mut exp = setup();
+        let arguments = vec!["@tests/**/fo[ox]!fox".to_string()];
+        let expanded = exp.expand_arguments(&arguments).unwrap();
+        assert_eq!(expanded, vec!["tests/foobar/foo"]);
+
+        // Exclusions match anywhere under the entry point, like the pattern itself
+        let arguments = vec!["@tests/**/fo?!foobar/**".to_string()];
+        let err = exp.expand_arguments(&arguments).unwrap_err();
+        assert!(matches!(err, LaxError::NoMatches(..)));
+
+        let arguments = vec!["@foo!".to_string()];
+        let err = exp.expand_arguments(&arguments).unwrap_err();
+        assert!(matches!(err, LaxError::InvalidPattern(_)));
     }
 
     // '/' implies matching only directories
     #[test]
     fn imply_directory_matching() {
-        let exp = setup();
+        let mut exp = setup();
         let arguments = vec!["@fo*/^a".to_string()];
         let expanded = exp.expand_arguments(&arguments).unwrap();
         assert_eq!(expanded.len(), 1);
         assert_eq!(expanded.first().unwrap(), "./tests/foobar/");
     }
 
+    #[test]
+    fn modifiers() {
+        assert_eq!(split_modifiers("@main.rs"), ("@main.rs", vec![]));
+        assert_eq!(
+            split_modifiers("@*.rs^1:t:r"),
+            (
+                "@*.rs^1",
+                vec![FilenameModifier::Tail, FilenameModifier::Root]
+            )
+        );
+        assert_eq!(split_modifiers("@a\\:h"), ("@a\\:h", vec![]));
+        assert_eq!(split_modifiers("@:h"), ("@:h", vec![]));
+        assert_eq!(split_modifiers("@a:x"), ("@a:x", vec![]));
+        // A ':' that ends a prefix isn't a modifier's
+        assert_eq!(split_modifiers("@f:h"), ("@f:h", vec![]));
+        assert_eq!(split_modifiers("@d:t"), ("@d:t", vec![]));
+        assert_eq!(split_modifiers("@f:z:e"), ("@f:z:e", vec![]));
+        assert_eq!(split_modifiers("@recent:r"), ("@recent:r", vec![]));
+        assert_eq!(
+            split_modifiers("@f:f:h"),
+            ("@f:f", vec![FilenameModifier::Head])
+        );
+
+        let mut exp = setup();
+        let arguments: Vec<String> = ["@foo:h", "@src/lib.rs^1:t:r", "@foo"]
+            .map(String::from)
+            .into();
+        assert_eq!(
+            exp.expand_arguments(&arguments).unwrap(),
+            ["./tests/foobar", "lib", "./tests/foobar/foo"]
+        );
+    }
+
     #[test]
     fn transform_file_to_parent() {
         let mut exp = setup();
@@ -548,9 +3318,52 @@ mod tests {
         assert_eq!(expanded.first().unwrap(), "./src");
     }
 
+    // Transforms asked for by settings apply without building a pipeline by hand
+    #[test]
+    fn settings_transform() {
+        let mut exp = setup();
+        exp.config = Config::builder()
+            .transform_files_to_dirs(true)
+            .path_part(PathPart::Basename)
+            .build();
+        let arguments = vec!["@main.rs".to_string()];
+        assert_eq!(exp.expand_arguments(&arguments).unwrap(), ["src"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn transform_symlink_to_parent() {
+        let root = env::temp_dir().join(format!("lax_test_symlink_parent_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("links")).unwrap();
+        fs::create_dir_all(root.join("targets")).unwrap();
+        fs::write(root.join("targets/file"), "").unwrap();
+        std::os::unix::fs::symlink(root.join("targets/file"), root.join("links/link")).unwrap();
+        let link = vec![root.join("links/link").display().to_string()];
+
+        let mut exp = setup();
+        exp.config.transform_files_to_dirs = true;
+        assert_eq!(
+            exp.apply_post_transforms(link.clone(), &[]).unwrap(),
+            vec![root.join("links").display().to_string()]
+        );
+
+        exp.config.symlink_parent = SymlinkParent::Target;
+        exp.config.canonicalize_parent = true;
+        assert_eq!(
+            exp.apply_post_transforms(link, &[]).unwrap(),
+            vec![fs::canonicalize(root.join("targets"))
+                .unwrap()
+                .display()
+                .to_string()]
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
     #[test]
     fn expand_with_all_selector() {
-        let exp = setup();
+        let mut exp = setup();
 
         let arguments = vec!["@*.rs^a".to_string()];
         let expanded = exp.expand_arguments(&arguments).unwrap();
@@ -559,7 +3372,7 @@ mod tests {
 
     #[test]
     fn expand_with_last_selector() {
-        let exp = setup();
+        let mut exp = setup();
 
         let arguments = vec!["@*.rs^l".to_string()];
         let expanded = exp.expand_arguments(&arguments).unwrap();
@@ -568,16 +3381,134 @@ mod tests {
 
     #[test]
     fn expand_with_single_selector() {
-        let exp = setup();
+        let mut exp = setup();
 
         let arguments = vec!["@*.rs^1".to_string()];
         let expanded = exp.expand_arguments(&arguments).unwrap();
         assert_eq!(expanded.len(), 1);
     }
 
+    #[test]
+    fn expand_with_back_selectors() {
+        let mut exp = setup();
+        let all = exp.expand_arguments(&["@*.rs^a".to_string()]).unwrap();
+        assert!(all.len() > 4);
+
+        // Only the ends of the walk are kept, and the front can still be reused from the cache
+        for (pattern, expected) in [
+            ("@*.rs^-1", vec![all[all.len() - 1].as_str()]),
+            (
+                "@*.rs^1,-1",
+                vec![all[0].as_str(), all[all.len() - 1].as_str()],
+            ),
+            (
+                "@*.rs^-3..-2",
+                vec![all[all.len() - 3].as_str(), all[all.len() - 2].as_str()],
+            ),
+            ("@*.rs^2", vec![all[1].as_str()]),
+        ] {
+            let mut exp = setup();
+            let arguments = vec!["@*.rs^-1".to_string(), pattern.to_string()];
+            let expanded = exp.expand_arguments(&arguments).unwrap();
+            assert_eq!(expanded[1..], expected, "{pattern}");
+        }
+    }
+
+    #[test]
+    fn too_many_matches() {
+        struct Confirm(bool);
+        impl SelectionMenu for Confirm {
+            fn select(&mut self, _: &[Candidate], _: u32, _: bool) -> Result<MenuOutcome> {
+                panic!("Oh god a choice!")
+            }
+            fn confirm_many(&mut self, _pattern: &str, limit: usize) -> Result<bool> {
+                assert_eq!(limit, 1);
+                Ok(self.0)
+            }
+        }
+
+        let arguments = vec!["@fo[ox]^a".to_string()];
+        for (use_menu, confirm) in [(false, true), (true, false)] {
+            let mut exp = setup();
+            exp.config.max_matches = Some(1);
+            exp.config.use_menu = use_menu;
+            exp.selector_menu = Box::new(Confirm(confirm));
+            assert!(matches!(
+                exp.expand_arguments(&arguments).unwrap_err(),
+                LaxError::TooManyMatches(_, 1)
+            ));
+        }
+
+        let mut exp = setup();
+        exp.config.max_matches = Some(1);
+        exp.selector_menu = Box::new(Confirm(true));
+        assert_eq!(exp.expand_arguments(&arguments).unwrap().len(), 2);
+        // Patterns that can't match that many aren't held up
+        exp.config.max_matches = Some(2);
+        let arguments = vec!["@fo[ox]^1,2".to_string()];
+        assert_eq!(exp.expand_arguments(&arguments).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn match_order() {
+        let mut exp = setup();
+        let arguments = vec!["@tests/**/*^a".to_string()];
+        let by_path = exp.expand_arguments(&arguments).unwrap();
+        let mut sorted = by_path.clone();
+        sorted.sort_by(|a, b| Path::new(a).cmp(Path::new(b)));
+        assert_eq!(by_path, sorted);
+        assert_eq!(
+            exp.expand_arguments(&["@fo[ox]^1".to_string()]).unwrap(),
+            ["./tests/foobar/foo"]
+        );
+
+        // Other orders sort what the walk found, even for selectors that would stop it early
+        let mut exp = setup();
+        exp.config.sort = MatchOrder::Name;
+        let by_name = exp.expand_arguments(&arguments).unwrap();
+        sorted.sort_by_key(|path| Path::new(path).file_name().map(|name| name.to_owned()));
+        assert_eq!(by_name, sorted);
+        let arguments = vec!["@tests/**/*^1".to_string()];
+        assert_eq!(exp.expand_arguments(&arguments).unwrap(), sorted[..1]);
+        assert_eq!(MatchOrder::parse("mtime"), Some(MatchOrder::Modified));
+        assert_eq!(MatchOrder::parse("random"), None);
+    }
+
+    #[test]
+    fn timeout() {
+        let mut exp = setup();
+        exp.config.timeout = Some(Duration::ZERO);
+        let arguments = vec!["@foo".to_string()];
+        assert!(matches!(
+            exp.expand_arguments(&arguments).unwrap_err(),
+            LaxError::TimedOut(pattern, Duration::ZERO, found) if pattern == "@foo" && found.is_empty()
+        ));
+
+        exp.config.timeout = Some(Duration::from_secs(60));
+        assert_eq!(exp.expand_arguments(&arguments).unwrap().len(), 1);
+
+        // A backend that takes too long is given up on too
+        #[cfg(unix)]
+        {
+            exp.config.timeout = Some(Duration::from_millis(100));
+            exp.config.entry_point_rules = vec![EntryPointRule {
+                path: "tests".into(),
+                backend: Some("sleep 10".into()),
+                ..Default::default()
+            }];
+            let start = Instant::now();
+            assert!(matches!(
+                exp.expand_arguments(&["@tests/**/foo".to_string()]),
+                Err(LaxError::TimedOut(..))
+            ));
+            assert!(start.elapsed() < Duration::from_secs(5));
+        }
+    }
+
+    #[cfg(feature = "regex")]
     #[test]
     fn search() {
-        let exp = setup();
+        let mut exp = setup();
 
         let arguments = vec!["@*.rs^/nothingmatchesthis".to_string()];
         let expanded = exp.expand_arguments(&arguments).unwrap();
@@ -586,7 +3517,7 @@ mod tests {
 
     #[test]
     fn globbing() {
-        let exp = setup();
+        let mut exp = setup();
         let patterns_with_many_matches = ["@*.rs^a", "@src/*.rs^a", "@src/../**/*.rs^a"];
 
         for pattern in &patterns_with_many_matches {
@@ -604,12 +3535,167 @@ mod tests {
         }
     }
 
+    #[test]
+    fn explain() {
+        let mut exp = setup();
+        let arguments = vec!["echo".to_string(), "@foo".to_string()];
+        let reports = exp.explain_arguments(&arguments).unwrap();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].pattern, "@foo");
+        assert_eq!(reports[0].paths, vec!["./tests/foobar/foo"]);
+        assert!(reports[0].timings.walk > Duration::ZERO);
+        assert_eq!(reports[0].stats.matched, 1);
+        assert!(reports[0].stats.directories > 1);
+        assert!(reports[0].stats.files > 1);
+    }
+
+    #[test]
+    fn resolve_program_from_path() {
+        let mut exp = setup();
+        exp.config.resolve_program_from_path = true;
+        let arguments = vec!["@ls".to_string(), "@foo".to_string()];
+        let expanded = exp.expand_arguments(&arguments).unwrap();
+        assert!(expanded[0].ends_with("/ls"));
+        assert!(Path::new(&expanded[0]).is_absolute());
+        assert_eq!(expanded[1], "./tests/foobar/foo");
+    }
+
+    // `f:` and `d:` override the config for their own pattern only
+    #[test]
+    fn entry_kinds() {
+        let mut exp = setup();
+        let arguments: Vec<String> = ["@d:tests/foobar/**/*^a", "@f:tests/foobar/**/*^a"]
+            .map(String::from)
+            .into();
+        assert_eq!(
+            exp.expand_arguments(&arguments).unwrap(),
+            [
+                "tests/foobar/another_directory/",
+                "tests/foobar/this_is_a_directory/",
+                "tests/foobar/foo",
+                "tests/foobar/fox",
+            ]
+        );
+        exp.config.match_with_dirs = false;
+        let arguments = vec!["@d:this_is_a_directory".to_string()];
+        assert_eq!(
+            exp.expand_arguments(&arguments).unwrap(),
+            ["./tests/foobar/this_is_a_directory/"]
+        );
+        assert!(exp
+            .expand_arguments(&["@f:this_is_a_directory/".to_string()])
+            .is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn entry_types() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = env::temp_dir().join(format!("lax_test_entry_types_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("empty")).unwrap();
+        fs::write(dir.join("script"), "#!/bin/sh\n").unwrap();
+        fs::set_permissions(dir.join("script"), fs::Permissions::from_mode(0o755)).unwrap();
+        std::os::unix::fs::symlink("script", dir.join("link")).unwrap();
+
+        let mut exp = setup();
+        let pattern = format!("@{}/**/*^a", dir.display());
+        let mut matching = |types: Vec<EntryType>| {
+            exp.config.entry_types = types;
+            exp.expand_arguments(std::slice::from_ref(&pattern))
+                .map(|paths| {
+                    paths
+                        .iter()
+                        .map(|path| {
+                            path.rsplit('/')
+                                .find(|name| !name.is_empty())
+                                .unwrap()
+                                .to_string()
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default()
+        };
+        assert_eq!(matching(vec![EntryType::Symlink]), ["link"]);
+        assert_eq!(matching(vec![EntryType::Executable]), ["link", "script"]);
+        assert_eq!(matching(vec![EntryType::Empty]), ["empty"]);
+        assert_eq!(
+            matching(vec![EntryType::File, EntryType::Directory]),
+            ["empty", "script"]
+        );
+        assert_eq!(EntryType::parse("executable"), Some(EntryType::Executable));
+        assert_eq!(EntryType::parse("q"), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn extensions() {
+        let extensions = ["rs".to_string(), ".tar.gz".to_string()];
+        assert!(has_extension(Path::new("src/lib.rs"), &extensions));
+        assert!(has_extension(Path::new("a.TAR.GZ"), &extensions));
+        assert!(!has_extension(Path::new("a.gz"), &extensions));
+        assert!(!has_extension(Path::new("src/.rs"), &extensions));
+        assert!(!has_extension(Path::new("liblrs"), &extensions));
+    }
+
+    #[test]
+    fn access() {
+        let access = |read, write, execute| {
+            Some(Access {
+                read,
+                write,
+                execute,
+            })
+        };
+        assert_eq!(Access::parse("-w"), access(false, true, false));
+        assert_eq!(Access::parse("xr"), access(true, false, true));
+        assert_eq!(Access::parse("-"), None);
+        assert_eq!(Access::parse("rwq"), None);
+        assert!(Access::default().allows(Path::new("no_such_path")));
+        #[cfg(unix)]
+        assert!(!Access::parse("r")
+            .unwrap()
+            .allows(Path::new("no_such_path")));
+    }
+
+    #[test]
+    fn repeated_patterns_use_cache() {
+        let key = Search {
+            origin: Origin::CurrentDirectory,
+            entry_point: ".".into(),
+            glob: "foo".into(),
+            only: None,
+        };
+        let mut cache = MatchCache::default();
+        cache.entries.insert(
+            key.clone(),
+            CachedMatches {
+                paths: vec![Match::stat("a".into()), Match::stat("b".into())],
+                complete: false,
+            },
+        );
+        assert_eq!(cache.get(&key, Some(1)).unwrap().len(), 2);
+        assert!(cache.get(&key, Some(2)).is_none());
+        assert!(cache.get(&key, None).is_none());
+
+        let mut exp = setup();
+        let arguments = vec![
+            "@*.rs^1".to_string(),
+            "@*.rs^a".to_string(),
+            "@*.rs^1".to_string(),
+        ];
+        let expanded = exp.expand_arguments(&arguments).unwrap();
+        assert_eq!(expanded.first(), expanded.last());
+    }
+
     // Annoying bug that matches @dep* with @bla/bla/deps/bladfjdkfdf
     // This is undesirable, because if I wanted to look in the deps folder for something, I'd do:
     // @deps/* or @deps/**
     #[test]
     fn dont_match_with_parent_directory() {
-        let exp = setup();
+        let mut exp = setup();
 
         let arguments = vec!["@deps*^a".to_string()];
         let expanded = exp.expand_arguments(&arguments).unwrap();
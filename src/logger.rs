@@ -0,0 +1,48 @@
+//! The logger for `-v`, which writes lax's (and the library's) log messages to stderr.
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::terminal::Terminal;
+
+struct Logger;
+
+static LOGGER: Logger = Logger;
+
+/// Log to stderr, with more detail the more times `-v` was given: per-pattern decisions with one,
+/// skipped and pruned entries with two, and every path walked with three.
+pub fn init(verbosity: u8) {
+    let level = match verbosity {
+        0 => return,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let level = match record.level() {
+            Level::Error => "error",
+            Level::Warn => "warning",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        };
+        eprintln!(
+            "lax: {} {}",
+            Terminal::get().style("2", &format!("[{level}]")),
+            record.args()
+        );
+    }
+
+    fn flush(&self) {}
+}
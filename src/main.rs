@@ -1,9 +1,25 @@
 use std::{
-    env, io,
-    os::unix::process::CommandExt,
+    env,
+    io::{self, IsTerminal},
+    path::Path,
     process::{self, Command},
 };
+
+use lax::errors::LaxError;
+
 mod argparser;
+mod each;
+mod json;
+mod logger;
+mod menu;
+mod resolve;
+mod shell_init;
+mod signals;
+mod temp;
+mod terminal;
+
+// Version of lax's machine-readable output formats. Bump this when they change incompatibly.
+const PROTOCOL_VERSION: u32 = 1;
 
 BuildArgumentParser! {
     name: "lax",
@@ -13,6 +29,10 @@ BuildArgumentParser! {
     flags: {
         /// Search hidden files/directories
         search_all:('a', "--all"),
+        /// Search hidden files/directories, like -a
+        hidden: ('H', "--hidden"),
+        /// Don't search hidden files/directories, even if the config file says to
+        no_hidden: ("--no-hidden"),
         /// Only match directories
         directories:('d', "--directories"),
         /// Only match files
@@ -21,8 +41,121 @@ BuildArgumentParser! {
         print_only: ('p', "--print-only"),
         /// Print each arg on a new line, but don't execute
         print_lines: ('P', "--print-lines"),
+        /// Print shell-quoted args, safe to `eval`, but don't execute
+        print_quoted: ("--print-quoted"),
+        /// Print each arg terminated by a NUL character (for `xargs -0`), but don't execute
+        print0: ('0', "--print0"),
+        /// List what the '@' patterns match, one per line, without running anything
+        list: ('l', "--list"),
+        /// Print the transformed args and what each '@' pattern matched as JSON, but don't execute
+        json: ("--json"),
+        /// Replace symlinks with the paths they point to
+        resolve_symlinks: ("--resolve-symlinks"),
         /// Transform matched files to their parent directory
-        file_to_parent: ('D', "--file2parent")
+        file_to_parent: ('D', "--file2parent"),
+        /// With -D, use the directory of a symlink's target rather than of the link
+        target_parent: ("--target-parent"),
+        /// With -D, canonicalize the resulting directory
+        canonical_parent: ("--canonical-parent"),
+        /// Write expanded paths as absolute paths, without resolving symlinks
+        absolute: ("--absolute"),
+        /// Write expanded paths relative to the current directory
+        relative: ("--relative"),
+        /// Write expanded paths as absolute paths, with symlinks resolved
+        canonical: ("--canonical"),
+        /// Only keep the last component of each expanded path, like `basename`
+        basename: ("--basename"),
+        /// Keep all but the last component of each expanded path, like `dirname`
+        dirname: ("--dirname"),
+        /// Don't end matched directories with a separator, for tools like rsync that care
+        no_dir_slash: ("--no-dir-slash"),
+        /// Prompt separately for each occurrence of a repeated pattern
+        independent: ("--independent"),
+        /// Show how each '@' pattern expands and how long it took, but don't execute
+        explain: ("--explain"),
+        /// Print how many paths each '@' pattern walked and matched, and how long it took, to stderr
+        stats: ("--stats"),
+        /// Don't read or update the index of searched trees, even if `cache_dir` is set
+        no_cache: ("--no-cache"),
+        /// Rebuild the index of each tree searched, even if it looks up to date
+        refresh: ("--refresh"),
+        /// Keep the trees under `daemon_roots` indexed, and answer other invocations' searches
+        daemon: ("--daemon"),
+        /// Show the menu for ambiguous patterns, even if stdin isn't a terminal
+        menu: ("--menu"),
+        /// Never show the menu. Ambiguous patterns are an error that lists the candidates
+        no_menu: ("--no-menu"),
+        /// Choose from the menu by checking paths with space and confirming with Enter
+        checklist: ("--checklist"),
+        /// Choose from the menu by deleting the paths you don't want from a list in $EDITOR
+        edit: ("--edit"),
+        /// Select the first match of patterns without selectors, as if they ended with '^1'
+        first: ("--first"),
+        /// Select every match of patterns without selectors, as if they ended with '^a'
+        select_all: ("--select-all"),
+        /// Print the absolute path of the program that would run, but don't execute
+        print_resolved: ("--print-resolved"),
+        /// Ask before using each path an '@' pattern expands to, like `rm -i`
+        confirm_each: ("--confirm-each"),
+        /// Run BINARY as a child process and wait for it, rather than replacing lax with it
+        spawn: ("--spawn"),
+        /// Append arguments read from stdin, one per line, to the ones given
+        stdin: ("--stdin"),
+        /// Match '@' patterns against paths read from stdin, one per line, not the filesystem
+        filter: ("--filter")
+    }
+
+    options: {
+        /// Run the command inside this directory, which may be an '@' pattern
+        chdir: ("--chdir"),
+        /// Run this command once per path, substituting '{}', instead of running BINARY
+        each: ("--each"),
+        /// With --each, run up to this many commands at once
+        jobs: ('j', "--jobs"),
+        /// Report whether an '@' pattern is unambiguous, ambiguous, or unmatched, then exit
+        probe: ("--probe"),
+        /// Show at most this many matches in the menu, and find the rest on demand
+        max_results: ("--max-results"),
+        /// Ask before expanding a pattern with selectors to more than this many paths
+        max_matches: ("--max-matches"),
+        /// Give up on a pattern whose search takes longer than this, like 5s or 500ms
+        timeout: ("--timeout"),
+        /// Order matches before selectors pick from them: none, path, name, natural, mtime, or size
+        sort: ("--sort"),
+        /// Search from this directory, not the current one, when a pattern has no entry point
+        entry_point: ("--entry-point"),
+        /// Print bash/zsh integration to `eval`: all, aliases, cd, completions, or widgets
+        shell_init: ("--shell-init"),
+        /// With --shell-init, the shell to generate it for: bash, zsh, or fish
+        shell: ("--shell"),
+        /// Only match entries owned by this user, given by name or ID
+        owner: ("--owner"),
+        /// Only match entries we can read, write, and/or execute, like -w or rx
+        perm: ("--perm")
+    }
+
+    lists: {
+        /// Append arguments read from this file, one per line, to the ones given
+        args_file: ("--args-file"),
+        /// Only match this type of entry: f (file), d, l (symlink), x (executable), or e (empty)
+        types: ("--type"),
+        /// Only match entries with one of these comma-separated extensions, like rs,toml
+        extensions: ("--ext")
+    }
+
+    conflicts: {
+        [first, select_all],
+        [checklist, no_menu],
+        [checklist, edit],
+        [edit, no_menu],
+        [menu, no_menu],
+        [daemon, no_cache],
+        [no_cache, refresh],
+        [absolute, relative, canonical],
+        [basename, dirname],
+        [directories, files],
+        [search_all, hidden, no_hidden],
+        [filter, stdin]
     }
 }
 
@@ -31,81 +164,741 @@ fn main() {
 
     let args: Vec<String> = env::args().collect();
     let args = ap.process_arguments(&args);
+    logger::init(ap.verbose);
+
+    if let Some(format) = &ap.version_format {
+        print_version(format);
+        process::exit(0);
+    }
+    let shell = match ap.shell.as_deref() {
+        None => shell_init::Shell::Bash,
+        Some(_) if ap.shell_init.is_none() => {
+            eprintln!("lax: `--shell` only works with `--shell-init`");
+            process::exit(1);
+        }
+        Some(name) => shell_init::Shell::parse(name).unwrap_or_else(|| {
+            eprintln!(
+                "lax: `--shell` expects one of: {}",
+                shell_init::Shell::NAMES.join(", ")
+            );
+            process::exit(1);
+        }),
+    };
+    if let Some(part) = &ap.shell_init {
+        match shell_init::script(part, shell, ArgumentParser::FLAGS) {
+            Some(script) => print!("{}", script),
+            None => {
+                eprintln!(
+                    "lax: `--shell-init` expects one of: all, {}",
+                    shell_init::PARTS.join(", ")
+                );
+                process::exit(1);
+            }
+        }
+        process::exit(0);
+    }
+
+    let piped: Vec<String>;
+    let args = if ap.stdin || !ap.args_file.is_empty() {
+        let mut all = args.to_vec();
+        for path in &ap.args_file {
+            all.extend(read_args_file(path));
+        }
+        if ap.stdin {
+            all.extend(read_lines());
+        }
+        piped = all;
+        &piped
+    } else {
+        args
+    };
 
-    if args.is_empty() {
+    if args.is_empty() && ap.probe.is_none() && !ap.daemon {
         eprintln!("lax: No arguments");
         eprintln!("For more information try --help");
         process::exit(1);
     }
-    if ap.files && ap.directories {
-        eprintln!("The `-d` and `-f` flag can not be on at the same time. They are incompatible.");
-        process::exit(1);
-    }
-
-    let config = lax::Config {
-        transform_files_to_dirs: ap.file_to_parent,
-        match_with_files: !ap.directories,
-        match_with_dirs: !ap.files,
-        search_hidden: ap.search_all,
+    let jobs = match ap.jobs.as_deref().map(str::parse::<usize>) {
+        None => 1,
+        Some(Ok(jobs)) if jobs > 0 && ap.each.is_some() => jobs,
+        Some(Ok(_)) if ap.each.is_none() => {
+            eprintln!("lax: `-j` only works with `--each`");
+            process::exit(1);
+        }
+        Some(_) => {
+            eprintln!("lax: `-j` expects a positive number");
+            process::exit(1);
+        }
+    };
+    let max_results = match ap.max_results.as_deref().map(str::parse::<usize>) {
+        None => None,
+        Some(Ok(max_results)) if max_results > 0 => Some(max_results),
+        Some(_) => {
+            eprintln!("lax: `--max-results` expects a positive number");
+            process::exit(1);
+        }
+    };
+    let max_matches = match ap.max_matches.as_deref().map(str::parse::<usize>) {
+        None => None,
+        Some(Ok(max_matches)) if max_matches > 0 => Some(max_matches),
+        Some(_) => {
+            eprintln!("lax: `--max-matches` expects a positive number");
+            process::exit(1);
+        }
+    };
+    let timeout = match ap.timeout.as_deref().map(lax::config_file::parse_duration) {
+        None => None,
+        Some(Some(timeout)) => Some(timeout),
+        Some(None) => {
+            eprintln!("lax: `--timeout` expects a duration, like 5s or 500ms");
+            process::exit(1);
+        }
+    };
+    let sort = match ap.sort.as_deref().map(lax::MatchOrder::parse) {
+        None => None,
+        Some(Some(sort)) => Some(sort),
+        Some(None) => {
+            eprintln!("lax: `--sort` expects one of: none, path, name, natural, mtime, size");
+            process::exit(1);
+        }
     };
 
-    // After this, we only do '@' transformations
-    let expander = lax::Expander {
-        config,
-        selector_menu: |paths, first_call| {
-            if first_call {
-                eprintln!("Found the following:");
-                eprintln!("====================");
-                for (i, path) in paths.iter().enumerate() {
-                    eprintln!("{}. {}", i + 1, path);
-                }
-            }
-            eprint!("Select> ");
+    let types = ap
+        .types
+        .iter()
+        .map(|name| {
+            lax::EntryType::parse(name).unwrap_or_else(|| {
+                eprintln!("lax: `--type` expects one of: f, d, l, x, e");
+                process::exit(1);
+            })
+        })
+        .collect::<Vec<_>>();
 
-            let mut option = String::new();
-            io::stdin()
-                .read_line(&mut option)
-                .expect("Failed to read from stdin");
+    let access = ap.perm.as_deref().map(|access| {
+        lax::Access::parse(access).unwrap_or_else(|| {
+            eprintln!("lax: `--perm` expects some of r, w, and x, like -w");
+            process::exit(1);
+        })
+    });
 
-            // Allow user to quit
-            if option.starts_with('q') {
+    let mut config = lax::Config::builder()
+        .history_file(lax::history::default_history_path())
+        .frecency_file(lax::frecency::default_frecency_path())
+        .build();
+    if let Some(path) = lax::config_file::default_config_path() {
+        if path.exists() {
+            if let Err(err) = config.load_file(&path) {
+                eprintln!("lax: {:#}", err);
                 process::exit(1);
             }
+        }
+    }
+    if ap.no_cache {
+        config.cache_dir = None;
+    }
+    if ap.refresh {
+        if config.cache_dir.is_none() {
+            eprintln!("lax: `--refresh` needs `cache_dir` to be set in the config file");
+            process::exit(1);
+        }
+        config.refresh_cache = true;
+    }
+    if let Some(path) = env::var_os("LAX_PATH") {
+        config.search_path = env::split_paths(&path).collect();
+    }
+    if ap.resolve_symlinks {
+        config.resolve_symlinks = true;
+    }
+    if ap.file_to_parent {
+        config.transform_files_to_dirs = true;
+    }
+    if ap.target_parent {
+        config.symlink_parent = lax::SymlinkParent::Target;
+    }
+    if ap.canonical_parent {
+        config.canonicalize_parent = true;
+    }
+    if ap.absolute {
+        config.path_style = lax::PathStyle::Absolute;
+    }
+    if ap.relative {
+        config.path_style = lax::PathStyle::Relative;
+    }
+    if ap.canonical {
+        config.path_style = lax::PathStyle::Canonical;
+    }
+    if ap.basename {
+        config.path_part = lax::PathPart::Basename;
+    }
+    if ap.dirname {
+        config.path_part = lax::PathPart::Dirname;
+    }
+    if ap.directories {
+        config.match_with_dirs = true;
+        config.match_with_files = false;
+    }
+    if ap.files {
+        config.match_with_dirs = false;
+        config.match_with_files = true;
+    }
+    if !types.is_empty() {
+        config.entry_types = types;
+    }
+    if let Some(owner) = ap.owner {
+        config.owner = Some(owner);
+    }
+    if let Some(access) = access {
+        config.access = access;
+    }
+    if !ap.extensions.is_empty() {
+        config.extensions = ap
+            .extensions
+            .iter()
+            .flat_map(|extensions| extensions.split(','))
+            .map(str::trim)
+            .filter(|extension| !extension.is_empty())
+            .map(String::from)
+            .collect();
+    }
+    if ap.no_dir_slash {
+        config.append_dir_slash = false;
+    }
+    if ap.search_all || ap.hidden {
+        config.search_hidden = true;
+    }
+    if ap.no_hidden {
+        config.search_hidden = false;
+    }
+    if ap.independent {
+        config.reuse_selections = false;
+    }
+    if ap.first {
+        config.default_selectors = Some("1".into());
+    }
+    if ap.select_all {
+        config.default_selectors = Some("a".into());
+    }
+    if max_results.is_some() {
+        config.max_results = max_results;
+    }
+    if max_matches.is_some() {
+        config.max_matches = max_matches;
+    }
+    if timeout.is_some() {
+        config.timeout = timeout;
+    }
+    if let Some(sort) = sort {
+        config.sort = sort;
+    }
+    if let Some(directory) = &ap.entry_point {
+        config.default_entry_point = Some(directory.into());
+    }
+    if ap.filter {
+        config.candidates = Some(read_lines());
+    }
+    // In a script or pipeline, waiting on the menu would just hang
+    if ap.no_menu || (!io::stdin().is_terminal() && !ap.menu) {
+        config.use_menu = false;
+    }
+
+    if ap.daemon {
+        #[cfg(unix)]
+        if let Err(err) = lax::daemon::serve(&config) {
+            fail(err.into());
+        }
+        #[cfg(not(unix))]
+        {
+            eprintln!("lax: `--daemon` is only supported on Unix");
+            process::exit(1);
+        }
+        process::exit(0);
+    }
 
-            option
-        },
+    // After this, we only do '@' transformations
+    let line_menu = menu::LineMenu {
+        default: config.menu_default.clone(),
+    };
+    let temp_dir = config.temp_dir.clone().unwrap_or_else(env::temp_dir);
+    let expander = lax::Expander::builder().config(config);
+    let mut expander = if ap.checklist {
+        expander
+            .selector_menu(menu::ChecklistMenu(line_menu))
+            .build()
+    } else if ap.edit {
+        expander
+            .selector_menu(menu::EditorMenu {
+                line: line_menu,
+                temp_dir,
+            })
+            .build()
+    } else {
+        expander.selector_menu(line_menu).build()
     };
 
-    let args = match expander.expand_arguments(args) {
-        Ok(args) => args,
-        Err(err) => {
-            eprintln!("lax: {}", err);
-            process::exit(1)
+    // Recorded along with the rest of the expansion, once it's done
+    let mut chdir_reports = Vec::new();
+    if let Some(directory) = &ap.chdir {
+        // Change directory first, so the remaining patterns are relative to where the command runs
+        match change_directory(&mut expander, directory) {
+            Ok(reports) => chdir_reports = reports,
+            Err(err) => fail(err),
         }
+    }
+
+    if let Some(pattern) = &ap.probe {
+        process::exit(probe(&expander, pattern));
+    }
+
+    if ap.explain {
+        match expander.explain_arguments(args) {
+            Ok(reports) => explain(&reports),
+            Err(err) => fail(err.into()),
+        }
+        return;
+    }
+
+    // When executing, an '@' pattern in the program position picks from the executables in $PATH
+    let printing = ap.print_only || ap.print_lines || ap.print_quoted || ap.print0;
+    expander.config.resolve_program_from_path =
+        !(printing || ap.list || ap.json || ap.each.is_some());
+    // There's no binary when listing, so only the '@' patterns matter
+    let patterns: Vec<String>;
+    let args = if ap.list {
+        patterns = expander
+            .split_literal(args)
+            .0
+            .iter()
+            .filter(|arg| lax::is_pattern(arg))
+            .cloned()
+            .collect();
+        &patterns
+    } else {
+        args
+    };
+    let (expanded, reports) = match expander.expand_arguments_unrecorded(args) {
+        Ok(expansion) => expansion,
+        Err(err) => fail(err.into()),
+    };
+    chdir_reports.extend(reports.iter().cloned());
+    expander.record(&chdir_reports);
+    if ap.stats {
+        print_stats(&reports);
+    }
+    let args = if ap.confirm_each {
+        confirm_each(expander.split_literal(args).0, expanded, &reports)
+    } else {
+        expanded
     };
 
-    if ap.print_lines {
+    if ap.json {
+        print_json(&args, &reports);
+    } else if ap.list {
+        for path in &args {
+            println!("{}", path);
+        }
+    } else if ap.print_quoted {
+        let args: Vec<String> = args.iter().map(|arg| lax::quote::quote(arg)).collect();
+        println!("{}", args.join(" "));
+    } else if ap.print0 {
+        for arg in &args {
+            print!("{}\0", arg);
+        }
+    } else if ap.print_lines {
         println!("{}", args.join("\n"));
     } else if ap.print_only {
         print!("{}", args.join(" "));
+    } else if let Some(template) = &ap.each {
+        signals::forward();
+        process::exit(each::run(template, &args, jobs));
     } else {
         // Go ahead and run the binary with the transformed arguments
         let programs = &args[0];
         let args = &args[1..];
 
         // Try multiple programs delimited with '|' in case one doesn't exist.
-        let mut err_message = None;
+        let mut last_err = None;
         for program in programs.split('|') {
-            let err = Command::new(program).args(args).exec();
-            err_message = Some(format!("'{}': {}", program, err));
+            let err = match resolve::program(program) {
+                Ok(path) if ap.print_resolved => {
+                    println!("{}", path.display());
+                    return;
+                }
+                Ok(path) if ap.spawn => spawn(program, &path, args),
+                Ok(path) => exec(program, &path, args),
+                Err(err) => err,
+            };
+            last_err = Some((program, err));
+        }
+
+        // exec() or spawn() should not have returned. Like a shell, exit with 127 if the program wasn't
+        // found, or 126 if it couldn't be run
+        match last_err {
+            Some((program, err)) => {
+                eprintln!("lax: '{}': {}", program, err);
+                if err.kind() == io::ErrorKind::NotFound {
+                    process::exit(127);
+                }
+                process::exit(126);
+            }
+            None => {
+                eprintln!("lax: No program ran");
+                process::exit(1);
+            }
+        }
+    }
+}
+
+// Read the arguments in a file, one per line. Blank lines, and lines starting with '#', are
+// skipped. An argument that really starts with '#' is written as '\#'. Otherwise, arguments are
+// used as they are, so '@' patterns in the file, and their escapes, work like on the command line
+fn read_args_file(path: &str) -> Vec<String> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) => {
+            eprintln!("lax: Couldn't read arguments from '{}': {}", path, err);
+            process::exit(1);
+        }
+    };
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            line.strip_prefix('\\')
+                .filter(|rest| rest.starts_with('#'))
+                .unwrap_or(line)
+        })
+        .map(String::from)
+        .collect()
+}
+
+// Read arguments or candidates from stdin, one per line. Afterwards, the terminal becomes stdin,
+// if there is one, so the menu and the program can still read from it, like with `xargs -o`
+fn read_lines() -> Vec<String> {
+    let lines = match io::stdin().lines().collect::<io::Result<Vec<String>>>() {
+        Ok(lines) => lines,
+        Err(err) => {
+            eprintln!("lax: Couldn't read from stdin: {}", err);
+            process::exit(1);
+        }
+    };
+    #[cfg(unix)]
+    if let Ok(terminal) = std::fs::File::open("/dev/tty") {
+        use std::os::unix::io::AsRawFd;
+        // SAFETY: both file descriptors are open
+        unsafe { libc::dup2(terminal.as_raw_fd(), libc::STDIN_FILENO) };
+    }
+    lines
+        .into_iter()
+        .map(|line| line.trim_end_matches('\r').to_string())
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+// Ask about each path the '@' patterns in `args`, the arguments before any literal marker,
+// expanded to, and leave out the ones the user declines. Other arguments, including those after
+// the marker, are kept as they are
+fn confirm_each(
+    args: &[String],
+    expanded: Vec<String>,
+    reports: &[lax::PatternReport],
+) -> Vec<String> {
+    let mut expanded = expanded.into_iter();
+    let mut reports = reports.iter();
+    let mut confirm_all = false;
+    let mut confirmed = Vec::new();
+    for arg in args {
+        if !lax::is_pattern(arg) {
+            confirmed.extend(expanded.next());
+            continue;
+        }
+        let count = reports.next().map_or(0, |report| report.paths.len());
+        for path in expanded.by_ref().take(count) {
+            let answer = if confirm_all {
+                menu::Answer::Yes
+            } else {
+                menu::confirm(&path)
+            };
+            match answer {
+                menu::Answer::Yes => confirmed.push(path),
+                menu::Answer::No => (),
+                menu::Answer::All => {
+                    confirm_all = true;
+                    confirmed.push(path);
+                }
+            }
+        }
+    }
+    // What's left is the arguments after the literal marker
+    confirmed.extend(expanded);
+    confirmed
+}
+
+// Build the command to run the program at `path`, which it knows itself as `program`
+fn command(program: &str, path: &Path, args: &[String]) -> Command {
+    let mut command = Command::new(path);
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::arg0(&mut command, program);
+    #[cfg(not(unix))]
+    let _ = program;
+    command.args(args);
+    command
+}
+
+// Replace this process with the program. Only returns if the program couldn't be run
+#[cfg(unix)]
+fn exec(program: &str, path: &Path, args: &[String]) -> io::Error {
+    use std::os::unix::process::CommandExt;
+    command(program, path, args).exec()
+}
+
+// There's no exec() on Windows, so run the program as a child instead
+#[cfg(not(unix))]
+fn exec(program: &str, path: &Path, args: &[String]) -> io::Error {
+    spawn(program, path, args)
+}
+
+// Run the program as a child, wait for it, and exit with its exit status. Only returns if the
+// program couldn't be run
+fn spawn(program: &str, path: &Path, args: &[String]) -> io::Error {
+    signals::forward();
+    let mut child = match command(program, path, args).spawn() {
+        Ok(child) => child,
+        Err(err) => return err,
+    };
+    let _watch = signals::watch(child.id());
+    match child.wait() {
+        Ok(status) => process::exit(exit_code(status)),
+        Err(err) => err,
+    }
+}
+
+// Like a shell, report a program killed by a signal as 128 + the signal number
+fn exit_code(status: process::ExitStatus) -> i32 {
+    #[cfg(unix)]
+    if let Some(signal) = std::os::unix::process::ExitStatusExt::signal(&status) {
+        return 128 + signal;
+    }
+    status.code().unwrap_or(1)
+}
+
+// Report an error and exit with a code that tells scripts what kind of failure it was:
+// 2 if a pattern matched nothing, 3 if a pattern or selector is invalid, 4 if a pattern's entry
+// point doesn't exist, 5 if a pattern is ambiguous and there's no menu, 6 if a pattern matched
+// more paths than `max_matches` and going on wasn't confirmed, or 1 for anything else
+fn fail(err: anyhow::Error) -> ! {
+    // The user already knows they quit the menu
+    if let Some(LaxError::Cancelled) = err.downcast_ref::<LaxError>() {
+        process::exit(1);
+    }
+    eprintln!("lax: {}", err);
+    let code = match err.downcast_ref::<LaxError>() {
+        Some(LaxError::NoMatches(..) | LaxError::SelectorOutOfRange(_)) => 2,
+        Some(LaxError::InvalidSelector(_) | LaxError::InvalidPattern(_)) => 3,
+        Some(LaxError::EntryPointMissing(_) | LaxError::RepositoryRootNotFound(_)) => 4,
+        Some(LaxError::AmbiguousPattern(..)) => 5,
+        Some(LaxError::TooManyMatches(..)) => 6,
+        _ => 1,
+    };
+    process::exit(code)
+}
+
+// Expand the `--chdir` argument to a single directory and move into it. Returns how it was
+// expanded, which is left to the caller to record
+fn change_directory(
+    expander: &mut lax::Expander,
+    directory: &str,
+) -> anyhow::Result<Vec<lax::PatternReport>> {
+    let (directories, reports) = expander.expand_arguments_unrecorded(&[directory.to_string()])?;
+    let [directory] = directories.as_slice() else {
+        anyhow::bail!(
+            "--chdir expects a single directory, but got {}",
+            directories.len()
+        );
+    };
+    env::set_current_dir(directory)
+        .map_err(|err| anyhow::anyhow!("Could not change directory to '{}': {}", directory, err))?;
+    Ok(reports)
+}
+
+// Print a single JSON line describing how a pattern would resolve, and return the exit code: 0 if
+// unambiguous, 1 if ambiguous, 2 if unmatched, or 3 if the pattern couldn't be evaluated
+fn probe(expander: &lax::Expander, pattern: &str) -> i32 {
+    let (status, paths, exit_code) = match expander.probe(pattern) {
+        Ok(lax::Probe::Unambiguous(paths)) => ("unambiguous", paths, 0),
+        Ok(lax::Probe::Ambiguous(paths)) => ("ambiguous", paths, 1),
+        Ok(lax::Probe::Unmatched) => ("unmatched", Vec::new(), 2),
+        Err(err) => {
+            let error = json::object([
+                ("status", json::string("error")),
+                ("error", json::string(&err.to_string())),
+            ]);
+            println!("{}", error);
+            return 3;
+        }
+    };
+    let result = json::object([
+        ("status", json::string(status)),
+        (
+            "paths",
+            json::array(paths.iter().map(|path| json::string(path))),
+        ),
+    ]);
+    println!("{}", result);
+    exit_code
+}
+
+// Print the transformed args, and the metadata of what each '@' pattern expanded to, as JSON
+fn print_json(args: &[String], reports: &[lax::PatternReport]) {
+    let path = |path: &String| {
+        let metadata = std::fs::metadata(path).ok();
+        let (is_dir, size, mtime) = match &metadata {
+            Some(metadata) => (
+                metadata.is_dir().to_string(),
+                metadata.len().to_string(),
+                metadata
+                    .modified()
+                    .ok()
+                    .and_then(|mtime| mtime.duration_since(std::time::UNIX_EPOCH).ok())
+                    .map_or("null".into(), |mtime| mtime.as_secs().to_string()),
+            ),
+            None => ("null".into(), "null".into(), "null".into()),
+        };
+        json::object([
+            ("path", json::string(path)),
+            ("is_dir", is_dir),
+            ("size", size),
+            ("mtime", mtime),
+        ])
+    };
+    let pattern = |report: &lax::PatternReport| {
+        json::object([
+            ("pattern", json::string(&report.pattern)),
+            ("paths", json::array(report.paths.iter().map(path))),
+        ])
+    };
+
+    println!(
+        "{}",
+        json::object([
+            (
+                "args",
+                json::array(args.iter().map(|arg| json::string(arg)))
+            ),
+            ("patterns", json::array(reports.iter().map(pattern))),
+        ])
+    );
+}
+
+// Print how each '@' pattern was expanded
+fn explain(reports: &[lax::PatternReport]) {
+    for report in reports {
+        println!("{}", report.pattern);
+        if let Some(expanded_as) = &report.expanded_as {
+            println!("    [{}]", describe_pattern(expanded_as));
+        }
+        for path in &report.paths {
+            println!("    {}", path);
         }
+        let timings = &report.timings;
+        println!(
+            "    [parse {:?}, root detection {:?}, walk {:?}, selection {:?}, post-transform {:?}]",
+            timings.parse,
+            timings.root_detection,
+            timings.walk,
+            timings.selection,
+            timings.post_transform
+        );
+    }
+}
+
+// Describe the parts a pattern was parsed into, like "entry point src, glob *.rs, selectors 1"
+fn describe_pattern(pattern: &lax::pattern::Pattern) -> String {
+    use lax::pattern::Modifier;
 
-        // exec() should not have returned
-        if let Some(err_message) = err_message {
-            eprintln!("lax: {}", err_message);
-        } else {
-            eprintln!("lax: No program ran");
+    let mut parts = Vec::new();
+    match pattern.only {
+        None => {}
+        Some(lax::pattern::EntryKind::Files) => parts.push("only files".to_string()),
+        Some(lax::pattern::EntryKind::Directories) => parts.push("only directories".to_string()),
+    }
+    match &pattern.modifier {
+        None => {}
+        Some(Modifier::RepositoryRoot) => parts.push("from the repository root".into()),
+        Some(Modifier::OutermostRepositoryRoot) => {
+            parts.push("from the outermost repository root".into())
         }
+        Some(Modifier::Bookmark(name)) => parts.push(format!("from bookmark {}", name)),
+        Some(Modifier::Zoxide(query)) => parts.push(format!("from zoxide's pick for {}", query)),
+        Some(Modifier::Recent) => parts.push("from recent selections".into()),
+        Some(Modifier::History(index)) => parts.push(format!("from history entry {}", index)),
+    }
+    if !matches!(pattern.modifier, Some(Modifier::History(_))) {
+        parts.push(format!("entry point {}", pattern.entry_point));
+        parts.push(format!("glob {}", pattern.glob));
+    }
+    if let Some(selectors) = &pattern.selectors {
+        parts.push(format!("selectors {}", selectors));
+    }
+    if !pattern.filename_modifiers.is_empty() {
+        let modifiers: Vec<String> = pattern
+            .filename_modifiers
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        parts.push(format!("modifiers {}", modifiers.concat()));
+    }
+    parts.join(", ")
+}
+
+// Report how much searching each '@' pattern took, to help tune slow patterns
+fn print_stats(reports: &[lax::PatternReport]) {
+    for report in reports {
+        let stats = &report.stats;
+        eprintln!(
+            "lax: {}: walked {} directories and {} files, {} matched [glob compile {:?}, walk {:?}]",
+            report.pattern,
+            stats.directories,
+            stats.files,
+            stats.matched,
+            report.timings.glob_compile,
+            report.timings.walk
+        );
+    }
+}
+
+// Print version info in the format requested with `--version=FORMAT`
+fn print_version(format: &str) {
+    if format != "json" {
+        eprintln!("lax: Unknown version format '{}'", format);
         process::exit(1);
     }
+
+    let mut features = Vec::new();
+    if cfg!(feature = "config") {
+        features.push("config");
+    }
+    if cfg!(feature = "regex") {
+        features.push("regex");
+    }
+    if cfg!(feature = "tilde") {
+        features.push("tilde");
+    }
+    if cfg!(windows) {
+        features.push("windows");
+    }
+    let path = |path: Option<std::path::PathBuf>| match path {
+        Some(path) => json::string(&path.to_string_lossy()),
+        None => "null".into(),
+    };
+
+    println!(
+        "{{\"name\":{},\"version\":{},\"protocol\":{},\"features\":{},\"config_path\":{},\"history_path\":{}}}",
+        json::string("lax"),
+        json::string(env!("CARGO_PKG_VERSION")),
+        PROTOCOL_VERSION,
+        json::array(features.into_iter().map(json::string)),
+        path(lax::config_file::default_config_path()),
+        path(lax::history::default_history_path()),
+    );
 }
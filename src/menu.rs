@@ -0,0 +1,485 @@
+//! The menus used to choose between multiple matches: a line-based one, where the user types
+//! selectors, a checklist, and a list to edit in the user's editor.
+use std::{
+    env, fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    process::{self, Command},
+};
+
+use lax::{errors::LaxResult, Candidate, MenuOutcome, SelectionMenu};
+
+use crate::{temp, terminal::Terminal};
+
+/// The menu on the terminal: a numbered list of paths, and a prompt for a selector.
+pub struct LineMenu {
+    /// What answering with nothing selects, as in [`lax::Config::menu_default`], to show in the
+    /// prompt
+    pub default: Option<String>,
+}
+
+impl SelectionMenu for LineMenu {
+    fn select(
+        &mut self,
+        candidates: &[Candidate],
+        attempt: u32,
+        more: bool,
+    ) -> LaxResult<MenuOutcome> {
+        select(candidates, attempt == 0, more, self.default.as_deref())
+    }
+
+    fn confirm_many(&mut self, pattern: &str, limit: usize) -> LaxResult<bool> {
+        let terminal = Terminal::get();
+        eprint!(
+            "Pattern \"{pattern}\" matches more than {limit} paths. Expand it anyway? {}",
+            terminal.style("1", "[y/n]>")
+        );
+        let mut answer = String::new();
+        // Nothing left to read means there's nobody to answer, so don't
+        io::stdin().read_line(&mut answer)?;
+        Ok(matches!(answer.trim(), "y" | "yes"))
+    }
+}
+
+// How many paths the line menu lists at a time
+const PAGE_SIZE: usize = 20;
+
+// Show the user a list of paths, a page at a time, and ask them for a selector. If `more` is set,
+// the list was cut short, and the user can ask for more.
+fn select(
+    candidates: &[Candidate],
+    first_call: bool,
+    more: bool,
+    default: Option<&str>,
+) -> LaxResult<MenuOutcome> {
+    let terminal = Terminal::get();
+    let pages = candidates.len().div_ceil(PAGE_SIZE);
+    let mut page = 0;
+    let mut show = first_call;
+    loop {
+        if show {
+            show_page(candidates, page, more);
+        }
+        let prompt = match default {
+            Some(default) => format!("Select [{default}]> "),
+            None => "Select> ".into(),
+        };
+        eprint!("{}", terminal.style("1", &prompt));
+
+        let mut option = String::new();
+        let read = io::stdin().read_line(&mut option)?;
+
+        // Allow user to quit. Nothing left to read means there's nobody to answer, so quit then
+        // too
+        if read == 0 || option.starts_with('q') {
+            return Ok(MenuOutcome::Cancelled);
+        }
+        if let Some(id) = option.trim().strip_prefix('v') {
+            match id
+                .trim()
+                .parse()
+                .ok()
+                .and_then(|id: usize| candidates.iter().find(|candidate| candidate.id == id))
+            {
+                Some(candidate) => {
+                    for line in preview(&candidate.matched.path) {
+                        eprintln!("  {line}");
+                    }
+                }
+                None => eprintln!("Preview which? Like: v 2"),
+            }
+            show = false;
+            continue;
+        }
+        // Turning the page is up to the menu. 'n' already selects the newest path, so pages are
+        // turned with '>' and '<'
+        show = match option.trim() {
+            ">" if page + 1 < pages => {
+                page += 1;
+                true
+            }
+            "<" if page > 0 => {
+                page -= 1;
+                true
+            }
+            ">" | "<" => false,
+            _ => return Ok(MenuOutcome::Selectors(option)),
+        };
+    }
+}
+
+// List one page of the candidates. Their numbers are the same on every page, so any of them can be
+// selected from any page
+fn show_page(candidates: &[Candidate], page: usize, more: bool) {
+    let terminal = Terminal::get();
+    eprintln!("Found the following:");
+    eprintln!("====================");
+    for candidate in candidates.iter().skip(page * PAGE_SIZE).take(PAGE_SIZE) {
+        let id = format!("{}.", candidate.id);
+        eprintln!("{} {}", terminal.style("1", &id), display_path(candidate));
+    }
+    let pages = candidates.len().div_ceil(PAGE_SIZE);
+    if pages > 1 {
+        eprintln!(
+            "Page {} of {} ({} matches): > next page, < previous page",
+            page + 1,
+            pages,
+            candidates.len()
+        );
+    }
+    if more {
+        eprintln!("+more (press m to load)");
+    }
+    eprintln!("Selectors: 2, -1, 1..3, a, /regex, a,!2,!5 (all but 2 and 5), q (quit)");
+    eprintln!("Sort with: s name, s natural, s mtime, s size, s depth");
+    eprintln!("Preview with: v 2, search again with: e *.toml");
+}
+
+/// The checklist menu on the terminal: the user moves with the arrow keys (or j and k), checks
+/// paths with space (or all of them with a), and confirms with Enter. Terminals that can't be
+/// redrawn get the line menu instead.
+pub struct ChecklistMenu(pub LineMenu);
+
+impl SelectionMenu for ChecklistMenu {
+    fn select(
+        &mut self,
+        candidates: &[Candidate],
+        attempt: u32,
+        more: bool,
+    ) -> LaxResult<MenuOutcome> {
+        #[cfg(unix)]
+        if Terminal::get().cursor {
+            if let Ok(raw_mode) = crate::terminal::RawMode::enable() {
+                let outcome = checklist(candidates, more);
+                drop(raw_mode);
+                return outcome;
+            }
+        }
+        self.0.select(candidates, attempt, more)
+    }
+
+    fn confirm_many(&mut self, pattern: &str, limit: usize) -> LaxResult<bool> {
+        self.0.confirm_many(pattern, limit)
+    }
+}
+
+// Show the candidates with checkboxes, and redraw them after each key until the user is done
+#[cfg(unix)]
+fn checklist(candidates: &[Candidate], more: bool) -> LaxResult<MenuOutcome> {
+    use io::Write;
+    use lax::checklist::{Checklist, Key};
+
+    let terminal = Terminal::get();
+    let mut checklist = Checklist::new(candidates);
+    let mut stderr = io::stderr();
+    let mut drawn = 0;
+    let mut previewing = false;
+    loop {
+        // Go back up over the last drawing, and clear it
+        let mut screen = String::new();
+        if drawn > 0 {
+            screen.push_str(&format!("\x1b[{drawn}A"));
+        }
+        screen.push_str("\r\x1b[J");
+        for (position, candidate) in candidates.iter().enumerate() {
+            let mark = format!(
+                "{} [{}] {}.",
+                if position == checklist.cursor() {
+                    '>'
+                } else {
+                    ' '
+                },
+                if checklist.is_checked(position) {
+                    'x'
+                } else {
+                    ' '
+                },
+                candidate.id,
+            );
+            if position == checklist.cursor() {
+                screen.push_str(&terminal.style("1", &mark));
+            } else {
+                screen.push_str(&mark);
+            }
+            screen.push_str(&format!(" {}\r\n", display_path(candidate)));
+        }
+        if more {
+            screen.push_str("+more (press m to load)\r\n");
+        }
+        drawn = candidates.len() + usize::from(more);
+        // The candidate under the cursor, previewed below the list
+        if let Some(candidate) = candidates.get(checklist.cursor()).filter(|_| previewing) {
+            for line in preview(&candidate.matched.path) {
+                screen.push_str(&format!("  {line}\r\n"));
+                drawn += 1;
+            }
+        }
+        screen.push_str("Move: up/down, check: space, all: a, preview: v, confirm: Enter, quit: q");
+        write!(stderr, "{screen}")?;
+        stderr.flush()?;
+
+        // Arrow keys arrive as a whole escape sequence in one read
+        let mut buffer = [0u8; 8];
+        let read = io::stdin().read(&mut buffer)?;
+        let key = match &buffer[..read] {
+            // Nothing left to read means there's nobody to answer, so quit
+            [] => Key::Cancel,
+            b"\x1b[A" | b"\x1bOA" | b"k" => Key::Up,
+            b"\x1b[B" | b"\x1bOB" | b"j" => Key::Down,
+            b" " => Key::Toggle,
+            b"a" => Key::ToggleAll,
+            b"m" if more => Key::More,
+            b"v" => {
+                previewing = !previewing;
+                continue;
+            }
+            b"\r" | b"\n" => Key::Confirm,
+            // Escape, Ctrl-C, or Ctrl-D
+            b"q" | b"\x1b" | b"\x03" | b"\x04" => Key::Cancel,
+            _ => continue,
+        };
+        if let Some(outcome) = checklist.press(key) {
+            eprint!("\r\n");
+            return Ok(outcome);
+        }
+    }
+}
+
+/// The menu in the user's editor: the candidates are listed in a temporary file opened in
+/// `$VISUAL` or `$EDITOR`, and the ones still listed when the editor exits are used.
+pub struct EditorMenu {
+    /// What to ask anything other than which paths to use
+    pub line: LineMenu,
+    /// Where the list is written, as in [`lax::Config::temp_dir`]
+    pub temp_dir: PathBuf,
+}
+
+impl SelectionMenu for EditorMenu {
+    fn select(
+        &mut self,
+        candidates: &[Candidate],
+        _attempt: u32,
+        more: bool,
+    ) -> LaxResult<MenuOutcome> {
+        let list = temp::TempFile::create(&self.temp_dir, ".txt", &edit_list(candidates, more))?;
+        let editor = editor();
+        // Like git, the editor can be given with arguments, as in `code --wait`
+        let mut words = editor.split_whitespace();
+        let mut command = Command::new(words.next().unwrap_or("vi"));
+        command.args(words).arg(list.path());
+        let status = temp::foreground(|| command.status())?;
+        // Quitting the editor with an error, like vim's `:cq`, gives up
+        if !status.success() {
+            return Ok(MenuOutcome::Cancelled);
+        }
+        Ok(edited_selectors(&fs::read_to_string(list.path())?))
+    }
+
+    fn confirm_many(&mut self, pattern: &str, limit: usize) -> LaxResult<bool> {
+        self.line.confirm_many(pattern, limit)
+    }
+}
+
+// The user's editor, as git finds it
+fn editor() -> String {
+    ["VISUAL", "EDITOR"]
+        .into_iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| if cfg!(windows) { "notepad" } else { "vi" }.into())
+}
+
+// The list of candidates the user edits, one per line after its selector ID
+fn edit_list(candidates: &[Candidate], more: bool) -> String {
+    let mut list = String::from(
+        "# Delete the lines of the paths you don't want, then save and quit.\n\
+         # Delete every line, or quit with an error, to give up.\n",
+    );
+    if more {
+        list.push_str(
+            "# There are more matches. Replace the list with a line saying m to load them.\n",
+        );
+    }
+    for candidate in candidates {
+        list.push_str(&format!(
+            "{} {}\n",
+            candidate.id,
+            candidate.matched.path.display()
+        ));
+    }
+    list
+}
+
+// What's left of the list once the user is done with it. Each line's first word is a selector, so
+// lines can be typed in as well as deleted
+fn edited_selectors(list: &str) -> MenuOutcome {
+    let selectors: Vec<&str> = list
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_whitespace().next())
+        .collect();
+    if selectors.is_empty() {
+        MenuOutcome::Cancelled
+    } else {
+        MenuOutcome::Selectors(selectors.join(","))
+    }
+}
+
+// How many lines of a file, or entries of a directory, are previewed
+const PREVIEW_LINES: usize = 10;
+// How much of a file is read to preview it
+const PREVIEW_BYTES: u64 = 16 * 1024;
+
+// The first lines of a file, or a short listing of a directory, for the menu to preview
+fn preview(path: &Path) -> Vec<String> {
+    if path.is_dir() {
+        let mut names: Vec<String> = match fs::read_dir(path) {
+            Ok(entries) => entries
+                .flatten()
+                .map(|entry| {
+                    let mut name = entry.file_name().to_string_lossy().into_owned();
+                    if entry.path().is_dir() {
+                        name.push(std::path::MAIN_SEPARATOR);
+                    }
+                    name
+                })
+                .collect(),
+            Err(err) => return vec![format!("(can't list: {err})")],
+        };
+        if names.is_empty() {
+            return vec!["(empty directory)".into()];
+        }
+        names.sort();
+        let more = names.len().saturating_sub(PREVIEW_LINES);
+        names.truncate(PREVIEW_LINES);
+        if more > 0 {
+            names.push(format!("… and {more} more"));
+        }
+        return names;
+    }
+
+    let mut contents = Vec::new();
+    if let Err(err) =
+        fs::File::open(path).and_then(|file| file.take(PREVIEW_BYTES).read_to_end(&mut contents))
+    {
+        return vec![format!("(can't read: {err})")];
+    }
+    if contents.contains(&0) {
+        return vec!["(binary file)".into()];
+    }
+    if contents.is_empty() {
+        return vec!["(empty file)".into()];
+    }
+    String::from_utf8_lossy(&contents)
+        .lines()
+        .take(PREVIEW_LINES)
+        // Tabs and control characters would throw off the menu's drawing
+        .map(|line| {
+            line.replace('\t', "    ")
+                .chars()
+                .filter(|c| !c.is_control())
+                .collect()
+        })
+        .collect()
+}
+
+// A path as the menu shows it: colored like `ls` would, with the part the pattern named underlined
+fn display_path(candidate: &Candidate) -> String {
+    let terminal = Terminal::get();
+    let path = candidate.matched.path.display().to_string();
+    if !terminal.color {
+        return path;
+    }
+    let style = |sgr: &str, text: &str| {
+        if sgr.is_empty() || text.is_empty() {
+            text.to_string()
+        } else {
+            terminal.style(sgr, text)
+        }
+    };
+    let color = terminal.ls_colors.color(&candidate.matched.path);
+    let part = &candidate.matched_part;
+    let (Some(before), Some(named), Some(after)) = (
+        path.get(..part.start),
+        path.get(part.clone()),
+        path.get(part.end..),
+    ) else {
+        return style(color, &path);
+    };
+    let underlined = if color.is_empty() {
+        "4".to_string()
+    } else {
+        format!("{color};4")
+    };
+    format!(
+        "{}{}{}",
+        style(color, before),
+        style(&underlined, named),
+        style(color, after)
+    )
+}
+
+/// An answer to [`confirm`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Answer {
+    /// Include this path.
+    Yes,
+    /// Leave this path out.
+    No,
+    /// Include this path and every one after it, without asking.
+    All,
+}
+
+/// Ask the user whether to include a path, like `rm -i`. Exits if they quit.
+pub fn confirm(path: &str) -> Answer {
+    let terminal = Terminal::get();
+    loop {
+        eprint!("{} {} ", path, terminal.style("1", "[y/n/q/a]>"));
+        let mut answer = String::new();
+        let read = io::stdin()
+            .read_line(&mut answer)
+            .expect("Failed to read from stdin");
+        // Nothing left to read means there's nobody to answer, so quit
+        if read == 0 {
+            process::exit(1);
+        }
+        match answer.trim() {
+            "y" | "yes" => return Answer::Yes,
+            "n" | "no" => return Answer::No,
+            "a" | "all" => return Answer::All,
+            "q" | "quit" => process::exit(1),
+            _ => eprintln!("Answer y (yes), n (no), q (quit), or a (yes to all)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn previews() {
+        let sep = std::path::MAIN_SEPARATOR;
+        assert_eq!(
+            preview(Path::new("tests/foobar")),
+            vec![
+                format!("another_directory{sep}"),
+                "foo".into(),
+                "fox".into(),
+                format!("this_is_a_directory{sep}"),
+            ]
+        );
+        assert_eq!(preview(Path::new("tests/foobar/foo")), vec!["(empty file)"]);
+        assert_eq!(preview(Path::new("Cargo.toml"))[0], "[package]");
+        assert_eq!(preview(Path::new("src/lib.rs")).len(), PREVIEW_LINES);
+    }
+
+    #[test]
+    fn edited_lists() {
+        let list = "# Comment\n3 src/main.rs\n\n  1 a path with spaces\n";
+        assert_eq!(edited_selectors(list), MenuOutcome::Selectors("3,1".into()));
+        assert_eq!(edited_selectors("m\n"), MenuOutcome::Selectors("m".into()));
+        assert_eq!(edited_selectors("# Nothing left\n"), MenuOutcome::Cancelled);
+    }
+}
@@ -0,0 +1,529 @@
+//! Splitting '@' patterns into their parts: where the search starts, the entry point, the glob
+//! pattern, selectors, and modifiers. Errors point at the part of the pattern at fault.
+
+use std::{borrow::Cow, env, ops::Range};
+
+use globset::{ErrorKind, GlobBuilder, GlobMatcher};
+
+use crate::{
+    pattern::EntryKind, transform::FilenameModifier, Expander, LaxError, Origin, Result, RootSearch,
+};
+
+// An '@' pattern's origin, entry point, glob pattern, selector group, and the kind of entries it
+// matches, unescaped
+pub(crate) type ParsedPattern<'a> = (
+    Origin,
+    Cow<'a, str>,
+    Cow<'a, str>,
+    Option<&'a str>,
+    Option<EntryKind>,
+);
+
+// A result whose error also says which bytes of the text being parsed are at fault
+pub(crate) type Spanned<T> = std::result::Result<T, (LaxError, Range<usize>)>;
+
+// Characters a '\' escapes in a pattern. Other escapes, like "\*", are left for the glob
+pub(crate) const ESCAPABLE: &[char] = &['^', '%', '/', '|', '$'];
+
+// Split the exclusion off a glob pattern, as in "*.rs!**/generated/**", and compile it. Like the
+// pattern, it matches anywhere under the entry point
+pub(crate) fn split_exclusion(pattern: &str) -> Result<(&str, Option<GlobMatcher>)> {
+    let Some(index) = find_unescaped(pattern, "!").filter(|&index| index > 0) else {
+        return Ok((pattern, None));
+    };
+    let exclusion = &pattern[index + 1..];
+    if exclusion.is_empty() {
+        return Err(LaxError::InvalidPattern(
+            "Expected a glob pattern after '!'".into(),
+        ));
+    }
+    let exclusion = GlobBuilder::new(&format!("**/{exclusion}"))
+        .literal_separator(true)
+        .build()
+        .map_err(|err| LaxError::InvalidPattern(err.to_string()))?
+        .compile_matcher();
+    Ok((&pattern[..index], Some(exclusion)))
+}
+
+// How many components at the end of a glob pattern's matches it names after its last `**`, not
+// counting its exclusion
+pub(crate) fn named_components(glob_pattern: &str) -> usize {
+    let end = find_unescaped(glob_pattern, "!")
+        .filter(|&index| index > 0)
+        .unwrap_or(glob_pattern.len());
+    let glob_pattern = glob_pattern[..end].trim_end_matches('/');
+    let named = match glob_pattern.rsplit_once("**/") {
+        Some((_, named)) => named,
+        None => glob_pattern,
+    };
+    named.split('/').count()
+}
+
+// Split the filename modifiers off the end of a pattern, as in "@main.rs^1:t:r". A ':' before the
+// letter can be escaped, for paths that really end like that
+pub(crate) fn split_modifiers(pattern: &str) -> (&str, Vec<FilenameModifier>) {
+    let mut rest = pattern;
+    let mut modifiers = Vec::new();
+    while let Some((head, letter)) = rest.rsplit_once(':') {
+        let mut letters = letter.chars();
+        let (Some(modifier), None) = (
+            letters.next().and_then(FilenameModifier::from_letter),
+            letters.next(),
+        ) else {
+            break;
+        };
+        // Keep the '@', whatever the modifier would otherwise be part of, and a ':' that ends a
+        // prefix, as in "@f:h", which is the file "h"
+        if head.len() < 2 || head.ends_with('\\') || is_prefix(&head[1..]) {
+            break;
+        }
+        modifiers.push(modifier);
+        rest = head;
+    }
+    modifiers.reverse();
+    (rest, modifiers)
+}
+
+// Is this the start of a pattern, after its '@', that a ':' would make a prefix of?
+fn is_prefix(head: &str) -> bool {
+    let rest = head
+        .strip_prefix("f:")
+        .or_else(|| head.strip_prefix("d:"))
+        .unwrap_or(head);
+    matches!(rest, "z" | "recent") || (rest.len() == head.len() && matches!(rest, "f" | "d"))
+}
+
+// Split a pattern into its '|'-separated alternatives, each a pattern of its own with the
+// original's selectors
+pub(crate) fn split_alternatives(pattern: &str) -> Vec<Cow<'_, str>> {
+    let (head, selectors) = pattern.split_at(find_unescaped(pattern, "^").unwrap_or(pattern.len()));
+    let Some(mut rest) = head.strip_prefix('@') else {
+        return vec![Cow::Borrowed(pattern)];
+    };
+    if find_unescaped(rest, "|").is_none() {
+        return vec![Cow::Borrowed(pattern)];
+    }
+    let mut alternatives = Vec::new();
+    while let Some(index) = find_unescaped(rest, "|") {
+        alternatives.push(Cow::Owned(format!("@{}{selectors}", &rest[..index])));
+        rest = &rest[index + 1..];
+    }
+    alternatives.push(Cow::Owned(format!("@{rest}{selectors}")));
+    alternatives
+}
+
+// Find the first `needle` in a pattern that isn't escaped, or inside a "{a,b}" alternation group
+// or "[a-z]" character class
+pub(crate) fn find_unescaped(pattern: &str, needle: &str) -> Option<usize> {
+    let mut escaped = false;
+    let mut in_class = false;
+    let mut depth = 0usize;
+    for (index, character) in pattern.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if character == '\\' {
+            escaped = true;
+        } else if in_class {
+            in_class = character != ']';
+        } else if depth == 0 && pattern[index..].starts_with(needle) {
+            return Some(index);
+        } else if character == '[' {
+            in_class = true;
+        } else if character == '{' {
+            depth += 1;
+        } else if character == '}' {
+            depth = depth.saturating_sub(1);
+        }
+    }
+    None
+}
+
+// Expand the "{a,b}" alternation groups in an entry point into every path they stand for, like a
+// shell would. Groups may be nested, and an unclosed '{' is literal.
+pub(crate) fn expand_braces(pattern: &str) -> Vec<String> {
+    let mut escaped = false;
+    let mut depth = 0usize;
+    let mut open = 0;
+    let mut commas = Vec::new();
+    for (index, character) in pattern.char_indices() {
+        match character {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '{' => {
+                if depth == 0 {
+                    open = index;
+                    commas.clear();
+                }
+                depth += 1;
+            }
+            ',' if depth == 1 => commas.push(index),
+            '}' if depth > 0 => {
+                depth -= 1;
+                if depth > 0 {
+                    continue;
+                }
+                let (prefix, suffix) = (&pattern[..open], &pattern[index + 1..]);
+                let starts = std::iter::once(open).chain(commas.iter().copied());
+                let ends = commas.iter().copied().chain(std::iter::once(index));
+                return starts
+                    .zip(ends)
+                    .flat_map(|(start, end)| {
+                        expand_braces(&format!("{prefix}{}{suffix}", &pattern[start + 1..end]))
+                    })
+                    .collect();
+            }
+            _ => (),
+        }
+    }
+    vec![pattern.into()]
+}
+
+// Move an error's span from a part of a pattern to the whole pattern, given where the part starts
+pub(crate) fn shift(
+    (err, span): (LaxError, Range<usize>),
+    start: usize,
+) -> (LaxError, Range<usize>) {
+    (err, start + span.start..start + span.end)
+}
+
+// Add a caret diagram to an invalid pattern or selector error, pointing at the bytes of `source`
+// that are at fault, like:
+//
+//     Invalid selector: 'x'
+//         @src/**/*.rs^x,
+//                      ^
+pub(crate) fn point_at(err: LaxError, source: &str, span: Range<usize>) -> LaxError {
+    let diagram = |message: String| {
+        let indent = source[..span.start].chars().count();
+        let width = source[span.clone()].chars().count().max(1);
+        format!(
+            "{message}\n\t{source}\n\t{}{}",
+            " ".repeat(indent),
+            "^".repeat(width)
+        )
+    };
+    match err {
+        LaxError::InvalidSelector(message) => LaxError::InvalidSelector(diagram(message)),
+        LaxError::InvalidPattern(message) => LaxError::InvalidPattern(diagram(message)),
+        err => err,
+    }
+}
+
+// Remove the '\' from escaped metacharacters, and substitute environment variables written as
+// "$NAME" or "${NAME}", once the pattern has been split up. A '$' that doesn't start a variable
+// name is left alone. In a glob, the values are escaped, so a '*' or '[' in one matches itself
+pub(crate) fn unescape_and_expand(pattern: &str, glob: bool) -> Spanned<Cow<'_, str>> {
+    if !pattern.contains(['\\', '$']) {
+        return Ok(Cow::Borrowed(pattern));
+    }
+    let mut expanded = String::with_capacity(pattern.len());
+    let mut characters = pattern.char_indices();
+    while let Some((index, character)) = characters.next() {
+        match character {
+            '\\' => match characters.next() {
+                Some((_, next)) if ESCAPABLE.contains(&next) => expanded.push(next),
+                Some((_, next)) => {
+                    expanded.push('\\');
+                    expanded.push(next);
+                }
+                None => expanded.push('\\'),
+            },
+            '$' => {
+                let rest = &pattern[index + 1..];
+                let (name, len) = if let Some(braced) = rest.strip_prefix('{') {
+                    let end = braced.find('}').ok_or_else(|| {
+                        (
+                            LaxError::InvalidPattern(format!(
+                                "Expected a '}}' after '${{' in '{pattern}'"
+                            )),
+                            index..pattern.len(),
+                        )
+                    })?;
+                    (&braced[..end], end + 2)
+                } else if rest.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+                    let end = rest
+                        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                        .unwrap_or(rest.len());
+                    (&rest[..end], end)
+                } else {
+                    ("", 0)
+                };
+                if name.is_empty() {
+                    expanded.push('$');
+                    continue;
+                }
+                let value = env::var(name).map_err(|_| {
+                    (
+                        LaxError::InvalidPattern(format!(
+                            "Environment variable '{name}' is not set"
+                        )),
+                        index..index + 1 + len,
+                    )
+                })?;
+                if glob {
+                    expanded.push_str(&escape_glob(&value));
+                } else {
+                    expanded.push_str(&value);
+                }
+                for _ in 0..rest[..len].chars().count() {
+                    characters.next();
+                }
+            }
+            character => expanded.push(character),
+        }
+    }
+    Ok(Cow::Owned(expanded))
+}
+
+// Escape text so that, in a glob, it only matches itself. Metacharacters are put in a class of
+// their own, like "[*]". '\' is escaped too, except on Windows, where it's a separator instead
+fn escape_glob(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for character in text.chars() {
+        match character {
+            '*' | '?' | '[' | ']' | '{' | '}' => {
+                escaped.push('[');
+                escaped.push(character);
+                escaped.push(']');
+            }
+            '\\' if !cfg!(windows) => escaped.push_str("\\\\"),
+            character => escaped.push(character),
+        }
+    }
+    escaped
+}
+
+// Check that a glob pattern compiles, once it's been unescaped and had its variables substituted.
+// If it doesn't, the error points at the part of `raw`, the glob as it was written, that's at
+// fault, or at all of it if that part can't be found
+pub(crate) fn check_glob(raw: &str, glob: &str) -> Spanned<()> {
+    let Err(err) = GlobBuilder::new(glob).literal_separator(true).build() else {
+        return Ok(());
+    };
+    let span = match err.kind() {
+        ErrorKind::UnclosedClass => raw.rfind('[').map(|index| index..raw.len()),
+        ErrorKind::UnclosedAlternates => raw.rfind('{').map(|index| index..raw.len()),
+        ErrorKind::UnopenedAlternates => raw.find('}').map(|index| index..index + 1),
+        ErrorKind::InvalidRange(first, last) => {
+            let range = format!("{first}-{last}");
+            raw.find(&range).map(|index| index..index + range.len())
+        }
+        ErrorKind::DanglingEscape => raw.ends_with('\\').then(|| raw.len() - 1..raw.len()),
+        _ => None,
+    };
+    Err((
+        LaxError::InvalidPattern(format!("Invalid glob pattern: {}", err.kind())),
+        span.unwrap_or(0..raw.len()),
+    ))
+}
+
+// Windows paths may use '\' as a separator, but patterns are parsed and matched with '/'. A '\'
+// straight after the '@', or before a metacharacter, is still an escape, and selectors (which may
+// be regexes) are left alone.
+pub(crate) fn normalize_separators(pattern: &str) -> Cow<'_, str> {
+    if !cfg!(windows) || !pattern.contains('\\') {
+        return Cow::Borrowed(pattern);
+    }
+    let (pattern, selectors) =
+        pattern.split_at(find_unescaped(pattern, "^").unwrap_or(pattern.len()));
+    let prefix = if pattern.starts_with("@\\") { 2 } else { 1 };
+    let (prefix, pattern) = pattern.split_at(prefix.min(pattern.len()));
+    let mut normalized = String::from(prefix);
+    let mut characters = pattern.chars().peekable();
+    while let Some(character) = characters.next() {
+        match characters.peek() {
+            Some(&next) if character == '\\' && ESCAPABLE.contains(&next) => {
+                normalized.push(character);
+                normalized.push(next);
+                characters.next();
+            }
+            _ if character == '\\' => normalized.push('/'),
+            _ => normalized.push(character),
+        }
+    }
+    normalized.push_str(selectors);
+    Cow::Owned(normalized)
+}
+
+// Does a pattern start with a Windows drive, like "C:/"? These aren't zoxide queries.
+pub(crate) fn has_drive_letter(pattern: &str) -> bool {
+    cfg!(windows)
+        && matches!(pattern.as_bytes(), [letter, b':', b'/', ..] if letter.is_ascii_alphabetic())
+}
+
+impl Expander {
+    // Parse an @ pattern into its subcomponents
+    //
+    // A '\' escapes a '^', '%', '/', '|', or '$' that would otherwise be special, anywhere in the
+    // pattern. Environment variables, as in "$HOME" or "${HOME}", are substituted in the entry
+    // point and glob pattern.
+    //
+    // '@' patterns are in the form:
+    // @[%|%%|:BOOKMARK/|z:QUERY/|recent:][ENTRY_POINT/**/]GLOB_PATTERN[^SELECTOR_GROUP]
+    // or
+    // @![N][^SELECTOR_GROUP]
+    //
+    // Where [%|%%|:BOOKMARK/|z:QUERY/|recent:][ENTRY_POINT/**/]GLOB_PATTERN (or !N, for the Nth previous
+    // invocation)
+    // expands into multiple paths, and a selector group(possibly SELECTOR_GROUP) is used to narrow
+    // them down
+    //
+    // If the pattern is invalid, the error points at the part of it that's at fault.
+    pub(crate) fn parse_pattern(pattern: &str) -> Result<ParsedPattern<'_>> {
+        Self::parse_pattern_spanned(pattern).map_err(|(err, span)| point_at(err, pattern, span))
+    }
+
+    // Parse an @ pattern like `parse_pattern`, but on failure, also return which bytes of the
+    // pattern are at fault
+    pub(crate) fn parse_pattern_spanned(pattern: &str) -> Spanned<ParsedPattern<'_>> {
+        // Git rid of '@' symbol
+        let pattern = &pattern[1..];
+
+        if pattern.is_empty() {
+            return Err((
+                LaxError::InvalidPattern(
+                    "Empty pattern - nothing specified after '@' symbol".into(),
+                ),
+                1..1,
+            ));
+        }
+
+        // Selectors start at the first '^' that isn't escaped
+        let (pattern, selectors) = match find_unescaped(pattern, "^") {
+            Some(index) => (&pattern[..index], pattern[index + 1..].split('^').next()),
+            None => (pattern, None),
+        };
+
+        // Modifiers are parsed off the front, so what's left is always the end of the pattern,
+        // before the selectors. This is where it starts in the full pattern
+        let end = 1 + pattern.len();
+        let start = |rest: &str| end - rest.len();
+
+        // The files-only and directories-only modifiers, which come before any other. A
+        // Windows path starting from a drive isn't one
+        let kind = |prefix, kind| {
+            pattern
+                .strip_prefix(prefix)
+                .filter(|_| !has_drive_letter(pattern))
+                .map(|pattern| (pattern, Some(kind)))
+        };
+        let (pattern, only) = kind("f:", EntryKind::Files)
+            .or_else(|| kind("d:", EntryKind::Directories))
+            .unwrap_or((pattern, None));
+        let modifier_start = start(pattern);
+
+        // The "from repository root" modifier. This enables us to start the search from the root
+        // of the git/svn/hg/etc. repository. Doubled, it's the outermost root instead of the
+        // nearest, for nested repositories
+        let (pattern, origin) = if let Some(pattern) = pattern.strip_prefix("%%") {
+            (pattern, Origin::RepositoryRoot(RootSearch::Outermost))
+        } else if let Some(pattern) = pattern.strip_prefix('%') {
+            (pattern, Origin::RepositoryRoot(RootSearch::Nearest))
+        // The bookmark modifier. The bookmark's name extends up to the first '/', and the rest of
+        // the pattern is searched for from the bookmark's directory.
+        } else if let Some(pattern) = pattern.strip_prefix(':') {
+            let (name, pattern) = pattern.split_once('/').unwrap_or((pattern, ""));
+            if name.is_empty() {
+                return Err((
+                    LaxError::InvalidPattern("Expected a bookmark name after ':'".into()),
+                    modifier_start..modifier_start + 1,
+                ));
+            }
+            (pattern, Origin::Bookmark(name.into()))
+        // The zoxide modifier, which works like a bookmark, but asks zoxide for the directory
+        } else if let Some(pattern) = pattern
+            .strip_prefix("z:")
+            .filter(|_| !has_drive_letter(pattern))
+        {
+            let (query, pattern) = pattern.split_once('/').unwrap_or((pattern, ""));
+            if query.is_empty() {
+                return Err((
+                    LaxError::InvalidPattern("Expected a zoxide query after 'z:'".into()),
+                    modifier_start..modifier_start + 2,
+                ));
+            }
+            (pattern, Origin::Zoxide(query.into()))
+        // The recent modifier, which matches against recently selected paths rather than walking
+        // the filesystem
+        } else if let Some(pattern) = pattern.strip_prefix("recent:") {
+            (pattern, Origin::Recent)
+        // The history modifier, which reuses the paths from a previous invocation
+        } else if let Some(index) = pattern.strip_prefix('!') {
+            let index = if index.is_empty() {
+                1
+            } else {
+                index.parse().map_err(|_| {
+                    (
+                        LaxError::InvalidPattern(format!("Invalid history index: '{index}'")),
+                        start(index)..end,
+                    )
+                })?
+            };
+            ("", Origin::History(index))
+        // Faux "escape modifier" modifier, so we can escape what would otherwise be considered a
+        // modifier. A leading "\/" or "\$" is left for the unescape pass, so it isn't read as the
+        // root or a variable
+        } else if let Some(pattern) = pattern
+            .strip_prefix('\\')
+            .filter(|pattern| !pattern.starts_with(['/', '$']))
+        {
+            (pattern, Origin::CurrentDirectory)
+        } else {
+            (pattern, Origin::CurrentDirectory)
+        };
+
+        let entry_point_start = start(pattern);
+
+        // An exclusion, as in "**/*.rs!**/generated/**", stays with the glob pattern, and its own
+        // "/**/" isn't taken for the entry point
+        let (pattern, exclusion) = match find_unescaped(pattern, "!").filter(|&index| index > 0) {
+            Some(index) => pattern.split_at(index),
+            None => (pattern, ""),
+        };
+
+        // Extract entry_point and glob pattern, split at the first "/**/" that isn't escaped
+        let glob_start = entry_point_start
+            + find_unescaped(pattern, "/**/").map_or(0, |index| index + "/**/".len());
+        let (entry_point, glob_pattern) = match find_unescaped(pattern, "/**/") {
+            None => (".", pattern),
+            Some(index) => {
+                let (entry_point, glob_pattern) = (&pattern[..index], &pattern[index + 4..]);
+                (
+                    // Root is an expected default in this case, even if it's not very useful
+                    if entry_point.is_empty() {
+                        "/"
+                    } else {
+                        entry_point
+                    },
+                    // If no glob pattern is given, we should match all directories, since we end
+                    // with '/**/'
+                    if glob_pattern.is_empty() {
+                        "*/"
+                    } else {
+                        glob_pattern
+                    },
+                )
+            }
+        };
+
+        let entry_point =
+            unescape_and_expand(entry_point, false).map_err(|err| shift(err, entry_point_start))?;
+        let raw_glob = glob_pattern;
+        let glob_pattern =
+            unescape_and_expand(glob_pattern, true).map_err(|err| shift(err, glob_start))?;
+        check_glob(raw_glob, &glob_pattern).map_err(|err| shift(err, glob_start))?;
+        let glob_pattern = if exclusion.is_empty() {
+            glob_pattern
+        } else {
+            let exclusion_start = start(exclusion);
+            let raw_exclusion = exclusion;
+            let exclusion =
+                unescape_and_expand(exclusion, true).map_err(|err| shift(err, exclusion_start))?;
+            // Past the '!'
+            check_glob(&raw_exclusion[1..], &exclusion[1..])
+                .map_err(|err| shift(err, exclusion_start + 1))?;
+            Cow::Owned(format!("{glob_pattern}{exclusion}"))
+        };
+        Ok((origin, entry_point, glob_pattern, selectors, only))
+    }
+}
@@ -0,0 +1,260 @@
+//! '@' patterns as values, so they can be built and inspected programmatically.
+//!
+//! A [`Pattern`] parses from the same syntax lax accepts on the command line, and displays back
+//! to an equivalent string, so it can be handed to an [`Expander`].
+use std::{fmt, str::FromStr};
+
+use crate::{
+    errors::{LaxError, LaxResult as Result},
+    is_pattern, normalize_separators, split_alternatives, split_modifiers,
+    transform::FilenameModifier,
+    Expander, Origin, RootSearch, SelectorGroup,
+};
+
+/// Where a pattern's search starts from, if not the current directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Modifier {
+    /// The root of the repository we're in, written `%`.
+    RepositoryRoot,
+    /// The outermost root of nested repositories, written `%%`.
+    OutermostRepositoryRoot,
+    /// A bookmark from the config, written `:NAME/`.
+    Bookmark(String),
+    /// The directory zoxide picks for a query, written `z:QUERY/`.
+    Zoxide(String),
+    /// Recently selected paths, written `recent:`.
+    Recent,
+    /// The paths the nth most recent invocation expanded to, written `!N`. These patterns have
+    /// no entry point or glob.
+    History(usize),
+}
+
+/// Which kind of entries a pattern matches, regardless of the config.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EntryKind {
+    /// Only files, written `f:`.
+    Files,
+    /// Only directories, written `d:`.
+    Directories,
+}
+
+/// A single '@' pattern, without alternatives.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    /// Which kind of entries are matched, or `None` for what the config says.
+    pub only: Option<EntryKind>,
+    /// Where the search starts from, or `None` for the current directory.
+    pub modifier: Option<Modifier>,
+    /// The directory searched, relative to where the search starts. `.` if the pattern doesn't
+    /// name one.
+    pub entry_point: String,
+    /// The glob matched against the paths under the entry point, including any `!` exclusion.
+    pub glob: String,
+    /// The selectors after the '^', if there is one.
+    pub selectors: Option<SelectorGroup>,
+    /// The filename modifiers at the very end, like `:t:r`, applied in order to each path.
+    pub filename_modifiers: Vec<FilenameModifier>,
+}
+
+impl Pattern {
+    /// A pattern matching a glob under the current directory, like `@GLOB`.
+    pub fn new(glob: impl Into<String>) -> Self {
+        Pattern {
+            only: None,
+            modifier: None,
+            entry_point: ".".into(),
+            glob: glob.into(),
+            selectors: None,
+            filename_modifiers: Vec::new(),
+        }
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = LaxError;
+
+    // Environment variables are substituted and escapes removed as the pattern is parsed, so
+    // they don't survive a round trip
+    fn from_str(pattern: &str) -> Result<Self> {
+        if !is_pattern(pattern) {
+            return Err(LaxError::InvalidPattern(format!(
+                "Expected an '@' pattern, not '{pattern}'"
+            )));
+        }
+        let (pattern, filename_modifiers) = split_modifiers(pattern);
+        let pattern = normalize_separators(pattern);
+        if split_alternatives(&pattern).len() > 1 {
+            return Err(LaxError::InvalidPattern(
+                "A pattern with '|' alternatives can't be a single Pattern".into(),
+            ));
+        }
+
+        let (origin, entry_point, glob, selectors, only) = Expander::parse_pattern(&pattern)?;
+        let modifier = match origin {
+            Origin::CurrentDirectory | Origin::ExecutableSearchPath => None,
+            Origin::RepositoryRoot(RootSearch::Nearest) => Some(Modifier::RepositoryRoot),
+            Origin::RepositoryRoot(RootSearch::Outermost) => {
+                Some(Modifier::OutermostRepositoryRoot)
+            }
+            Origin::Bookmark(name) => Some(Modifier::Bookmark(name)),
+            Origin::Zoxide(query) => Some(Modifier::Zoxide(query)),
+            Origin::Recent => Some(Modifier::Recent),
+            Origin::History(index) => Some(Modifier::History(index)),
+        };
+        Ok(Pattern {
+            only,
+            modifier,
+            entry_point: entry_point.into_owned(),
+            glob: glob.into_owned(),
+            selectors: selectors
+                .map(|selectors| Expander::parse_selectors_in(&pattern, selectors))
+                .transpose()?,
+            filename_modifiers,
+        })
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "@")?;
+        match self.only {
+            None => {}
+            Some(EntryKind::Files) => write!(f, "f:")?,
+            Some(EntryKind::Directories) => write!(f, "d:")?,
+        }
+        match &self.modifier {
+            None => {}
+            Some(Modifier::RepositoryRoot) => write!(f, "%")?,
+            Some(Modifier::OutermostRepositoryRoot) => write!(f, "%%")?,
+            Some(Modifier::Bookmark(name)) => write!(f, ":{name}/")?,
+            Some(Modifier::Zoxide(query)) => write!(f, "z:{query}/")?,
+            Some(Modifier::Recent) => write!(f, "recent:")?,
+            Some(Modifier::History(index)) => write!(f, "!{index}")?,
+        }
+
+        if !matches!(self.modifier, Some(Modifier::History(_))) {
+            let mut body = match self.entry_point.as_str() {
+                "." => escape(&self.glob),
+                "/" => format!("/**/{}", escape(&self.glob)),
+                entry_point => format!(
+                    "{}/**/{}",
+                    escape(entry_point).replace("/**/", "\\/**/"),
+                    escape(&self.glob)
+                ),
+            };
+            // Don't let the start of the pattern be taken for a modifier. A leading '%' is
+            // already escaped
+            let start = match self.entry_point.as_str() {
+                "." => &self.glob,
+                entry_point => entry_point,
+            };
+            let looks_like_modifier = [":", "!", "\\", "z:", "recent:", "f:", "d:"]
+                .iter()
+                .any(|modifier| start.starts_with(modifier));
+            // Nor a name like "z" for a prefix, when a filename modifier's ':' follows it
+            let looks_like_prefix = self.selectors.is_none()
+                && !self.filename_modifiers.is_empty()
+                && (matches!(body.as_str(), "z" | "recent")
+                    || (self.only.is_none() && matches!(body.as_str(), "f" | "d")));
+            if self.modifier.is_none() && (looks_like_modifier || looks_like_prefix) {
+                write!(f, "\\")?;
+            }
+            // Nor the end of the glob for a filename modifier, as in "foo:t"
+            if self.selectors.is_none() {
+                if let Some((head, letter)) = body.rsplit_once(':') {
+                    let modifier = letter.parse().ok().and_then(FilenameModifier::from_letter);
+                    if modifier.is_some() && !head.ends_with('\\') {
+                        body.insert(head.len(), '\\');
+                    }
+                }
+            }
+            write!(f, "{body}")?;
+        }
+
+        if let Some(selectors) = &self.selectors {
+            write!(f, "^{selectors}")?;
+        }
+        for modifier in &self.filename_modifiers {
+            write!(f, "{modifier}")?;
+        }
+        Ok(())
+    }
+}
+
+// Escape the characters that would otherwise be special in a pattern
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for character in text.chars() {
+        if matches!(character, '^' | '%' | '|' | '$') {
+            escaped.push('\\');
+        }
+        escaped.push(character);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Selector;
+
+    #[test]
+    fn round_trip() {
+        for pattern in [
+            "@foo",
+            "@%src/**/*.rs^1,-1",
+            "@%%Cargo.toml",
+            "@:docs/*.md^a,!2,D",
+            "@z:proj/*.toml",
+            "@recent:*.rs",
+            "@/**/fish",
+            "@src/**/*.rs!generated/**",
+            "@*.rs^1:t:r",
+            "@main.rs:h",
+            "@f:%src/**/*.rs^1",
+            "@d:foo",
+            r"@\f:literal",
+            r"@a\^b",
+            r"@\:literal",
+            r"@\%literal",
+            "@f:h",
+            "@d:t",
+            r"@foo\:t",
+            r"@src/**/foo\:e:r",
+        ]
+        .into_iter()
+        .chain(cfg!(feature = "regex").then_some("@!2^/foo"))
+        {
+            let parsed: Pattern = pattern.parse().unwrap();
+            assert_eq!(parsed.to_string(), pattern);
+            assert_eq!(parsed.to_string().parse::<Pattern>().unwrap(), parsed);
+        }
+        assert_eq!(Pattern::new("foo:t").to_string(), r"@foo\:t");
+        let mut pattern = Pattern::new("z");
+        pattern.filename_modifiers = vec![FilenameModifier::Head];
+        assert_eq!(pattern.to_string(), r"@\z:h");
+        assert_eq!(pattern.to_string().parse::<Pattern>().unwrap(), pattern);
+        assert!("foo".parse::<Pattern>().is_err());
+        assert!("@foo|bar".parse::<Pattern>().is_err());
+    }
+
+    #[test]
+    fn build() {
+        let mut pattern = Pattern::new("*.rs");
+        pattern.modifier = Some(Modifier::RepositoryRoot);
+        pattern.entry_point = "src".into();
+        pattern.selectors = Some(SelectorGroup::new(
+            vec![
+                Selector::index(1).unwrap(),
+                Selector::Exclude(Box::new(Selector::index(-1).unwrap())),
+            ],
+            false,
+        ));
+        assert_eq!(pattern.to_string(), "@%src/**/*.rs^1,!-1");
+        assert_eq!(
+            "1..-2,n".parse::<SelectorGroup>().unwrap().to_string(),
+            "1..-2,n"
+        );
+        assert!(Selector::index(0).is_err());
+    }
+}
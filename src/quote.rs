@@ -0,0 +1,41 @@
+//! Quote words so that bash, zsh, and fish all read each back as a single, literal word.
+
+/// Quote a word for the shell, or leave it as it is if nothing in it is special.
+///
+/// Single quotes keep everything literal in bash and zsh, but fish also treats `\'` and `\\` as
+/// escapes inside them. So single quotes and backslashes are escaped outside of the quotes, where
+/// every shell agrees on what they mean.
+pub fn quote(word: &str) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "-_./:@,+".contains(c);
+    if !word.is_empty() && word.chars().all(is_safe) {
+        return word.into();
+    }
+
+    let mut quoted = String::with_capacity(word.len() + 2);
+    quoted.push('\'');
+    for c in word.chars() {
+        match c {
+            '\'' => quoted.push_str("'\\''"),
+            '\\' => quoted.push_str("'\\\\'"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('\'');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quoting() {
+        assert_eq!(quote("./src/main.rs"), "./src/main.rs");
+        assert_eq!(quote(""), "''");
+        assert_eq!(quote("with space"), "'with space'");
+        assert_eq!(quote("$HOME/*.rs"), "'$HOME/*.rs'");
+        assert_eq!(quote("~user"), "'~user'");
+        assert_eq!(quote("it's"), "'it'\\''s'");
+        assert_eq!(quote("back\\slash"), "'back'\\\\'slash'");
+    }
+}
@@ -0,0 +1,66 @@
+//! Find the program lax runs, the way a shell would, but without ever picking up a program from
+//! the current directory by accident.
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Find a program's absolute path. Names containing a path separator, like `./build.sh`, are
+/// used as they are. Other names are looked up in `$PATH`, skipping relative entries (such as
+/// `.`), which would otherwise run whatever the current directory happens to contain.
+pub fn program(name: &str) -> io::Result<PathBuf> {
+    if name.chars().any(std::path::is_separator) {
+        return absolute(Path::new(name));
+    }
+
+    let search_path = env::var_os("PATH").unwrap_or_default();
+    let mut relative_match = None;
+    for directory in env::split_paths(&search_path) {
+        let Some(candidate) = find_in(&directory, name) else {
+            continue;
+        };
+        if directory.is_relative() {
+            relative_match.get_or_insert(candidate);
+            continue;
+        }
+        return Ok(candidate);
+    }
+
+    match relative_match {
+        Some(candidate) => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "only found as {:?}, through a relative directory in $PATH. \
+                 Run it as './{}' if that's what you meant",
+                candidate, name
+            ),
+        )),
+        None => Err(io::ErrorKind::NotFound.into()),
+    }
+}
+
+fn absolute(path: &Path) -> io::Result<PathBuf> {
+    if path.is_absolute() {
+        Ok(path.into())
+    } else {
+        Ok(env::current_dir()?.join(path))
+    }
+}
+
+#[cfg(unix)]
+fn find_in(directory: &Path, name: &str) -> Option<PathBuf> {
+    use std::os::unix::fs::PermissionsExt;
+    let candidate = directory.join(name);
+    let metadata = fs::metadata(&candidate).ok()?;
+    (metadata.is_file() && metadata.permissions().mode() & 0o111 != 0).then_some(candidate)
+}
+
+// Windows programs are found by their name plus any of the extensions in `%PATHEXT%`
+#[cfg(not(unix))]
+fn find_in(directory: &Path, name: &str) -> Option<PathBuf> {
+    let extensions = env::var("PATHEXT").unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".into());
+    std::iter::once(String::new())
+        .chain(extensions.split(';').map(String::from))
+        .map(|extension| directory.join(format!("{name}{extension}")))
+        .find(|candidate| fs::metadata(candidate).is_ok_and(|metadata| metadata.is_file()))
+}
@@ -0,0 +1,477 @@
+//! Selectors, which pick some of a pattern's matches, as in `@*.rs^1,-1`, and how they're parsed.
+
+use std::fmt;
+
+use log::debug;
+
+use crate::{
+    find_unescaped, point_at, shift, Expander, LaxError, Match, MatchOrder, Origin, Result, Spanned,
+};
+
+/// A single selector, which picks some of a pattern's matches, as in `@*.rs^2`.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Selector {
+    /// Every match, written `a`.
+    All,
+    /// The match this far from the front, counting from 0, written as a positive number
+    /// counting from 1.
+    FromFront(usize),
+    /// The match this far from the back, counting from 0, written as a negative number
+    /// counting from -1.
+    FromBack(usize),
+    /// Every match between two `FromFront`/`FromBack` selectors, inclusive, as in `2..-1`.
+    Range(Box<Selector>, Box<Selector>),
+    /// Every match of a regex, as in `/\.rs$`.
+    Regex(SelectorRegex),
+    /// The most recently modified match, written `n`.
+    Newest,
+    /// Remove whatever the inner selector matches from the selection, as in `!2`.
+    Exclude(Box<Selector>),
+}
+
+impl Selector {
+    /// The selector for a single match, counting from 1 at the front, or from -1 at the back.
+    pub fn index(index: isize) -> Result<Self> {
+        match index {
+            // Selectors are 1-indexed
+            0 => Err(LaxError::InvalidSelector(
+                "Selectors are 1-indexed and cannot be zero".into(),
+            )),
+            index if index < 0 => Ok(Selector::FromBack(index.unsigned_abs() - 1)),
+            index => Ok(Selector::FromFront(index.unsigned_abs() - 1)),
+        }
+    }
+}
+
+/// The regex of a `Selector::Regex`, compiled once when the selector is parsed.
+#[derive(Debug, Clone)]
+pub struct SelectorRegex {
+    source: String,
+    #[cfg(feature = "regex")]
+    regex: regex::Regex,
+}
+
+impl SelectorRegex {
+    /// Compile a regex for a selector. Fails if it's invalid, or without lax's 'regex' feature.
+    pub fn new(source: impl Into<String>) -> Result<Self> {
+        let source = source.into();
+        #[cfg(feature = "regex")]
+        {
+            let regex = regex::Regex::new(&source).map_err(|err| {
+                // The regex crate draws its own diagram, which would clash with ours, so just
+                // keep the description at the end
+                let err = err.to_string();
+                let description = err.lines().last().unwrap_or_default().trim();
+                let description = description.strip_prefix("error: ").unwrap_or(description);
+                LaxError::InvalidSelector(format!("Invalid regex selector: {description}"))
+            })?;
+            Ok(SelectorRegex { source, regex })
+        }
+        #[cfg(not(feature = "regex"))]
+        {
+            let _ = source;
+            Err(LaxError::InvalidSelector(
+                "Regex selectors need lax's 'regex' feature".into(),
+            ))
+        }
+    }
+
+    /// The regex as it was written.
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    #[cfg(feature = "regex")]
+    fn is_match(&self, path: &str) -> bool {
+        self.regex.is_match(path)
+    }
+}
+
+impl PartialEq for SelectorRegex {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl Eq for SelectorRegex {}
+
+impl fmt::Display for SelectorRegex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Selector::All => write!(f, "a"),
+            Selector::FromFront(offset) => write!(f, "{}", offset + 1),
+            Selector::FromBack(offset) => write!(f, "-{}", offset + 1),
+            Selector::Range(start, end) => write!(f, "{start}..{end}"),
+            Selector::Regex(regex) => write!(f, "/{regex}"),
+            Selector::Newest => write!(f, "n"),
+            Selector::Exclude(selector) => write!(f, "!{selector}"),
+        }
+    }
+}
+
+/// The selectors after a pattern's '^', as in `@*.rs^1,-1`.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct SelectorGroup {
+    /// The selectors, in order. Without any, the user is asked to choose.
+    pub selectors: Vec<Selector>,
+    /// Collapse the selection to its unique parent directories, like `-D`. Written `D`.
+    pub to_parents: bool,
+    /// Put the matches in this order before selecting, instead of [`Config::sort`]. Written
+    /// `s:natural`, `s:mtime`, and so on.
+    pub sort: Option<MatchOrder>,
+    // The selectors came from `Config::default_selectors`, so fall back to the menu if they
+    // select nothing
+    pub(crate) is_default: bool,
+}
+
+impl SelectorGroup {
+    /// Group some selectors together.
+    pub fn new(selectors: Vec<Selector>, to_parents: bool) -> Self {
+        SelectorGroup {
+            selectors,
+            to_parents,
+            sort: None,
+            is_default: false,
+        }
+    }
+}
+
+impl std::str::FromStr for SelectorGroup {
+    type Err = LaxError;
+
+    fn from_str(selectors: &str) -> Result<Self> {
+        Expander::parse_selectors(selectors)
+    }
+}
+
+impl fmt::Display for SelectorGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut selectors: Vec<String> = self.selectors.iter().map(Selector::to_string).collect();
+        if let Some(sort) = self.sort {
+            selectors.push(format!("s:{sort}"));
+        }
+        if self.to_parents {
+            selectors.push("D".into());
+        }
+        write!(f, "{}", selectors.join(","))
+    }
+}
+
+impl Selector {
+    // Get the indices of the paths this selector matches
+    fn indices(&self, paths: &[Match]) -> Result<Vec<usize>> {
+        Ok(match self {
+            Selector::All => (0..paths.len()).collect(),
+            Selector::FromFront(offset) => {
+                if *offset >= paths.len() {
+                    return Err(LaxError::SelectorOutOfRange(format!("{}", offset + 1)));
+                }
+                vec![*offset]
+            }
+            Selector::FromBack(offset) => {
+                if *offset >= paths.len() {
+                    return Err(LaxError::SelectorOutOfRange(format!("-{}", offset + 1)));
+                }
+                vec![paths.len() - 1 - offset]
+            }
+            Selector::Range(start, end) => {
+                let start = start.indices(paths)?[0];
+                let end = end.indices(paths)?[0];
+                if start <= end {
+                    (start..=end).collect()
+                } else {
+                    (end..=start).rev().collect()
+                }
+            }
+            #[cfg(feature = "regex")]
+            Selector::Regex(regex) => (0..paths.len())
+                .filter(|&index| regex.is_match(&paths[index].path.to_string_lossy()))
+                .collect(),
+            #[cfg(not(feature = "regex"))]
+            Selector::Regex(_) => unreachable!("Regex selectors need the 'regex' feature"),
+            Selector::Newest => {
+                // Ties go to the earlier path
+                (0..paths.len())
+                    .rev()
+                    .max_by_key(|&index| paths[index].modified)
+                    .into_iter()
+                    .collect()
+            }
+            Selector::Exclude(selector) => selector.indices(paths)?,
+        })
+    }
+
+    // Return how far into the front and back of the list this selector can reach, if that can be
+    // known ahead of time
+    fn window(&self) -> Option<Window> {
+        match self {
+            Selector::FromFront(offset) => Some(Window {
+                front: offset + 1,
+                back: 0,
+            }),
+            Selector::FromBack(offset) => Some(Window {
+                front: 0,
+                back: offset + 1,
+            }),
+            // A range from one end to the other covers the middle, however long the list is
+            Selector::Range(start, end) => {
+                let (start, end) = (start.window()?, end.window()?);
+                let same_end =
+                    (start.back == 0 && end.back == 0) || (start.front == 0 && end.front == 0);
+                same_end.then(|| start.max(end))
+            }
+            Selector::Exclude(selector) => selector.window(),
+            Selector::All | Selector::Regex(_) | Selector::Newest => None,
+        }
+    }
+}
+
+// How many matches from the front and from the back of the list some selectors can pick. Matches
+// in between can be dropped, since they'll never be selected
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) struct Window {
+    pub(crate) front: usize,
+    pub(crate) back: usize,
+}
+
+impl Window {
+    fn max(self, other: Self) -> Self {
+        Window {
+            front: self.front.max(other.front),
+            back: self.back.max(other.back),
+        }
+    }
+}
+
+impl SelectorGroup {
+    /// Select the paths of the matches the selector group picks.
+    pub fn select(&self, paths: &[Match]) -> Result<Vec<String>> {
+        if self.selectors.is_empty() {
+            return Err(LaxError::InvalidSelector("No selectors given".into()));
+        }
+        if paths.is_empty() {
+            return Err(LaxError::NothingSelected("No paths to select!".into()));
+        }
+
+        let mut included = Vec::new();
+        let mut excluded = Vec::new();
+        for selector in &self.selectors {
+            match selector {
+                Selector::Exclude(_) => excluded.extend(selector.indices(paths)?),
+                _ => included.extend(selector.indices(paths)?),
+            }
+        }
+        // Only exclusions means "everything except"
+        if !self.includes_anything() {
+            included = (0..paths.len()).collect();
+        }
+
+        let selected_paths: Vec<String> = included
+            .into_iter()
+            .filter(|index| !excluded.contains(index))
+            .map(|index| paths[index].to_path_string())
+            .collect();
+        if selected_paths.is_empty() && !excluded.is_empty() {
+            return Err(LaxError::NothingSelected("Every match was excluded".into()));
+        }
+        Ok(selected_paths)
+    }
+
+    // Return highest index we will select, with no knowledge of how long the list of paths will
+    // be. None implies infinity
+    pub(crate) fn highest_index(&self) -> Option<usize> {
+        self.window()
+            .filter(|window| window.back == 0)
+            .and_then(|window| window.front.checked_sub(1))
+    }
+
+    // Return how much of the front and back of the list we will select from, with no knowledge
+    // of how long it will be. None implies all of it
+    pub(crate) fn window(&self) -> Option<Window> {
+        // Without selectors, the menu needs every match, and with only exclusions, we select
+        // everything else
+        if !self.includes_anything() {
+            return None;
+        }
+        let mut window = Window::default();
+        for selector in &self.selectors {
+            window = window.max(selector.window()?);
+        }
+        Some(window)
+    }
+
+    fn includes_anything(&self) -> bool {
+        self.selectors
+            .iter()
+            .any(|selector| !matches!(selector, Selector::Exclude(_)))
+    }
+}
+
+impl Expander {
+    // Build a selector group from string.
+    //
+    // Selectors can be:
+    // 1 to N: Select path number #n
+    // -N to -1: Select path number #n in reverse order
+    // N..M: Select paths #n through #m, where either end can be negative
+    // 'a': Select all paths
+    // 'l': Select last path
+    // 'n': Select the most recently modified path
+    // '/regex': Select paths matching a regex
+    // '!selector': Exclude the paths a selector matches. If there are only exclusions, every
+    //              other path is selected
+    // 'D': Collapse the selection to its parent directories. This isn't a selector itself, and
+    //      applies after the others, so `^D` alone still brings up the menu
+    // 's:ORDER': Put the matches in another order, like `s:natural`, before the others select
+    //            from them. Like 'D', this isn't a selector itself
+    //
+    // Multiple selectors are delimited by commas.
+    pub(crate) fn parse_selectors(raw_selectors: &str) -> Result<SelectorGroup> {
+        Self::parse_selectors_spanned(raw_selectors)
+            .map_err(|(err, span)| point_at(err, raw_selectors.trim_end(), span))
+    }
+
+    // Parse the selectors after a pattern's '^', pointing at the faulty selector in the pattern
+    // if one is invalid
+    pub(crate) fn parse_selectors_in(pattern: &str, raw_selectors: &str) -> Result<SelectorGroup> {
+        let start = find_unescaped(pattern, "^").map_or(0, |index| index + 1);
+        Self::parse_selectors_spanned(raw_selectors)
+            .map_err(|err| shift(err, start))
+            .map_err(|(err, span)| point_at(err, pattern, span))
+    }
+
+    // Parse a selector group like `parse_selectors`, but on failure, also return which bytes of
+    // it are at fault
+    pub(crate) fn parse_selectors_spanned(raw_selectors: &str) -> Spanned<SelectorGroup> {
+        let mut selectors = vec![];
+        let mut to_parents = false;
+        let mut sort = None;
+
+        let mut start = raw_selectors.len() - raw_selectors.trim_start().len();
+        for selector in raw_selectors.trim().split(',') {
+            let span = start..start + selector.len();
+            start = span.end + 1;
+            if selector == "D" {
+                to_parents = true;
+                continue;
+            }
+            if let Some(order) = selector.strip_prefix("s:") {
+                let order = MatchOrder::parse(order).ok_or_else(|| {
+                    let err = format!(
+                        "Invalid sort order: '{order}'. \
+                         Expected path, name, natural, mtime, size, or none"
+                    );
+                    (LaxError::InvalidSelector(err), span)
+                })?;
+                sort = Some(order);
+                continue;
+            }
+
+            if let Some(selector) = selector.strip_prefix('!') {
+                let selector = Self::parse_selector(selector).map_err(|err| (err, span))?;
+                selectors.push(Selector::Exclude(Box::new(selector)));
+                continue;
+            }
+
+            selectors.push(Self::parse_selector(selector).map_err(|err| (err, span))?);
+        }
+        let selector_group = SelectorGroup {
+            selectors,
+            to_parents,
+            sort,
+            is_default: false,
+        };
+        debug!(
+            "Parsed selectors '{}' as '{selector_group}'",
+            raw_selectors.trim()
+        );
+        Ok(selector_group)
+    }
+
+    // Parse a pattern's selectors. '@!' patterns without selectors of their own reuse every path,
+    // and other patterns without them use `Config::default_selectors`, if set, rather than the
+    // menu
+    pub(crate) fn selector_group(
+        &self,
+        pattern: &str,
+        origin: &Origin,
+        raw_selectors: Option<&str>,
+    ) -> Result<Option<SelectorGroup>> {
+        let selector_group = raw_selectors
+            .map(|raw_selectors| Self::parse_selectors_in(pattern, raw_selectors))
+            .transpose()?;
+        if let Origin::History(_) = origin {
+            let mut selector_group =
+                selector_group.unwrap_or_else(|| SelectorGroup::new(Vec::new(), false));
+            if selector_group.selectors.is_empty() {
+                selector_group.selectors.push(Selector::All);
+            }
+            return Ok(Some(selector_group));
+        }
+        let Some(default_selectors) = &self.config.default_selectors else {
+            return Ok(selector_group);
+        };
+        Ok(match selector_group {
+            Some(selector_group) if !selector_group.selectors.is_empty() => Some(selector_group),
+            selector_group => {
+                let mut default_group = Self::parse_selectors(default_selectors)?;
+                if let Some(group) = selector_group {
+                    default_group.to_parents |= group.to_parents;
+                    default_group.sort = group.sort.or(default_group.sort);
+                }
+                default_group.is_default = true;
+                Some(default_group)
+            }
+        })
+    }
+
+    // Parse a single selector, other than an exclusion
+    pub(crate) fn parse_selector(selector: &str) -> Result<Selector> {
+        if selector == "a" {
+            return Ok(Selector::All);
+        }
+
+        if let Some(selector) = selector.strip_prefix('/') {
+            return Ok(Selector::Regex(SelectorRegex::new(selector)?));
+        }
+
+        if selector == "n" {
+            return Ok(Selector::Newest);
+        }
+
+        // This was added before you could specify negative selectors. Consider deprecation.
+        if selector == "l" {
+            return Ok(Selector::FromBack(0));
+        }
+
+        if let Some((start, end)) = selector.split_once("..") {
+            // A missing or mistyped end is reported with the whole range, so it's clear which
+            // end it is
+            let end_of_range = |end: &str| match end.parse::<isize>() {
+                Ok(_) => Self::parse_index(end),
+                Err(_) => Err(LaxError::InvalidSelector(format!(
+                    "Invalid range: '{selector}'. Expected a number at each end, as in 1..3"
+                ))),
+            };
+            return Ok(Selector::Range(
+                Box::new(end_of_range(start)?),
+                Box::new(end_of_range(end)?),
+            ));
+        }
+
+        Self::parse_index(selector)
+    }
+
+    pub(crate) fn parse_index(selector: &str) -> Result<Selector> {
+        let index: isize = selector
+            .parse()
+            .map_err(|_| LaxError::InvalidSelector(format!("Invalid selector: '{selector}'")))?;
+        Selector::index(index)
+    }
+}
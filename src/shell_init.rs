@@ -0,0 +1,243 @@
+//! Shell integration for `--shell-init`: aliases, completions, line editor widgets, and a cd
+//! helper, for bash, zsh, and fish.
+//!
+//! The completions are generated from lax's own flag table, so re-running `--shell-init` after an
+//! upgrade always matches the binary. One script serves both bash and zsh, and picks its half when
+//! it's loaded. Fish gets its own.
+use crate::Flag;
+
+/// Bump this when the functions or variables the script defines change incompatibly.
+const SHELL_INIT_VERSION: u32 = 1;
+
+/// The parts `--shell-init` accepts, besides `all`.
+pub const PARTS: &[&str] = &["aliases", "cd", "completions", "widgets"];
+
+/// A shell to generate the integration for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl Shell {
+    /// The names `--shell` accepts.
+    pub const NAMES: &'static [&'static str] = &["bash", "zsh", "fish"];
+
+    /// Parse a shell's name, as given to `--shell`.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "bash" => Some(Shell::Bash),
+            "zsh" => Some(Shell::Zsh),
+            "fish" => Some(Shell::Fish),
+            _ => None,
+        }
+    }
+}
+
+/// Generate the script for one part of the integration, or for `all` of it. Returns `None` if
+/// there's no such part.
+pub fn script(part: &str, shell: Shell, flags: &[Flag]) -> Option<String> {
+    let body = match part {
+        "all" => PARTS
+            .iter()
+            .map(|part| generate(part, shell, flags))
+            .collect::<Option<Vec<_>>>()?
+            .join("\n"),
+        part => generate(part, shell, flags)?,
+    };
+    Some(match shell {
+        Shell::Bash | Shell::Zsh => format!(
+            "# lax shell integration for bash and zsh, generated by lax {}\n\
+             # Load it from your shell's rc file with: eval \"$(lax --shell-init {})\"\n\
+             __lax_shell_init_version={}\n\n{}",
+            env!("CARGO_PKG_VERSION"),
+            part,
+            SHELL_INIT_VERSION,
+            body
+        ),
+        Shell::Fish => format!(
+            "# lax shell integration for fish, generated by lax {}\n\
+             # Load it from config.fish with: lax --shell-init {} --shell fish | source\n\
+             set -g __lax_shell_init_version {}\n\n{}",
+            env!("CARGO_PKG_VERSION"),
+            part,
+            SHELL_INIT_VERSION,
+            body
+        ),
+    })
+}
+
+fn generate(part: &str, shell: Shell, flags: &[Flag]) -> Option<String> {
+    let fish = shell == Shell::Fish;
+    match part {
+        "aliases" => Some(if fish { FISH_ALIASES } else { ALIASES }.into()),
+        "cd" => Some(if fish { FISH_CD } else { CD }.into()),
+        "completions" if fish => Some(fish_completions(flags)),
+        "completions" => Some(completions(flags)),
+        "widgets" => Some(if fish { FISH_WIDGETS } else { WIDGETS }.into()),
+        _ => None,
+    }
+}
+
+const ALIASES: &str = r#"# List what '@' patterns match, or run a command once per match
+alias lxl='lax --list'
+alias lxe='lax --each'
+"#;
+
+const CD: &str = r#"# cd to where an '@' pattern points. Files are taken to their directory
+lcd() {
+    local directory
+    directory=$(command lax -D --print-lines -- "$@") || return
+    builtin cd -- "$directory"
+}
+"#;
+
+const FISH_ALIASES: &str = r#"# List what '@' patterns match, or run a command once per match
+alias lxl 'lax --list'
+alias lxe 'lax --each'
+"#;
+
+const FISH_CD: &str = r#"# cd to where an '@' pattern points. Files are taken to their directory
+function lcd --description "cd to where an '@' pattern points"
+    set -l directory (command lax -D --print-lines -- $argv)
+    or return
+    builtin cd -- $directory
+end
+"#;
+
+// Ctrl-X @ replaces the '@' pattern before the cursor with what it expands to
+const WIDGETS: &str = r#"# Ctrl-X @ expands the '@' pattern before the cursor in place
+if [ -n "$ZSH_VERSION" ]; then
+    __lax_expand_word() {
+        local word=${LBUFFER##*[[:space:]]} expanded
+        if [[ $word == @* ]] && expanded=$(command lax --print-quoted -- "$word" </dev/tty); then
+            LBUFFER=${LBUFFER%"$word"}$expanded
+        fi
+        zle reset-prompt
+    }
+    zle -N __lax_expand_word
+    bindkey '^X@' __lax_expand_word
+elif [ -n "$BASH_VERSION" ]; then
+    __lax_expand_word() {
+        local before=${READLINE_LINE:0:READLINE_POINT} after=${READLINE_LINE:READLINE_POINT}
+        local word=${before##*[[:space:]]} expanded
+        [[ $word == @* ]] || return
+        expanded=$(command lax --print-quoted -- "$word" </dev/tty) || return
+        READLINE_LINE=${before%"$word"}$expanded$after
+        READLINE_POINT=$((${#before} - ${#word} + ${#expanded}))
+    }
+    [[ $- == *i* ]] && bind -x '"\C-x@": __lax_expand_word'
+fi
+"#;
+
+const FISH_WIDGETS: &str = r#"# Ctrl-X @ expands the '@' pattern under the cursor in place
+function __lax_expand_word
+    set -l word (commandline --current-token)
+    string match -q -- '@*' $word
+    or return
+    set -l expanded (command lax --print-quoted -- $word </dev/tty)
+    and commandline --current-token --replace -- "$expanded"
+    commandline --function repaint
+end
+status is-interactive; and bind \cx@ __lax_expand_word
+"#;
+
+fn completions(flags: &[Flag]) -> String {
+    let names = |flag: &Flag| {
+        let long = flag.long.to_string();
+        match flag.short {
+            Some(short) => vec![format!("-{}", short), long],
+            None => vec![long],
+        }
+    };
+    let bash_flags: Vec<String> = flags.iter().flat_map(names).collect();
+    let bash_options: Vec<String> = flags
+        .iter()
+        .filter(|flag| flag.takes_value)
+        .flat_map(names)
+        .collect();
+
+    let mut zsh_specs = String::new();
+    for flag in flags {
+        let description = zsh_escape(flag.description.trim());
+        for name in names(flag) {
+            let spec = match (flag.takes_value, name.starts_with("--")) {
+                (false, _) => format!("{}[{}]", name, description),
+                (true, true) => format!("{}=[{}]:value:_default", name, description),
+                (true, false) => format!("{}+[{}]:value:_default", name, description),
+            };
+            zsh_specs.push_str(&format!(" \\\n            '{}'", spec));
+        }
+    }
+
+    format!(
+        r#"# Completion for lax's flags, then the program it runs and that program's arguments
+if [ -n "$ZSH_VERSION" ]; then
+    _lax() {{
+        _arguments -s -S -A '-*'{zsh_specs} \
+            '1:program:_command_names -e' \
+            '*::arguments:_normal'
+    }}
+    (( $+functions[compdef] )) && compdef _lax lax
+elif [ -n "$BASH_VERSION" ]; then
+    _lax() {{
+        local current=${{COMP_WORDS[COMP_CWORD]}} i=1
+        COMPREPLY=()
+        while ((i < COMP_CWORD)); do
+            case ${{COMP_WORDS[i]}} in
+                --) ((i++)); break ;;
+                {bash_options}) ((i += 2)) ;;
+                -*) ((i++)) ;;
+                *) break ;;
+            esac
+        done
+        # Past the program, or on an option's value, leave it to the default completion
+        ((i == COMP_CWORD)) || return
+        if [[ $current == -* ]]; then
+            COMPREPLY=($(compgen -W '{bash_flags}' -- "$current"))
+        else
+            COMPREPLY=($(compgen -c -- "$current"))
+        fi
+    }}
+    complete -o default -F _lax lax
+fi
+"#,
+        zsh_specs = zsh_specs,
+        bash_options = bash_options.join("|"),
+        bash_flags = bash_flags.join(" "),
+    )
+}
+
+fn fish_completions(flags: &[Flag]) -> String {
+    let mut script = String::from(
+        "# Completion for lax's flags, then the program it runs and that program's arguments\n\
+         complete -c lax -x -a '(__fish_complete_subcommand)'\n",
+    );
+    for flag in flags {
+        script.push_str("complete -c lax");
+        if let Some(short) = flag.short {
+            script.push_str(&format!(" -s {}", short));
+        }
+        script.push_str(&format!(" -l {}", flag.long.trim_start_matches("--")));
+        if flag.takes_value {
+            script.push_str(" -r");
+        }
+        script.push_str(&format!(" -d '{}'\n", fish_escape(flag.description.trim())));
+    }
+    script
+}
+
+// Escape a description for fish's single quotes, where only `\'` and `\\` are escapes
+fn fish_escape(description: &str) -> String {
+    description.replace('\\', "\\\\").replace('\'', "\\'")
+}
+
+// Escape a description for the brackets of an `_arguments` spec, inside single quotes
+fn zsh_escape(description: &str) -> String {
+    description
+        .replace('\\', "\\\\")
+        .replace('[', "\\[")
+        .replace(']', "\\]")
+        .replace('\'', "'\\''")
+}
@@ -0,0 +1,117 @@
+//! Make signals behave the same whether they're aimed at lax or at the programs it runs as
+//! children (`--spawn` and `--each`).
+//!
+//! Ctrl-C and Ctrl-\ already reach the children, since they share lax's process group, so lax
+//! just stays alive until they've exited. Signals sent to lax alone, like a SIGTERM from `kill`,
+//! are passed on to every running child.
+use std::process::ExitStatus;
+
+/// Start handling SIGINT, SIGQUIT, SIGTERM, and SIGHUP for the rest of lax's life. Call this
+/// before starting any children.
+#[cfg(unix)]
+pub fn forward() {
+    // A handler rather than SIG_IGN, since ignored signals would stay ignored in the children
+    let handler = handle as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    for signal in [libc::SIGINT, libc::SIGQUIT, libc::SIGTERM, libc::SIGHUP] {
+        // SAFETY: `handle` only touches atomics and calls kill(), which is async-signal-safe
+        unsafe { libc::signal(signal, handler) };
+    }
+}
+
+#[cfg(not(unix))]
+pub fn forward() {}
+
+/// Have signals sent to lax passed on to a child until the returned guard is dropped. A signal
+/// that arrived before the child was watched, maybe while it was starting, is passed on now.
+pub fn watch(pid: u32) -> Watch {
+    #[cfg(unix)]
+    for (slot, child) in imp::CHILDREN.iter().enumerate() {
+        use std::sync::atomic::Ordering;
+        if child
+            .compare_exchange(0, pid as i32, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+        {
+            let signal = imp::RECEIVED.load(Ordering::SeqCst);
+            if signal != 0 {
+                // SAFETY: kill() has no memory safety requirements
+                unsafe { libc::kill(pid as i32, signal) };
+            }
+            return Watch(Some(slot));
+        }
+    }
+    // Out of slots. The child still gets Ctrl-C from the terminal, just not forwarded signals
+    let _ = pid;
+    Watch(None)
+}
+
+/// Stops forwarding signals to a child when dropped.
+pub struct Watch(Option<usize>);
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some(slot) = self.0 {
+            imp::CHILDREN[slot].store(0, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+}
+
+/// The signal that killed a child, if the user interrupted it with Ctrl-C or Ctrl-\.
+pub fn interruption(status: &ExitStatus) -> Option<i32> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        status
+            .signal()
+            .filter(|&signal| signal == libc::SIGINT || signal == libc::SIGQUIT)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = status;
+        None
+    }
+}
+
+/// The signal sent to lax itself that was passed on to its children, if there was one. Once one
+/// has arrived, no more children should be started.
+pub fn received() -> Option<i32> {
+    #[cfg(unix)]
+    {
+        let signal = imp::RECEIVED.load(std::sync::atomic::Ordering::SeqCst);
+        (signal != 0).then_some(signal)
+    }
+    #[cfg(not(unix))]
+    None
+}
+
+#[cfg(unix)]
+extern "C" fn handle(signal: libc::c_int) {
+    // The terminal already sent these to the whole process group
+    if signal == libc::SIGINT || signal == libc::SIGQUIT {
+        return;
+    }
+    // Recorded before looking at the children, so one being watched right now can't miss it
+    imp::RECEIVED.store(signal, std::sync::atomic::Ordering::SeqCst);
+    for child in &imp::CHILDREN {
+        let pid = child.load(std::sync::atomic::Ordering::SeqCst);
+        if pid != 0 {
+            // SAFETY: kill() has no memory safety requirements
+            unsafe { libc::kill(pid, signal) };
+        }
+    }
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::sync::atomic::AtomicI32;
+
+    // Enough for any sensible --jobs. A signal handler can't take a lock, so the running
+    // children's PIDs live in a fixed set of atomic slots, with 0 marking a free slot
+    const MAX_CHILDREN: usize = 256;
+    #[allow(clippy::declare_interior_mutable_const)]
+    const FREE: AtomicI32 = AtomicI32::new(0);
+    pub static CHILDREN: [AtomicI32; MAX_CHILDREN] = [FREE; MAX_CHILDREN];
+
+    // The last signal that was passed on, if any
+    pub static RECEIVED: AtomicI32 = AtomicI32::new(0);
+}
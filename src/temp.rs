@@ -0,0 +1,150 @@
+//! Temporary files, like the list of matches `--edit` opens in `$EDITOR`, which are removed
+//! however lax exits: normally, or by a signal while the editor is open.
+//!
+//! Each temporary file is registered for as long as it exists. Signals that would kill lax remove
+//! every registered file first, then kill it as they would have. Signals that lax already handles
+//! (see [`crate::signals`]) or ignores are left alone, since they don't stop it from cleaning up.
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// A file that's removed when dropped, or when a signal kills lax.
+pub struct TempFile {
+    path: PathBuf,
+    #[cfg(unix)]
+    slot: Option<usize>,
+}
+
+impl TempFile {
+    /// Create a file holding `contents` in `dir`, which is created if it's missing. Its name ends
+    /// with `suffix`, so editors can tell what kind of file it is.
+    pub fn create(dir: &Path, suffix: &str, contents: &str) -> io::Result<Self> {
+        // Counted across the whole process, so no two files lax creates get the same name
+        static CREATED: AtomicUsize = AtomicUsize::new(0);
+
+        fs::create_dir_all(dir)?;
+        loop {
+            let count = CREATED.fetch_add(1, Ordering::SeqCst);
+            let path = dir.join(format!("lax-{}-{count}{suffix}", process::id()));
+            // Never reuse a file that's already there, which might be someone else's
+            let mut file = match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(file) => file,
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            };
+            let temp = TempFile {
+                #[cfg(unix)]
+                slot: imp::register(&path),
+                path,
+            };
+            file.write_all(contents.as_bytes())?;
+            return Ok(temp);
+        }
+    }
+
+    /// Where the file is.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some(slot) = self.slot {
+            imp::FILES[slot].store(std::ptr::null_mut(), Ordering::SeqCst);
+        }
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Run `f`, which waits on a program that has the terminal, like an editor. Ctrl-C and Ctrl-\ are
+/// left to the program while it runs, rather than killing lax out from under it.
+pub fn foreground<T>(f: impl FnOnce() -> T) -> T {
+    #[cfg(unix)]
+    imp::FOREGROUND.store(true, Ordering::SeqCst);
+    let result = f();
+    #[cfg(unix)]
+    imp::FOREGROUND.store(false, Ordering::SeqCst);
+    result
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::{
+        ffi::CString,
+        os::unix::ffi::OsStrExt,
+        path::Path,
+        ptr,
+        sync::{
+            atomic::{AtomicBool, AtomicPtr, Ordering},
+            Once,
+        },
+    };
+
+    // More than lax ever has at once. A signal handler can't take a lock, so the files' names
+    // live in a fixed set of atomic slots, with null marking a free slot
+    const MAX_FILES: usize = 16;
+    #[allow(clippy::declare_interior_mutable_const)]
+    const FREE: AtomicPtr<libc::c_char> = AtomicPtr::new(ptr::null_mut());
+    pub static FILES: [AtomicPtr<libc::c_char>; MAX_FILES] = [FREE; MAX_FILES];
+
+    // Whether a program that has the terminal is running, as in `foreground`
+    pub static FOREGROUND: AtomicBool = AtomicBool::new(false);
+
+    // Register a file to be removed if a signal kills lax, and return its slot. Out of slots, the
+    // file is still removed when it's dropped, just not on a signal
+    pub fn register(path: &Path) -> Option<usize> {
+        static HANDLE: Once = Once::new();
+        HANDLE.call_once(|| {
+            let handler = cleanup as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            for signal in [libc::SIGINT, libc::SIGQUIT, libc::SIGTERM, libc::SIGHUP] {
+                // SAFETY: a zeroed sigaction is valid, and sigaction() only writes to it
+                let mut current: libc::sigaction = unsafe { std::mem::zeroed() };
+                if unsafe { libc::sigaction(signal, ptr::null(), &mut current) } == 0
+                    && current.sa_sigaction == libc::SIG_DFL
+                {
+                    // SAFETY: `cleanup` only touches atomics and calls async-signal-safe functions
+                    unsafe { libc::signal(signal, handler) };
+                }
+            }
+        });
+
+        // The name is leaked rather than freed when the file is dropped, so a handler that's
+        // reading it can never find it freed out from under it
+        let name = CString::new(path.as_os_str().as_bytes()).ok()?.into_raw();
+        FILES.iter().position(|slot| {
+            slot.compare_exchange(ptr::null_mut(), name, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+        })
+    }
+
+    extern "C" fn cleanup(signal: libc::c_int) {
+        // The terminal sent these to the editor too, which decides what they mean
+        if (signal == libc::SIGINT || signal == libc::SIGQUIT) && FOREGROUND.load(Ordering::SeqCst)
+        {
+            return;
+        }
+        for slot in &FILES {
+            let name = slot.swap(ptr::null_mut(), Ordering::SeqCst);
+            if !name.is_null() {
+                // SAFETY: registered names are valid C strings that are never freed
+                unsafe { libc::unlink(name) };
+            }
+        }
+        // Die of the signal, as lax would have without this handler
+        // SAFETY: signal() and raise() have no memory safety requirements
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+    }
+}
@@ -0,0 +1,197 @@
+//! Detect what the user's terminal is capable of, so richer output can be skipped on dumb
+//! terminals (Emacs shells, CI logs, serial consoles, etc), and how the user likes paths colored.
+use std::{
+    env, fs,
+    io::{self, IsTerminal},
+    path::Path,
+    sync::OnceLock,
+};
+
+/// Capabilities of the terminal that interactive output (on stderr) is going to.
+pub struct Terminal {
+    /// Can we use ANSI escape sequences, such as colors?
+    pub color: bool,
+    /// Can we move the cursor around to redraw what we've printed? Unlike colors, this doesn't
+    /// go away with `NO_COLOR`
+    pub cursor: bool,
+    /// How paths are colored, like `ls` colors them
+    pub ls_colors: LsColors,
+}
+
+impl Terminal {
+    /// Get the (lazily detected) capabilities of the current terminal.
+    pub fn get() -> &'static Self {
+        static TERMINAL: OnceLock<Terminal> = OnceLock::new();
+        TERMINAL.get_or_init(|| {
+            Self::from_env(
+                env::var("TERM").ok().as_deref(),
+                env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()),
+                io::stderr().is_terminal(),
+                env::var("LS_COLORS").ok().as_deref(),
+            )
+        })
+    }
+
+    fn from_env(
+        term: Option<&str>,
+        no_color: bool,
+        is_terminal: bool,
+        ls_colors: Option<&str>,
+    ) -> Self {
+        // A missing TERM means there's no terminfo to go by, so assume the worst
+        let dumb = matches!(term, None | Some("") | Some("dumb"));
+        Self {
+            color: is_terminal && !dumb && !no_color,
+            cursor: is_terminal && !dumb,
+            ls_colors: LsColors::parse(ls_colors.unwrap_or_default()),
+        }
+    }
+
+    /// Wrap text in an ANSI SGR escape sequence (eg "1" for bold), if supported.
+    pub fn style(&self, sgr: &str, text: &str) -> String {
+        if self.color {
+            format!("\x1b[{sgr}m{text}\x1b[0m")
+        } else {
+            text.into()
+        }
+    }
+}
+
+/// The SGR sequences `ls` uses for kinds of paths, from `LS_COLORS` (as set by `dircolors`).
+/// Kinds it doesn't mention keep GNU `ls`'s defaults.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LsColors {
+    directory: String,
+    symlink: String,
+    executable: String,
+    file: String,
+    // File name suffixes, like ".tar.gz", and their colors. Later ones win
+    suffixes: Vec<(String, String)>,
+}
+
+impl LsColors {
+    fn parse(ls_colors: &str) -> Self {
+        let mut colors = LsColors {
+            directory: "01;34".into(),
+            symlink: "01;36".into(),
+            executable: "01;32".into(),
+            file: String::new(),
+            suffixes: Vec::new(),
+        };
+        for entry in ls_colors.split(':') {
+            let Some((key, sgr)) = entry.split_once('=') else {
+                continue;
+            };
+            match key {
+                "di" => colors.directory = sgr.into(),
+                "ln" => colors.symlink = sgr.into(),
+                "ex" => colors.executable = sgr.into(),
+                "fi" => colors.file = sgr.into(),
+                _ => {
+                    if let Some(suffix) = key.strip_prefix('*') {
+                        colors.suffixes.push((suffix.into(), sgr.into()));
+                    }
+                }
+            }
+        }
+        colors
+    }
+
+    /// The SGR sequence for a path, which may be empty. Symlinks aren't followed, like the walk.
+    pub fn color(&self, path: &Path) -> &str {
+        let Ok(metadata) = fs::symlink_metadata(path) else {
+            return &self.file;
+        };
+        if metadata.file_type().is_symlink() {
+            return &self.symlink;
+        }
+        if metadata.is_dir() {
+            return &self.directory;
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            if metadata.permissions().mode() & 0o111 != 0 {
+                return &self.executable;
+            }
+        }
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy())
+            .unwrap_or_default();
+        self.suffixes
+            .iter()
+            .rev()
+            .find(|(suffix, _)| name.ends_with(suffix.as_str()))
+            .map_or(&self.file, |(_, sgr)| sgr)
+    }
+}
+
+/// Keeps stdin in raw mode, where each key is read as soon as it's pressed and isn't echoed,
+/// until dropped. Ctrl-C is read like any other key, rather than killing lax with the terminal
+/// still raw.
+#[cfg(unix)]
+pub struct RawMode(libc::termios);
+
+#[cfg(unix)]
+impl RawMode {
+    /// Put stdin in raw mode, if it's a terminal.
+    pub fn enable() -> io::Result<Self> {
+        // SAFETY: `termios` is plain data, and is filled in by tcgetattr() before it's read
+        let mut original: libc::termios = unsafe { std::mem::zeroed() };
+        // SAFETY: `original` is valid for writes
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut raw = original;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        // SAFETY: `raw` is a valid termios
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(RawMode(original))
+    }
+}
+
+#[cfg(unix)]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // SAFETY: `self.0` is the valid termios stdin had before
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &self.0) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detection() {
+        let from_env =
+            |term, no_color, is_terminal| Terminal::from_env(term, no_color, is_terminal, None);
+        assert!(from_env(Some("xterm-256color"), false, true).color);
+        assert!(!from_env(Some("dumb"), false, true).color);
+        assert!(!from_env(Some(""), false, true).color);
+        assert!(!from_env(None, false, true).color);
+        assert!(!from_env(Some("xterm"), true, true).color);
+        assert!(from_env(Some("xterm"), true, true).cursor);
+        assert!(!from_env(Some("dumb"), false, true).cursor);
+        assert!(!from_env(Some("xterm"), false, false).color);
+
+        let terminal = from_env(Some("dumb"), false, true);
+        assert_eq!(terminal.style("1", "text"), "text");
+    }
+
+    #[test]
+    fn ls_colors() {
+        let colors = LsColors::parse("rs=0:di=01;33:*.rs=38;5;208:*.toml=0;31:bogus");
+        assert_eq!(colors.color(Path::new("src")), "01;33");
+        assert_eq!(colors.color(Path::new("src/lib.rs")), "38;5;208");
+        assert_eq!(colors.color(Path::new("Cargo.toml")), "0;31");
+        assert_eq!(colors.color(Path::new("README.md")), "");
+        assert_eq!(colors.color(Path::new("no/such/file.rs")), "");
+        assert_eq!(LsColors::parse("").color(Path::new("src")), "01;34");
+    }
+}
@@ -0,0 +1,462 @@
+//! Post-selector transformations, applied to each path an '@' pattern expands to.
+//!
+//! Transforms are composed into a [`Pipeline`], which runs them in order. The ones a config's
+//! settings ask for are always applied. Library users can add their own by implementing
+//! [`Transform`] and pushing them onto [`Config::post_transforms`].
+use std::{
+    env, fmt, fs, io,
+    path::{Component, Path, PathBuf, MAIN_SEPARATOR},
+};
+
+use crate::{
+    errors::{LaxError, LaxResult as Result},
+    quote, Config, PathPart, PathStyle, SymlinkParent,
+};
+
+/// A transformation applied to each selected path.
+pub trait Transform {
+    /// Transform a single path.
+    fn apply(&self, path: String) -> Result<String>;
+}
+
+/// An ordered list of transforms.
+#[derive(Default)]
+pub struct Pipeline {
+    transforms: Vec<Box<dyn Transform>>,
+}
+
+impl Pipeline {
+    /// Create an empty pipeline, which leaves paths as they are.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Build the pipeline described by a config's transform settings.
+    pub fn from_config(config: &Config) -> Self {
+        let mut pipeline = Self::new();
+        if config.resolve_symlinks {
+            pipeline.push(ResolveSymlinks);
+        }
+        if config.transform_files_to_dirs {
+            pipeline.push(FilesToDirs::from_config(config));
+        }
+        match config.path_style {
+            PathStyle::AsMatched => {}
+            PathStyle::Absolute => {
+                pipeline.push(Absolute);
+            }
+            PathStyle::Relative => {
+                pipeline.push(Relative);
+            }
+            PathStyle::Canonical => {
+                pipeline.push(Canonicalize);
+            }
+        }
+        match config.path_part {
+            PathPart::Whole => {}
+            PathPart::Basename => {
+                pipeline.push(Basename);
+            }
+            PathPart::Dirname => {
+                pipeline.push(Dirname);
+            }
+        }
+        pipeline
+    }
+
+    /// Add a transform to the end of the pipeline.
+    pub fn push(&mut self, transform: impl Transform + 'static) -> &mut Self {
+        self.transforms.push(Box::new(transform));
+        self
+    }
+
+    /// Does this pipeline have no transforms?
+    pub fn is_empty(&self) -> bool {
+        self.transforms.is_empty()
+    }
+
+    /// Run every path through each transform in turn.
+    pub fn apply(&self, paths: Vec<String>) -> Result<Vec<String>> {
+        paths
+            .into_iter()
+            .map(|path| {
+                self.transforms
+                    .iter()
+                    .try_fold(path, |path, transform| transform.apply(path))
+            })
+            .collect()
+    }
+}
+
+/// Transform files into the directory they're in. Directories are left as they are.
+#[derive(Debug, Clone, Default)]
+pub struct FilesToDirs {
+    /// Which parent to use for symlinks.
+    pub symlink_parent: SymlinkParent,
+    /// Canonicalize the resulting directory.
+    pub canonicalize: bool,
+}
+
+impl FilesToDirs {
+    /// Use a config's `symlink_parent` and `canonicalize_parent` settings.
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            symlink_parent: config.symlink_parent,
+            canonicalize: config.canonicalize_parent,
+        }
+    }
+}
+
+impl Transform for FilesToDirs {
+    fn apply(&self, path: String) -> Result<String> {
+        let directory = if fs::metadata(&path)?.is_dir() {
+            path
+        } else {
+            // A symlink's parent directory can be either where the link lives or where its target
+            // lives
+            let file = match self.symlink_parent {
+                SymlinkParent::Link => PathBuf::from(&path),
+                SymlinkParent::Target => fs::canonicalize(&path)?,
+            };
+            match file.parent() {
+                Some(parent) => parent.display().to_string(),
+                None => return Err(LaxError::NoParent(path)),
+            }
+        };
+
+        if self.canonicalize {
+            return Canonicalize.apply(directory);
+        }
+        Ok(directory)
+    }
+}
+
+/// Transform symlinks into the paths they point to, following links to links. Targets are joined
+/// to the link's directory, so relative links give relative paths. Paths that aren't symlinks are
+/// left as they are, and a link to nothing is a [`LaxError::BrokenSymlink`] error.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ResolveSymlinks;
+
+impl Transform for ResolveSymlinks {
+    fn apply(&self, path: String) -> Result<String> {
+        if !fs::symlink_metadata(&path)?.file_type().is_symlink() {
+            return Ok(path);
+        }
+        match fs::metadata(&path) {
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Err(LaxError::BrokenSymlink(path))
+            }
+            // Like a loop of links
+            Err(err) => return Err(err.into()),
+        }
+
+        let mut resolved = PathBuf::from(&path);
+        while let Ok(target) = fs::read_link(&resolved) {
+            resolved = match resolved.parent() {
+                Some(parent) => parent.join(target),
+                None => target,
+            };
+        }
+        Ok(resolved.display().to_string())
+    }
+}
+
+/// Transform paths into absolute paths, with all symlinks resolved.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Canonicalize;
+
+impl Transform for Canonicalize {
+    fn apply(&self, path: String) -> Result<String> {
+        Ok(fs::canonicalize(path)?.display().to_string())
+    }
+}
+
+/// Transform paths into absolute paths, without resolving symlinks. `.` and `..` components are
+/// removed without looking at the filesystem, like `realpath -s`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Absolute;
+
+impl Transform for Absolute {
+    fn apply(&self, path: String) -> Result<String> {
+        Ok(with_trailing_separator(&path, absolute(Path::new(&path))?))
+    }
+}
+
+/// Transform paths to be relative to the current directory, going up with `..` for paths
+/// outside it. Like [`Absolute`], symlinks aren't resolved.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Relative;
+
+impl Transform for Relative {
+    fn apply(&self, path: String) -> Result<String> {
+        let absolute_path = absolute(Path::new(&path))?;
+        let base = absolute(&env::current_dir()?)?;
+        let common = absolute_path
+            .components()
+            .zip(base.components())
+            .take_while(|(a, b)| a == b)
+            .count();
+        // Paths on another drive can't be made relative
+        if common == 0 {
+            return Ok(with_trailing_separator(&path, absolute_path));
+        }
+        let mut relative: PathBuf = base.components().skip(common).map(|_| "..").collect();
+        relative.extend(absolute_path.components().skip(common));
+        if relative.as_os_str().is_empty() {
+            relative.push(".");
+        }
+        Ok(with_trailing_separator(&path, relative))
+    }
+}
+
+/// Transform paths into their last component, like `basename`. Paths without one, like `/`, are
+/// left as they are.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Basename;
+
+impl Transform for Basename {
+    fn apply(&self, path: String) -> Result<String> {
+        let Some(name) = Path::new(&path).file_name() else {
+            return Ok(path);
+        };
+        Ok(with_trailing_separator(&path, name.into()))
+    }
+}
+
+/// Transform paths into everything but their last component, like `dirname`. Paths in the
+/// current directory become `.`, and paths without a parent, like `/`, are left as they are.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Dirname;
+
+impl Transform for Dirname {
+    fn apply(&self, path: String) -> Result<String> {
+        Ok(match Path::new(&path).parent() {
+            Some(parent) if parent.as_os_str().is_empty() => ".".into(),
+            Some(parent) => parent.display().to_string(),
+            None => path,
+        })
+    }
+}
+
+/// A filename modifier at the end of an '@' pattern, like vim's and zsh's, as in `@main.rs:r`.
+/// Modifiers can be chained, as in `:t:r`, and apply in order after every other transform.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilenameModifier {
+    /// `:h`: the head, with the last component removed, or `.` if there's nothing else. Like in
+    /// vim, a trailing separator is all that's removed from a directory.
+    Head,
+    /// `:t`: the tail, which is the last component, like [`Basename`].
+    Tail,
+    /// `:r`: the root, with the last extension removed.
+    Root,
+    /// `:e`: the last extension, without its '.'. Empty if there isn't one.
+    Extension,
+}
+
+impl FilenameModifier {
+    /// The modifier written as `:` followed by `letter`, if any.
+    pub fn from_letter(letter: char) -> Option<Self> {
+        Some(match letter {
+            'h' => FilenameModifier::Head,
+            't' => FilenameModifier::Tail,
+            'r' => FilenameModifier::Root,
+            'e' => FilenameModifier::Extension,
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for FilenameModifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let letter = match self {
+            FilenameModifier::Head => 'h',
+            FilenameModifier::Tail => 't',
+            FilenameModifier::Root => 'r',
+            FilenameModifier::Extension => 'e',
+        };
+        write!(f, ":{letter}")
+    }
+}
+
+impl Transform for FilenameModifier {
+    fn apply(&self, path: String) -> Result<String> {
+        let trimmed = path.trim_end_matches(['/', MAIN_SEPARATOR]);
+        Ok(match self {
+            FilenameModifier::Head if trimmed.len() < path.len() && !trimmed.is_empty() => {
+                trimmed.into()
+            }
+            FilenameModifier::Head => Dirname.apply(path)?,
+            FilenameModifier::Tail => Basename.apply(path)?,
+            FilenameModifier::Root => match Path::new(trimmed).extension() {
+                Some(extension) => {
+                    let root = &trimmed[..trimmed.len() - extension.len() - 1];
+                    format!("{root}{}", &path[trimmed.len()..])
+                }
+                None => path,
+            },
+            FilenameModifier::Extension => Path::new(trimmed)
+                .extension()
+                .map(|extension| extension.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        })
+    }
+}
+
+// Make a path absolute, dropping `.` and resolving `..` lexically
+fn absolute(path: &Path) -> io::Result<PathBuf> {
+    let base = if path.is_absolute() {
+        PathBuf::new()
+    } else {
+        env::current_dir()?
+    };
+    let mut absolute = PathBuf::new();
+    for component in base.components().chain(path.components()) {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                absolute.pop();
+            }
+            component => absolute.push(component),
+        }
+    }
+    Ok(absolute)
+}
+
+// Directories are matched with a trailing separator, which rewriting them shouldn't lose
+fn with_trailing_separator(original: &str, path: PathBuf) -> String {
+    let mut path = path.display().to_string();
+    let is_dir = original.ends_with('/') || original.ends_with(MAIN_SEPARATOR);
+    if is_dir && !path.ends_with(MAIN_SEPARATOR) {
+        path.push(MAIN_SEPARATOR);
+    }
+    path
+}
+
+/// Transform paths to be relative to a directory, where possible. Paths outside the directory are
+/// left as they are.
+#[derive(Debug, Clone)]
+pub struct RelativeTo(pub PathBuf);
+
+impl Transform for RelativeTo {
+    fn apply(&self, path: String) -> Result<String> {
+        let absolute = fs::canonicalize(&path)?;
+        let base = fs::canonicalize(&self.0)?;
+        Ok(match absolute.strip_prefix(&base) {
+            Ok(relative) if relative == Path::new("") => ".".into(),
+            Ok(relative) => relative.display().to_string(),
+            Err(_) => path,
+        })
+    }
+}
+
+/// Quote paths so bash, zsh, and fish read each back as a single word. See [`quote::quote`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Quote;
+
+impl Transform for Quote {
+    fn apply(&self, path: String) -> Result<String> {
+        Ok(quote::quote(&path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pipeline() {
+        let mut pipeline = Pipeline::new();
+        assert!(pipeline.is_empty());
+        assert_eq!(
+            pipeline.apply(vec!["./src/lib.rs".into()]).unwrap(),
+            vec!["./src/lib.rs"]
+        );
+
+        pipeline
+            .push(FilesToDirs::default())
+            .push(RelativeTo("src".into()))
+            .push(Quote);
+        assert_eq!(
+            pipeline
+                .apply(vec!["./src/lib.rs".into(), "./tests/foobar/foo".into()])
+                .unwrap(),
+            vec![".", "./tests/foobar"]
+        );
+        assert_eq!(Quote.apply("it's".into()).unwrap(), "'it'\\''s'");
+    }
+
+    #[test]
+    fn path_parts() {
+        let apply = |transform: &dyn Transform, path: &str| transform.apply(path.into()).unwrap();
+        assert_eq!(apply(&Basename, "./src/lib.rs"), "lib.rs");
+        assert_eq!(apply(&Basename, "./tests/foobar/"), "foobar/");
+        assert_eq!(apply(&Basename, "/"), "/");
+        assert_eq!(apply(&Dirname, "./src/lib.rs"), "./src");
+        assert_eq!(apply(&Dirname, "./tests/foobar/"), "./tests");
+        assert_eq!(apply(&Dirname, "lib.rs"), ".");
+        assert_eq!(apply(&Dirname, "/"), "/");
+    }
+
+    #[test]
+    fn modifiers() {
+        let apply = |modifier: FilenameModifier, path: &str| modifier.apply(path.into()).unwrap();
+        assert_eq!(apply(FilenameModifier::Head, "./src/main.rs"), "./src");
+        assert_eq!(apply(FilenameModifier::Head, "main.rs"), ".");
+        assert_eq!(apply(FilenameModifier::Head, "./src/"), "./src");
+        assert_eq!(apply(FilenameModifier::Head, "/"), "/");
+        assert_eq!(apply(FilenameModifier::Tail, "./src/main.rs"), "main.rs");
+        assert_eq!(apply(FilenameModifier::Root, "./src/main.rs"), "./src/main");
+        assert_eq!(
+            apply(FilenameModifier::Root, "./archive.tar.gz"),
+            "./archive.tar"
+        );
+        assert_eq!(apply(FilenameModifier::Root, "./.bashrc"), "./.bashrc");
+        assert_eq!(apply(FilenameModifier::Root, "./pkg.d/"), "./pkg/");
+        assert_eq!(apply(FilenameModifier::Extension, "./archive.tar.gz"), "gz");
+        assert_eq!(apply(FilenameModifier::Extension, "./Makefile"), "");
+        assert_eq!(FilenameModifier::from_letter('x'), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let dir = env::temp_dir().join(format!("lax_test_resolve_symlinks_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("sub").join("real"), "").unwrap();
+        symlink("sub/real", dir.join("link")).unwrap();
+        symlink("link", dir.join("link_to_link")).unwrap();
+        symlink("missing", dir.join("broken")).unwrap();
+
+        let resolve = |name: &str| ResolveSymlinks.apply(dir.join(name).display().to_string());
+        let real = dir.join("sub/real").display().to_string();
+        assert_eq!(resolve("link").unwrap(), real);
+        assert_eq!(resolve("link_to_link").unwrap(), real);
+        assert_eq!(resolve("sub/real").unwrap(), real);
+        assert!(matches!(resolve("broken"), Err(LaxError::BrokenSymlink(_))));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn path_styles() {
+        let cwd = env::current_dir().unwrap();
+        let apply = |transform: &dyn Transform, path: &Path| {
+            transform.apply(path.display().to_string()).unwrap()
+        };
+        let src = format!("src{MAIN_SEPARATOR}");
+        assert_eq!(
+            apply(&Absolute, Path::new("./src/../src/")),
+            cwd.join(&src).display().to_string()
+        );
+        assert_eq!(apply(&Relative, &cwd.join("src/")), src);
+        assert_eq!(apply(&Relative, &cwd), ".");
+        assert_eq!(
+            apply(&Relative, &cwd.parent().unwrap().join("elsewhere")),
+            Path::new("..").join("elsewhere").display().to_string()
+        );
+    }
+}
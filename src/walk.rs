@@ -0,0 +1,454 @@
+//! Walking a pattern's entry points for its matches. Matches are found lazily, whether the
+//! filesystem is walked, or entries are read from an index, the daemon, or a backend command.
+
+use std::{
+    collections::VecDeque,
+    fs, io, mem,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use globset::GlobMatcher;
+use log::{debug, info, trace};
+use walkdir::{DirEntry, WalkDir};
+
+use crate::{
+    backend, daemon, expand_tilde, index, Access, EntryPointRule, EntryType, LaxError, Match,
+    Result, SearchStats,
+};
+
+// Decides which entries a walk descends into
+#[derive(Clone)]
+pub(crate) struct WalkFilter {
+    pub(crate) search_hidden: bool,
+    // Hidden components the pattern names explicitly, which are searched regardless
+    pub(crate) named: Vec<GlobMatcher>,
+    // Names of directories to skip
+    pub(crate) prune: Vec<String>,
+    // A command that lists the entries instead, from the rules
+    pub(crate) backend: Option<String>,
+}
+
+impl WalkFilter {
+    // Apply the config's rules for the directories an entry point is under
+    pub(crate) fn for_entry_point(&self, entry_point: &Path, rules: &[EntryPointRule]) -> Self {
+        let mut filter = self.clone();
+        let Ok(entry_point) = entry_point.canonicalize() else {
+            return filter;
+        };
+        for rule in rules {
+            let under = fs::canonicalize(expand_tilde(&rule.path.to_string_lossy()).as_ref())
+                .is_ok_and(|path| entry_point.starts_with(path));
+            if !under {
+                continue;
+            }
+            if let Some(search_hidden) = rule.search_hidden {
+                filter.search_hidden = search_hidden;
+            }
+            filter.prune.extend(rule.prune.iter().cloned());
+            if let Some(backend) = &rule.backend {
+                filter.backend = Some(backend.clone());
+            }
+        }
+        filter
+    }
+
+    pub(crate) fn allows(&self, entry: &DirEntry) -> bool {
+        // The entry point itself is always searched, even if it's hidden
+        if entry.depth() == 0 {
+            return true;
+        }
+        let Some(file_name) = entry.file_name().to_str() else {
+            return true;
+        };
+        if entry.file_type().is_dir() && self.prune.iter().any(|name| name == file_name) {
+            debug!("Pruning {}", entry.path().display());
+            return false;
+        }
+        let is_hidden = file_name.starts_with('.') && file_name != "." && file_name != "..";
+        let allowed = self.search_hidden
+            || !is_hidden
+            || self.named.iter().any(|glob| glob.is_match(file_name));
+        if !allowed {
+            debug!("Skipping hidden {}", entry.path().display());
+        }
+        allowed
+    }
+
+    // Whether the entries this allows can be read from an index. Hidden entries the pattern
+    // names aren't indexed, unless every hidden entry is
+    pub(crate) fn indexable(&self) -> bool {
+        self.search_hidden || self.named.is_empty()
+    }
+
+    // Describe what this allows, so trees walked differently get different indexes
+    pub(crate) fn index_settings(&self) -> String {
+        format!(
+            "search_hidden={} prune={}",
+            self.search_hidden,
+            self.prune.join(":")
+        )
+    }
+}
+
+pub(crate) type EntryFilter = Box<dyn FnMut(&DirEntry) -> bool>;
+
+// The entries under an entry point, as they're walked, or as they're read from its index
+pub(crate) enum Entries {
+    Walking(walkdir::FilterEntry<walkdir::IntoIter, EntryFilter>),
+    Indexed(std::vec::IntoIter<index::Entry>),
+}
+
+impl Entries {
+    // Get the next entry's path relative to the entry point, whether it's a directory, and its
+    // depth. Entries that can't be read, or whose paths aren't UTF-8, are skipped
+    fn next(&mut self, entry_point: &Path) -> Option<(String, bool, usize)> {
+        match self {
+            Entries::Walking(entries) => loop {
+                let entry = match entries.next()? {
+                    Ok(entry) => entry,
+                    Err(err) => {
+                        debug!("Skipping unreadable entry: {err}");
+                        continue;
+                    }
+                };
+                let Ok(relative) = entry.path().strip_prefix(entry_point) else {
+                    continue;
+                };
+                let Some(relative) = relative.to_str() else {
+                    continue;
+                };
+                return Some((relative.into(), entry.file_type().is_dir(), entry.depth()));
+            },
+            Entries::Indexed(entries) => {
+                let entry = entries.next()?;
+                Some((entry.relative, entry.is_dir, entry.depth))
+            }
+        }
+    }
+}
+
+// A walk of the filesystem for a pattern's matches, across each of its entry points. Matches are
+// found lazily, so the walk can be paused once there are enough, and resumed if more are wanted.
+pub(crate) struct Walk {
+    pub(crate) entry_points: std::vec::IntoIter<PathBuf>,
+    // The entry point being walked, and where we are in it
+    pub(crate) current: Option<(PathBuf, Entries)>,
+    pub(crate) glob: GlobMatcher,
+    // Matches of this are skipped, even if they match `glob`
+    pub(crate) exclusion: Option<GlobMatcher>,
+    pub(crate) filter: WalkFilter,
+    pub(crate) rules: Vec<EntryPointRule>,
+    pub(crate) match_with_dirs: bool,
+    pub(crate) match_with_files: bool,
+    // Matches must be one of these types, if there are any
+    pub(crate) types: Vec<EntryType>,
+    // Matches must have one of these extensions, if there are any
+    pub(crate) extensions: Vec<String>,
+    // Matches must be owned by this user ID, if given
+    pub(crate) owner: Option<u32>,
+    pub(crate) access: Access,
+    pub(crate) append_dir_slash: bool,
+    // Where entry points' indexes are kept, if they're used
+    pub(crate) cache_dir: Option<PathBuf>,
+    pub(crate) refresh_cache: bool,
+    // Paths searched instead of the filesystem, in filter mode
+    pub(crate) candidates: Option<Vec<String>>,
+    // What's been walked so far. Matches are counted by whoever takes them
+    pub(crate) stats: SearchStats,
+    // Whether each directory's entries are walked in order of their names
+    pub(crate) sorted: bool,
+    // How long the walk may take, not counting while it's paused
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) walked: Duration,
+}
+
+impl Iterator for Walk {
+    type Item = Result<Match>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = Instant::now();
+        let next = self.walk(start);
+        self.walked += start.elapsed();
+        next
+    }
+}
+
+impl Walk {
+    // Walk until the next match. `start` is when this leg of the walk started
+    fn walk(&mut self, start: Instant) -> Option<Result<Match>> {
+        loop {
+            // Checked between entries, so a single slow directory can still overrun
+            let timeout = self.timeout;
+            if let Some(timeout) =
+                timeout.filter(|&timeout| self.walked + start.elapsed() > timeout)
+            {
+                info!("Giving up on the walk after {timeout:?}");
+                self.entry_points = Vec::new().into_iter();
+                self.current = None;
+                return Some(Err(LaxError::TimedOut(String::new(), timeout, Vec::new())));
+            }
+            let Some((entry_point, entries)) = &mut self.current else {
+                let entry_point = self.entry_points.next()?;
+                let walk_filter = self.filter.for_entry_point(&entry_point, &self.rules);
+                // Listing an entry point's entries can take a while too, so whatever lists them
+                // stops when the walk runs out of time. Then the check above gives up on the walk
+                let deadline = self
+                    .timeout
+                    .map(|timeout| start + timeout.saturating_sub(self.walked));
+                let entries = match (&walk_filter.backend, &self.cache_dir) {
+                    _ if self.candidates.is_some() => {
+                        let candidates = self.candidates.as_deref().unwrap_or_default();
+                        Entries::Indexed(backend::candidates(candidates, &entry_point).into_iter())
+                    }
+                    (Some(backend), _) => match backend::list(backend, &entry_point, deadline) {
+                        Ok(entries) => Entries::Indexed(entries.unwrap_or_default().into_iter()),
+                        Err(err) => return Some(Err(err)),
+                    },
+                    (None, Some(cache_dir)) if walk_filter.indexable() => {
+                        #[cfg(unix)]
+                        let entries =
+                            daemon::query(cache_dir, &entry_point, &walk_filter, deadline);
+                        #[cfg(not(unix))]
+                        let entries = None;
+                        let entries = entries.unwrap_or_else(|| {
+                            index::entries(
+                                cache_dir,
+                                &entry_point,
+                                &walk_filter.index_settings(),
+                                self.refresh_cache,
+                                deadline,
+                                |entry| walk_filter.allows(entry),
+                            )
+                        });
+                        Entries::Indexed(entries.into_iter())
+                    }
+                    _ => {
+                        info!("Walking {}", entry_point.display());
+                        let filter: EntryFilter = Box::new(move |entry| walk_filter.allows(entry));
+                        let walk = WalkDir::new(&entry_point);
+                        let walk = if self.sorted {
+                            walk.sort_by_file_name()
+                        } else {
+                            walk
+                        };
+                        Entries::Walking(walk.into_iter().filter_entry(filter))
+                    }
+                };
+                self.current = Some((entry_point, entries));
+                continue;
+            };
+            // Match against the path relative to the entry point, as in "./foo/bar"
+            let Some((relative, is_dir, depth)) = entries.next(entry_point) else {
+                self.current = None;
+                continue;
+            };
+            if is_dir {
+                self.stats.directories += 1;
+            } else {
+                self.stats.files += 1;
+            }
+
+            // Patterns always use '/', even on Windows
+            let path_name = if relative.is_empty() {
+                ".".to_string()
+            } else {
+                format!("./{}", relative.replace(std::path::MAIN_SEPARATOR, "/"))
+            };
+            if !self.glob.is_match(&path_name) {
+                trace!("Skipping {path_name}, which doesn't match");
+                continue;
+            }
+            if let Some(exclusion) = &self.exclusion {
+                if exclusion.is_match(&path_name) {
+                    debug!("Skipping {path_name}, which the exclusion matches");
+                    continue;
+                }
+            }
+
+            // String comparison is a lot faster than fetching the metadata, so only do this
+            // for paths that match. Like the walk, don't follow symlinks. Candidates are only
+            // paths, so there's nothing on disk to ask about them
+            let relative = if relative.is_empty() { "." } else { &relative };
+            let path = entry_point.join(relative);
+            let metadata = if self.candidates.is_some() {
+                None
+            } else {
+                match fs::symlink_metadata(&path) {
+                    Ok(metadata) => Some(metadata),
+                    // It may have been deleted since it was indexed
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                        debug!("Skipping {path_name}, which no longer exists");
+                        continue;
+                    }
+                    Err(err) => return Some(Err(err.into())),
+                }
+            };
+            let (is_dir, is_file) = match &metadata {
+                Some(metadata) => (metadata.is_dir(), metadata.is_file()),
+                None => (is_dir, !is_dir),
+            };
+            let matched = (self.match_with_dirs && (self.match_with_files || is_dir))
+                || (self.match_with_files && is_file);
+            if !matched {
+                debug!(
+                    "Skipping {path_name}, as only {} are matched",
+                    if self.match_with_dirs {
+                        "directories"
+                    } else {
+                        "files"
+                    }
+                );
+                continue;
+            }
+            let typed =
+                |entry_type: &EntryType| entry_type.matches(&path, metadata.as_ref(), is_dir);
+            if !self.types.is_empty() && !self.types.iter().any(typed) {
+                debug!("Skipping {path_name}, which isn't any of the types asked for");
+                continue;
+            }
+            if !self.extensions.is_empty() && !has_extension(&path, &self.extensions) {
+                debug!("Skipping {path_name}, which doesn't have any of the extensions asked for");
+                continue;
+            }
+            if !owned_by(metadata.as_ref(), self.owner) {
+                debug!("Skipping {path_name}, which isn't owned by the user asked for");
+                continue;
+            }
+            if !self.access.allows(&path) {
+                debug!("Skipping {path_name}, which we don't have the access asked for to");
+                continue;
+            }
+
+            let mut result = path.to_string_lossy().to_string();
+            if is_dir && self.append_dir_slash {
+                result.push(std::path::MAIN_SEPARATOR)
+            }
+            return Some(Ok(match metadata {
+                Some(metadata) => Match::new(result, &metadata, depth),
+                None => Match::without_metadata(result, is_dir, depth),
+            }));
+        }
+    }
+}
+
+// Pull the rest of the matches, but only keep the last `len` of them in `paths`. Returns how many
+// were dropped
+pub(crate) fn take_last(
+    matches: &mut impl Iterator<Item = Result<Match>>,
+    paths: &mut Vec<Match>,
+    len: usize,
+) -> Result<usize> {
+    let mut last = VecDeque::with_capacity(len);
+    let mut dropped = 0;
+    for path in matches {
+        let path = path?;
+        if last.len() == len {
+            last.pop_front();
+            dropped += 1;
+        }
+        last.push_back(path);
+    }
+    paths.extend(last);
+    Ok(dropped)
+}
+
+// Fill in the pattern of a search that timed out, and what it found before then
+pub(crate) fn timed_out(err: LaxError, pattern: &str, paths: &[Match]) -> LaxError {
+    match err {
+        LaxError::TimedOut(_, timeout, _) => LaxError::TimedOut(
+            pattern.into(),
+            timeout,
+            paths.iter().map(Match::to_path_string).collect(),
+        ),
+        err => err,
+    }
+}
+
+// Pull matches into `paths` until it holds `len` of them, or there are no more
+pub(crate) fn take_matches(
+    matches: &mut impl Iterator<Item = Result<Match>>,
+    paths: &mut Vec<Match>,
+    len: Option<usize>,
+) -> Result<()> {
+    let full = |paths: &Vec<Match>| len.is_some_and(|len| paths.len() >= len);
+    if full(paths) {
+        return Ok(());
+    }
+    for path in matches {
+        paths.push(path?);
+        if full(paths) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// Whether a path's name ends with one of `extensions`, which may be given with or without their
+// leading '.'. Names that are nothing but the extension, like `.rs`, don't count
+pub(crate) fn has_extension(path: &Path, extensions: &[String]) -> bool {
+    let Some(name) = path.file_name() else {
+        return false;
+    };
+    let name = name.to_string_lossy().to_lowercase();
+    extensions.iter().any(|extension| {
+        let extension = extension.trim_start_matches('.').to_lowercase();
+        name.strip_suffix(&extension)
+            .and_then(|stem| stem.strip_suffix('.'))
+            .is_some_and(|stem| !stem.is_empty())
+    })
+}
+
+// Look up the ID of a user given by name or ID
+#[cfg(unix)]
+pub(crate) fn owner_id(owner: Option<&str>) -> Result<Option<u32>> {
+    use std::{ffi::CString, ptr};
+
+    let Some(owner) = owner else {
+        return Ok(None);
+    };
+    if let Ok(id) = owner.parse() {
+        return Ok(Some(id));
+    }
+    let unknown = || LaxError::UnknownUser(owner.into());
+    let name = CString::new(owner).map_err(|_| unknown())?;
+    // SAFETY: An all-zero passwd is valid, as it's only pointers and integers
+    let mut entry: libc::passwd = unsafe { mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 16 * 1024];
+    let mut found = ptr::null_mut();
+    // SAFETY: Every pointer is valid for the call, and `buffer` for writes of its length
+    let status = unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            &mut entry,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut found,
+        )
+    };
+    if status != 0 || found.is_null() {
+        return Err(unknown());
+    }
+    Ok(Some(entry.pw_uid))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn owner_id(_owner: Option<&str>) -> Result<Option<u32>> {
+    Ok(None)
+}
+
+// Whether an entry is owned by `owner`, if given. Entries without metadata, like the candidates of
+// filter mode, can't be checked
+#[cfg(unix)]
+pub(crate) fn owned_by(metadata: Option<&fs::Metadata>, owner: Option<u32>) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match owner {
+        Some(owner) => metadata.is_some_and(|metadata| metadata.uid() == owner),
+        None => true,
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn owned_by(_metadata: Option<&fs::Metadata>, _owner: Option<u32>) -> bool {
+    true
+}
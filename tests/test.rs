@@ -1,5 +1,14 @@
+use std::{env, fs};
+
 fn setup_command() -> assert_cmd::cmd::Command {
-    assert_cmd::Command::cargo_bin("lax").unwrap()
+    let mut command = assert_cmd::Command::cargo_bin("lax").unwrap();
+    // Don't let the user's own config or history interfere with tests
+    command.env("LAX_CONFIG", "tests/no_such_config");
+    command.env(
+        "XDG_DATA_HOME",
+        env::temp_dir().join(format!("lax_test_data_{}", std::process::id())),
+    );
+    command
 }
 
 // Lax should only work when presented with a binary
@@ -26,6 +35,123 @@ fn fallback_binary() {
         .stdout("hello\n");
 }
 
+// Programs are only run from the current directory when asked for explicitly
+#[cfg(unix)]
+#[test]
+fn program_resolution() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let directory = env::temp_dir().join(format!("lax_test_resolution_{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let script = directory.join("lax_test_program");
+    fs::write(&script, "#!/bin/sh\necho ran\n").unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    let path = format!(".:{}", env::var("PATH").unwrap());
+
+    setup_command()
+        .current_dir(&directory)
+        .env("PATH", &path)
+        .arg("lax_test_program")
+        .assert()
+        .code(126);
+    setup_command()
+        .current_dir(&directory)
+        .env("PATH", &path)
+        .arg("./lax_test_program")
+        .assert()
+        .success()
+        .stdout("ran\n");
+
+    let output = setup_command()
+        .args(["--print-resolved", "nonexistent|sh"])
+        .output()
+        .unwrap();
+    let resolved = String::from_utf8(output.stdout).unwrap();
+    assert!(std::path::Path::new(resolved.trim_end()).is_absolute());
+    assert!(resolved.ends_with("/sh\n"));
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+// With --spawn, lax waits for the program and passes on its exit status
+#[cfg(unix)]
+#[test]
+fn spawn() {
+    setup_command()
+        .args(["--spawn", "sh", "-c", "echo hello; exit 3"])
+        .assert()
+        .code(3)
+        .stdout("hello\n");
+    setup_command()
+        .args(["--spawn", "sh", "-c", "kill -TERM $$"])
+        .assert()
+        .code(128 + 15);
+}
+
+// Signals sent to lax while it waits on a program are passed on to the program
+#[cfg(unix)]
+#[test]
+fn spawn_forwards_signals() {
+    use std::io::{BufRead, BufReader};
+    use std::process::{Command, Stdio};
+
+    let mut lax = Command::new(assert_cmd::cargo::cargo_bin("lax"))
+        .env("LAX_CONFIG", "tests/no_such_config")
+        .args(["--spawn", "sh", "-c"])
+        .arg("trap 'echo terminated; exit 7' TERM; echo ready; sleep 10 & wait")
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdout = BufReader::new(lax.stdout.take().unwrap());
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+    assert_eq!(line, "ready\n");
+
+    Command::new("kill")
+        .args(["-TERM", &lax.id().to_string()])
+        .status()
+        .unwrap();
+    line.clear();
+    stdout.read_line(&mut line).unwrap();
+    assert_eq!(line, "terminated\n");
+    assert_eq!(lax.wait().unwrap().code(), Some(7));
+}
+
+// A signal sent to lax stops --each from starting any more commands
+#[cfg(unix)]
+#[test]
+fn each_stops_on_signals() {
+    use std::io::{BufRead, BufReader, Read};
+    use std::process::{Command, Stdio};
+
+    let dir = std::env::temp_dir().join(format!("lax_test_each_signals_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let script = dir.join("script");
+    std::fs::write(&script, "echo \"ran $1\"; sleep 10 & wait\n").unwrap();
+
+    let mut lax = Command::new(assert_cmd::cargo::cargo_bin("lax"))
+        .env("LAX_CONFIG", "tests/no_such_config")
+        .arg("--each")
+        .arg(format!("sh {}", script.display()))
+        .arg("@fo[ox]^a")
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdout = BufReader::new(lax.stdout.take().unwrap());
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+    assert_eq!(line, "ran ./tests/foobar/foo\n");
+
+    Command::new("kill")
+        .args(["-TERM", &lax.id().to_string()])
+        .status()
+        .unwrap();
+    let mut rest = String::new();
+    stdout.read_to_string(&mut rest).unwrap();
+    assert_eq!(rest, "");
+    assert_eq!(lax.wait().unwrap().code(), Some(128 + 15));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
 // Ensure argument parser is working correctly
 #[test]
 fn help_flag() {
@@ -35,6 +161,74 @@ fn help_flag() {
     // and there's no point in checking for duplicates
     setup_command().arg("-hh").assert().success();
 }
+// The shell integration completes every flag, and is valid shell
+#[test]
+fn shell_init() {
+    let output = setup_command()
+        .args(["--shell-init", "all"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let script = String::from_utf8(output.stdout).unwrap();
+    for flag in [
+        "--select-all",
+        "--max-results",
+        "--shell-init",
+        "-j",
+        "--help",
+    ] {
+        assert!(script.contains(flag), "{} isn't completed", flag);
+    }
+    assert!(script.contains("lcd()"));
+    #[cfg(unix)]
+    assert_cmd::Command::new("bash")
+        .args(["-n", "-c", &script])
+        .assert()
+        .success();
+
+    let output = setup_command()
+        .args(["--shell-init", "completions"])
+        .output()
+        .unwrap();
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("lcd()"));
+    setup_command()
+        .args(["--shell-init", "fish"])
+        .assert()
+        .failure();
+
+    let output = setup_command()
+        .args(["--shell-init", "all", "--shell", "fish"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let script = String::from_utf8(output.stdout).unwrap();
+    assert!(script.contains("function lcd"));
+    assert!(script.contains("complete -c lax -s j -l jobs -r"));
+    assert!(script.contains("--shell fish | source"));
+    setup_command()
+        .args(["--shell-init", "all", "--shell", "csh"])
+        .assert()
+        .failure();
+    setup_command()
+        .args(["--shell", "fish", "echo"])
+        .assert()
+        .failure();
+}
+#[test]
+fn version_flag() {
+    setup_command()
+        .arg("--version")
+        .assert()
+        .success()
+        .stdout(format!("lax {}\n", env!("CARGO_PKG_VERSION")));
+    let output = setup_command().arg("--version=json").output().unwrap();
+    assert!(output.status.success());
+    let version = String::from_utf8(output.stdout).unwrap();
+    assert!(version.starts_with("{\"name\":\"lax\",\"version\":"));
+    // The binary needs every feature
+    assert!(version.contains("\"features\":[\"config\",\"regex\",\"tilde\""));
+    setup_command().arg("--version=yaml").assert().failure();
+}
 #[test]
 fn no_such_argument() {
     setup_command().arg("--tinkleberries").assert().failure();
@@ -85,6 +279,11 @@ fn escape_arobase() {
         .assert()
         .success()
         .stdout("@foo\n");
+    setup_command()
+        .args(["echo", "@@foo", "@@"])
+        .assert()
+        .success()
+        .stdout("@foo @\n");
 }
 
 // Lax will fail if it can't transform an '@' argument
@@ -110,6 +309,194 @@ fn menu_works_ok() {
         .stdout("tests/foobar/foo");
 }
 
+// Identical patterns should only prompt once, unless asked otherwise
+#[test]
+fn menu_reused_for_repeated_pattern() {
+    setup_command()
+        .args(["-pf", "--menu"])
+        .arg("@tests/**/fo*")
+        .arg("@tests/**/fo*")
+        .write_stdin("/fox\n")
+        .assert()
+        .success()
+        .stdout("tests/foobar/fox tests/foobar/fox");
+    setup_command()
+        .args(["-pf", "--menu"])
+        .arg("--independent")
+        .arg("@tests/**/fo*")
+        .arg("@tests/**/fo*")
+        .write_stdin("/fox\n/foo$\n")
+        .assert()
+        .success()
+        .stdout("tests/foobar/fox tests/foobar/foo");
+}
+
+// With --max-results, the menu holds back the rest of the matches until asked for them
+#[test]
+fn menu_loads_more_results() {
+    let output = setup_command()
+        .args(["-pf", "--menu", "--max-results", "1", "@tests/**/fo*"])
+        .write_stdin("m\n/fox\n")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "tests/foobar/fox");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("+more (press m to load)"));
+}
+
+// With --edit, the paths left in the list the editor was given are used, and the list is removed
+// afterwards, even when lax is killed while the editor is open
+#[cfg(unix)]
+#[test]
+fn edit_menu() {
+    let dir = std::env::temp_dir().join(format!("lax_test_edit_menu_{}", std::process::id()));
+    let temp_dir = dir.join("tmp");
+    std::fs::create_dir_all(&dir).unwrap();
+    let config = dir.join("config");
+    std::fs::write(&config, format!("temp_dir = {}\n", temp_dir.display())).unwrap();
+    let leftovers = || std::fs::read_dir(&temp_dir).unwrap().count();
+
+    setup_command()
+        .env("LAX_CONFIG", &config)
+        .env("EDITOR", "sed -i /fox/d")
+        .args(["-pf", "--menu", "--edit", "@tests/**/fo*"])
+        .assert()
+        .success()
+        .stdout("tests/foobar/foo");
+    assert_eq!(leftovers(), 0);
+
+    // Deleting everything gives up
+    setup_command()
+        .env("LAX_CONFIG", &config)
+        .env("EDITOR", "sed -i /fo/d")
+        .args(["-pf", "--menu", "--edit", "@tests/**/fo*"])
+        .assert()
+        .failure();
+    assert_eq!(leftovers(), 0);
+
+    let editor = dir.join("editor");
+    std::fs::write(&editor, "kill -TERM $PPID; exec sleep 10 >/dev/null 2>&1\n").unwrap();
+    let output = setup_command()
+        .env("LAX_CONFIG", &config)
+        .env("EDITOR", format!("sh {}", editor.display()))
+        .args(["-pf", "--menu", "--edit", "@tests/**/fo*"])
+        .timeout(std::time::Duration::from_secs(5))
+        .output()
+        .unwrap();
+    use std::os::unix::process::ExitStatusExt;
+    assert_eq!(output.status.signal(), Some(15));
+    assert_eq!(leftovers(), 0);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+// Without a terminal to ask on, ambiguous patterns are an error rather than a hang
+#[test]
+fn ambiguous_without_terminal() {
+    let output = setup_command()
+        .args(["-pf", "@tests/**/fo*"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(5));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Ambiguous pattern \"@tests/**/fo*\""));
+    assert!(stderr.contains("tests/foobar/fox"));
+
+    setup_command()
+        .args(["--menu", "--no-menu", "-p", "@foo"])
+        .assert()
+        .code(1);
+}
+
+// Without a menu to ask, patterns that match too many paths fail rather than expand
+#[test]
+fn too_many_matches() {
+    let output = setup_command()
+        .args(["--max-matches", "1", "-p", "@tests/**/fo[ox]^a"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(6));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("matches more than 1 paths"));
+
+    setup_command()
+        .args(["--max-matches", "2", "-p", "@tests/**/fo[ox]^a"])
+        .assert()
+        .success();
+}
+
+// --first and --select-all stand in for selectors on patterns without any
+#[test]
+fn implicit_selectors() {
+    let output = setup_command()
+        .args([
+            "--select-all",
+            "-P",
+            "@tests/**/fo[ox]",
+            "@tests/**/fo[ox]^/fox",
+        ])
+        .output()
+        .unwrap();
+    let mut paths: Vec<_> = std::str::from_utf8(&output.stdout)
+        .unwrap()
+        .lines()
+        .collect();
+    assert_eq!(paths.pop(), Some("tests/foobar/fox"));
+    paths.sort();
+    assert_eq!(paths, ["tests/foobar/foo", "tests/foobar/fox"]);
+    setup_command()
+        .args(["--first", "-p", "@tests/**/fo[ox]"])
+        .assert()
+        .success();
+    setup_command()
+        .args(["--first", "--select-all", "-p", "@foo"])
+        .assert()
+        .code(1);
+}
+
+// --confirm-each asks about each path, and leaves out the ones that are declined
+#[test]
+fn confirm_each() {
+    let output = setup_command()
+        .args([
+            "--confirm-each",
+            "-P",
+            "before",
+            "@tests/**/fo[ox]^a",
+            "after",
+        ])
+        .write_stdin("n\ny\n")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!((lines[0], lines[2]), ("before", "after"));
+
+    // Nothing is asked about arguments after the literal marker, which are kept
+    setup_command()
+        .args([
+            "--confirm-each",
+            "-p",
+            "echo",
+            "@tests/**/foo",
+            "--no-expand",
+            "@a",
+            "@b",
+        ])
+        .write_stdin("n\n")
+        .assert()
+        .success()
+        .stdout("echo @a @b");
+
+    setup_command()
+        .args(["--confirm-each", "-P", "@tests/**/fo[ox]^a"])
+        .write_stdin("q\n")
+        .assert()
+        .code(1)
+        .stdout("");
+}
+
 // Ensure the 'match with directories' functionality is working
 #[test]
 fn match_with_dirs() {
@@ -126,3 +513,794 @@ fn match_with_dirs() {
         .assert()
         .failure();
 }
+
+#[test]
+fn path_styles() {
+    let cwd = env::current_dir().unwrap();
+    let foo = cwd.join("tests").join("foobar").join("foo");
+    setup_command()
+        .args(["--absolute", "-p", "@foo"])
+        .assert()
+        .stdout(foo.display().to_string());
+    setup_command()
+        .args(["--relative", "-p", "@foo"])
+        .assert()
+        .stdout(["tests", "foobar", "foo"].join(std::path::MAIN_SEPARATOR_STR));
+    setup_command()
+        .args(["--absolute", "--canonical", "-p", "@foo"])
+        .assert()
+        .code(1);
+}
+
+#[test]
+fn path_parts() {
+    setup_command()
+        .args(["--basename", "-p", "@foo"])
+        .assert()
+        .stdout("foo");
+    setup_command()
+        .args(["--dirname", "-p", "@foo"])
+        .assert()
+        .stdout(["./tests", "foobar"].join(std::path::MAIN_SEPARATOR_STR));
+    setup_command()
+        .args(["--basename", "--dirname", "-p", "@foo"])
+        .assert()
+        .code(1);
+}
+
+// --no-dir-slash leaves the trailing separator off matched directories
+#[test]
+fn no_dir_slash() {
+    setup_command()
+        .args(["-p", "@this_is_a_directory"])
+        .assert()
+        .stdout("./tests/foobar/this_is_a_directory/");
+    setup_command()
+        .args(["--no-dir-slash", "-p", "@this_is_a_directory"])
+        .assert()
+        .stdout("./tests/foobar/this_is_a_directory");
+}
+
+// Arguments piped in with --stdin come after the ones given, and can be patterns
+#[test]
+fn stdin_arguments() {
+    setup_command()
+        .args(["--stdin", "-p", "echo"])
+        .write_stdin("@this_is_a_directory\n\nwith space\r\n")
+        .assert()
+        .stdout("echo ./tests/foobar/this_is_a_directory/ with space");
+    setup_command()
+        .args(["--stdin", "-p"])
+        .write_stdin("")
+        .assert()
+        .failure();
+}
+
+// Arguments can be kept in a file, one per line, with comments
+#[test]
+fn args_file() {
+    let path = env::temp_dir().join(format!("lax_test_args_file_{}", std::process::id()));
+    fs::write(
+        &path,
+        "# The program\necho\n\n  @this_is_a_directory  \n\\#not a comment\n\\@foo\nwith space\r\n",
+    )
+    .unwrap();
+    let path = path.to_str().unwrap();
+    setup_command()
+        .args(["--args-file", path, "-p"])
+        .assert()
+        .stdout("echo ./tests/foobar/this_is_a_directory/ #not a comment @foo with space");
+    setup_command()
+        .args(["-p", "--args-file", path, "--args-file", path, "printf"])
+        .assert()
+        .success();
+    setup_command()
+        .args(["--args-file", "tests/no_such_args_file", "-p", "echo"])
+        .assert()
+        .failure();
+    fs::remove_file(path).unwrap();
+}
+
+// With --filter, patterns match the paths piped in, whether or not they exist
+#[test]
+fn filter() {
+    setup_command()
+        .args(["--filter", "-p", "echo", "@*.rs^a", "@no_such/**/d*"])
+        .write_stdin("src/main.rs\nno_such/dir/\nno_such/a.rs\nREADME.md\n")
+        .assert()
+        .stdout("echo ./no_such/a.rs ./src/main.rs no_such/dir/");
+    setup_command()
+        .args(["--filter", "-p", "@*.rs"])
+        .write_stdin("")
+        .assert()
+        .failure();
+}
+
+// Hidden entries are only searched with -a, or if the config file says so and --no-hidden doesn't
+#[test]
+fn hidden() {
+    let config = env::temp_dir().join(format!("lax_test_hidden_config_{}", std::process::id()));
+    fs::write(&config, "search_hidden = true\n").unwrap();
+    setup_command()
+        .args(["-p", "@?github/*/rust.yml"])
+        .assert()
+        .failure();
+    setup_command()
+        .args(["-ap", "@?github/*/rust.yml"])
+        .assert()
+        .stdout("./.github/workflows/rust.yml");
+    setup_command()
+        .args(["-Hp", "@?github/*/rust.yml"])
+        .assert()
+        .stdout("./.github/workflows/rust.yml");
+    setup_command()
+        .args(["--hidden", "--no-hidden", "-p", "@?github/*/rust.yml"])
+        .assert()
+        .failure()
+        .stderr("lax: Only one of `--all`, `--hidden`, and `--no-hidden` can be used\n");
+    setup_command()
+        .env("LAX_CONFIG", &config)
+        .args(["-p", "@?github/*/rust.yml"])
+        .assert()
+        .stdout("./.github/workflows/rust.yml");
+    setup_command()
+        .env("LAX_CONFIG", &config)
+        .args(["--no-hidden", "-p", "@?github/*/rust.yml"])
+        .assert()
+        .failure();
+    fs::remove_file(&config).unwrap();
+}
+
+// --type limits matches to any of the types given
+#[test]
+fn entry_types() {
+    setup_command()
+        .args(["--type", "e", "-p", "@tests/foobar/**/*^a"])
+        .assert()
+        .stdout("tests/foobar/foo tests/foobar/fox");
+    setup_command()
+        .args(["--type", "d", "--type", "e", "-p", "@tests/foobar/**/*o*^a"])
+        .assert()
+        .stdout("tests/foobar/another_directory/ tests/foobar/foo tests/foobar/fox tests/foobar/this_is_a_directory/");
+    setup_command()
+        .args(["--type", "q", "-p", "@foo"])
+        .assert()
+        .failure();
+}
+
+// --ext limits matches to any of the extensions given
+#[test]
+fn extensions() {
+    setup_command()
+        .args(["--ext", "md,.RS", "--ext", "toml", "-p", "@src/c*^a"])
+        .assert()
+        .stdout("./src/checklist.rs ./src/config_file.rs");
+    setup_command()
+        .args(["--ext", "toml", "-p", "@src/c*^a"])
+        .assert()
+        .failure();
+}
+
+// --owner and --perm limit matches to those owned by a user, or that we have access to
+#[cfg(unix)]
+#[test]
+fn owner_and_access() {
+    use std::os::unix::fs::MetadataExt;
+
+    let owner = std::fs::metadata("src").unwrap().uid().to_string();
+    setup_command()
+        .args(["--owner", &owner, "--perm", "-r", "-p", "@src/c*^a"])
+        .assert()
+        .stdout("./src/checklist.rs ./src/config_file.rs");
+    setup_command()
+        .args(["--perm", "x", "-p", "@tests/foobar/**/*^a"])
+        .assert()
+        .stdout("tests/foobar/another_directory/ tests/foobar/this_is_a_directory/");
+    setup_command()
+        .args(["--owner", "lax_no_such_user", "-p", "@src/c*^a"])
+        .assert()
+        .failure()
+        .stderr("lax: No such user: 'lax_no_such_user'\n");
+    setup_command()
+        .args(["--perm", "rq", "-p", "@src/c*^a"])
+        .assert()
+        .failure();
+}
+
+// Relative entry points are also searched for in $LAX_PATH
+#[test]
+fn search_path() {
+    setup_command()
+        .env("LAX_PATH", "tests")
+        .arg("-p")
+        .arg("@foobar/**/fox")
+        .assert()
+        .success()
+        .stdout("tests/foobar/fox");
+}
+
+// Patterns without an entry point search from --entry-point, if it's given
+#[test]
+fn default_entry_point() {
+    setup_command()
+        .arg("--entry-point")
+        .arg("tests/foobar")
+        .arg("-p")
+        .arg("@fox")
+        .assert()
+        .success()
+        .stdout("tests/foobar/fox");
+    // An explicit entry point still searches from where it says
+    setup_command()
+        .arg("--entry-point")
+        .arg("tests/foobar/another_directory")
+        .arg("-p")
+        .arg("@tests/**/fox")
+        .assert()
+        .success()
+        .stdout("tests/foobar/fox");
+}
+
+// Bookmarks come from the config file
+#[test]
+fn bookmarks() {
+    let config = env::temp_dir().join(format!("lax_test_bookmarks_config_{}", std::process::id()));
+    fs::write(&config, "[bookmarks]\nfoobar = tests/foobar\n").unwrap();
+
+    setup_command()
+        .env("LAX_CONFIG", &config)
+        .arg("-p")
+        .arg("@:foobar")
+        .assert()
+        .success()
+        .stdout("tests/foobar");
+    setup_command()
+        .env("LAX_CONFIG", &config)
+        .arg("-p")
+        .arg("@:foobar/fox")
+        .assert()
+        .success()
+        .stdout("tests/foobar/fox");
+    setup_command()
+        .env("LAX_CONFIG", &config)
+        .arg("-p")
+        .arg("@:nonsense")
+        .assert()
+        .failure();
+    fs::remove_file(&config).unwrap();
+}
+
+// '@!' expands to the previous invocation's paths
+#[test]
+fn history() {
+    let data = env::temp_dir().join(format!("lax_test_history_data_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&data);
+
+    setup_command()
+        .env("XDG_DATA_HOME", &data)
+        .arg("-p")
+        .arg("@!")
+        .assert()
+        .failure();
+    setup_command()
+        .env("XDG_DATA_HOME", &data)
+        .arg("-p")
+        .arg("@fox")
+        .assert()
+        .success();
+    setup_command()
+        .env("XDG_DATA_HOME", &data)
+        .arg("-p")
+        .arg("@foo")
+        .assert()
+        .success();
+    setup_command()
+        .env("XDG_DATA_HOME", &data)
+        .arg("-p")
+        .arg("@!2")
+        .arg("@!")
+        .assert()
+        .success()
+        .stdout("./tests/foobar/fox ./tests/foobar/foo");
+
+    // Without selectors, every path is reused
+    setup_command()
+        .env("XDG_DATA_HOME", &data)
+        .args(["-p", "@!^-1"])
+        .assert()
+        .success()
+        .stdout("./tests/foobar/foo");
+    setup_command()
+        .env("XDG_DATA_HOME", &data)
+        .args(["-p", "@!2"])
+        .assert()
+        .success()
+        .stdout("./tests/foobar/fox ./tests/foobar/foo");
+
+    // Explaining records nothing, even with --chdir
+    setup_command()
+        .env("XDG_DATA_HOME", &data)
+        .args(["--explain", "--chdir", "@foobar/", "false", "@fox"])
+        .assert()
+        .success();
+    setup_command()
+        .env("XDG_DATA_HOME", &data)
+        .args(["-p", "@!"])
+        .assert()
+        .success()
+        .stdout("./tests/foobar/fox ./tests/foobar/foo");
+
+    fs::remove_dir_all(&data).unwrap();
+}
+
+// --explain shows the expansion without running anything
+#[test]
+fn explain() {
+    let output = setup_command()
+        .arg("--explain")
+        .arg("false")
+        .arg("@foo")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout
+        .starts_with("@foo\n    [entry point ., glob foo]\n    ./tests/foobar/foo\n    [parse "));
+
+    let output = setup_command()
+        .args(["--explain", "@no_such_file|tests/**/fo[ox]^-1:t"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with(
+        "@no_such_file|tests/**/fo[ox]^-1:t\n    \
+         [entry point tests, glob fo[ox], selectors -1, modifiers :t]\n    fox\n"
+    ));
+}
+
+// --stats reports each pattern's search on stderr, and still runs the command
+#[test]
+fn stats() {
+    let output = setup_command()
+        .arg("--stats")
+        .arg("echo")
+        .arg("@foo")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "./tests/foobar/foo\n"
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("lax: @foo: walked "));
+    assert!(stderr.contains(", 1 matched [glob compile "));
+}
+
+// With `cache_dir` set, entry points are indexed, and the index is used while it's up to date
+#[test]
+fn index() {
+    let cache = env::temp_dir().join(format!("lax_test_index_cache_{}", std::process::id()));
+    let config = env::temp_dir().join(format!("lax_test_index_config_{}", std::process::id()));
+    fs::write(&config, format!("cache_dir = {}\n", cache.display())).unwrap();
+
+    let output = setup_command()
+        .env("LAX_CONFIG", &config)
+        .args(["-v", "--refresh", "-p", "@tests/**/fox"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "tests/foobar/fox");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Indexing tests"));
+    assert!(cache.join("index").read_dir().unwrap().next().is_some());
+
+    let output = setup_command()
+        .env("LAX_CONFIG", &config)
+        .args(["-v", "--no-cache", "-p", "@tests/**/fox"])
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "tests/foobar/fox");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Walking tests"));
+
+    setup_command()
+        .args(["--refresh", "-p", "@foo"])
+        .assert()
+        .failure();
+    fs::remove_dir_all(&cache).unwrap();
+    fs::remove_file(&config).unwrap();
+}
+
+// A backend lists what's under an entry point instead of walking it
+#[test]
+fn backend() {
+    let config = env::temp_dir().join(format!("lax_test_backend_config_{}", std::process::id()));
+    let tests = env::current_dir().unwrap().join("tests");
+    fs::write(
+        &config,
+        format!("[under {}]\nbackend = git ls-files\n", tests.display()),
+    )
+    .unwrap();
+    let output = setup_command()
+        .env("LAX_CONFIG", &config)
+        .args(["-v", "-p", "@tests/**/*.placeholder^a"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("with `git ls-files`"));
+
+    fs::write(
+        &config,
+        format!(
+            "[under {}]\nbackend = lax_no_such_lister\n",
+            tests.display()
+        ),
+    )
+    .unwrap();
+    let output = setup_command()
+        .env("LAX_CONFIG", &config)
+        .args(["-p", "@tests/**/fox"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Search backend `lax_no_such_lister` failed"));
+    fs::remove_file(&config).unwrap();
+}
+
+// While `lax --daemon` runs, searches under its roots are answered by it
+#[cfg(unix)]
+#[test]
+fn daemon() {
+    let root = env::temp_dir().join(format!("lax_test_daemon_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("tree").join("sub")).unwrap();
+    fs::write(root.join("tree").join("sub").join("a"), "").unwrap();
+    let config = root.join("config");
+    fs::write(
+        &config,
+        format!(
+            "cache_dir = {}\ndaemon_roots = {}\n",
+            root.join("cache").display(),
+            root.join("tree").display()
+        ),
+    )
+    .unwrap();
+
+    let mut daemon = std::process::Command::new(assert_cmd::cargo::cargo_bin("lax"))
+        .env("LAX_CONFIG", &config)
+        .arg("--daemon")
+        .spawn()
+        .unwrap();
+    let socket = root.join("cache").join("daemon.sock");
+    for _ in 0..100 {
+        if socket.exists() {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+
+    // A client that never sends its request only holds the daemon up for a while
+    let idle = std::os::unix::net::UnixStream::connect(&socket).unwrap();
+    let output = setup_command()
+        .env("LAX_CONFIG", &config)
+        .current_dir(root.join("tree"))
+        .args(["-v", "-p", "@a"])
+        .output()
+        .unwrap();
+    drop(idle);
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "./sub/a");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Searching the daemon's index of ."));
+
+    // Without the daemon, the search is done as usual
+    let output = setup_command()
+        .env("LAX_CONFIG", &config)
+        .current_dir(root.join("tree"))
+        .args(["-v", "-p", "@a"])
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "./sub/a");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(!stderr.contains("daemon"));
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+// -v logs each pattern's search, and -vv what was skipped
+#[test]
+fn verbose() {
+    let output = setup_command()
+        .args(["-v", "echo", "@foo"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("@foo: matched 1 paths"));
+    assert!(!stderr.contains("[debug]"));
+
+    let output = setup_command()
+        .args(["-vv", "echo", "@tests/**/fo?!fox"])
+        .output()
+        .unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "tests/foobar/foo\n"
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Skipping ./foobar/fox, which the exclusion matches"));
+}
+
+// '@z:QUERY' asks zoxide where to start searching from
+#[cfg(unix)]
+#[test]
+fn zoxide_entry_point() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let bin = env::temp_dir().join(format!("lax_test_zoxide_bin_{}", std::process::id()));
+    fs::create_dir_all(&bin).unwrap();
+    let zoxide = bin.join("zoxide");
+    fs::write(
+        &zoxide,
+        "#!/bin/sh\n[ \"$3\" = foobar ] && echo tests/foobar\n",
+    )
+    .unwrap();
+    fs::set_permissions(&zoxide, fs::Permissions::from_mode(0o755)).unwrap();
+    let path = format!("{}:{}", bin.display(), env::var("PATH").unwrap());
+
+    setup_command()
+        .env("PATH", &path)
+        .arg("-p")
+        .arg("@z:foobar/fox")
+        .assert()
+        .success()
+        .stdout("tests/foobar/fox");
+    setup_command()
+        .env("PATH", &path)
+        .arg("-p")
+        .arg("@z:nonsense")
+        .assert()
+        .failure();
+    fs::remove_dir_all(&bin).unwrap();
+}
+
+// --chdir runs the command inside the expanded directory
+#[test]
+fn chdir() {
+    setup_command()
+        .arg("--chdir")
+        .arg("@foobar/")
+        .arg("--")
+        .arg("pwd")
+        .assert()
+        .success()
+        .stdout(format!(
+            "{}\n",
+            fs::canonicalize("tests/foobar").unwrap().display()
+        ));
+    setup_command()
+        .arg("--chdir=@foobar/")
+        .arg("-p")
+        .arg("@fox")
+        .assert()
+        .success()
+        .stdout("./fox");
+    setup_command()
+        .arg("--chdir")
+        .arg("@fo*^a")
+        .arg("pwd")
+        .assert()
+        .failure();
+}
+
+// --each runs a command once per path
+#[test]
+fn each() {
+    let output = setup_command()
+        .arg("--each")
+        .arg("echo found {}")
+        .arg("@fo[ox]^a")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let mut lines: Vec<&str> = stdout.lines().collect();
+    lines.sort();
+    assert_eq!(
+        lines,
+        vec!["found ./tests/foobar/foo", "found ./tests/foobar/fox"]
+    );
+
+    setup_command()
+        .arg("--each")
+        .arg("echo")
+        .arg("@foo")
+        .assert()
+        .success()
+        .stdout("./tests/foobar/foo\n");
+    setup_command()
+        .arg("--each")
+        .arg("false")
+        .arg("@foo")
+        .assert()
+        .code(123);
+}
+
+// -j runs --each commands concurrently, without mixing up their output
+#[test]
+fn each_parallel() {
+    let output = setup_command()
+        .arg("-j2")
+        .arg("--each")
+        .arg("echo found {}")
+        .arg("@fo[ox]^a")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let mut lines: Vec<&str> = stdout.lines().collect();
+    lines.sort();
+    assert_eq!(
+        lines,
+        vec!["found ./tests/foobar/foo", "found ./tests/foobar/fox"]
+    );
+
+    setup_command()
+        .arg("-j")
+        .arg("2")
+        .arg("--each")
+        .arg("false")
+        .arg("@fo[ox]^a")
+        .assert()
+        .code(123);
+    setup_command()
+        .arg("-j0")
+        .arg("--each")
+        .arg("echo")
+        .arg("@foo")
+        .assert()
+        .failure();
+    setup_command().arg("-j2").arg("echo").assert().failure();
+}
+
+// --list prints what the patterns match, one per line
+#[test]
+fn list() {
+    setup_command()
+        .arg("-l")
+        .arg("@foo")
+        .arg("not_a_pattern")
+        .arg("@fox")
+        .assert()
+        .success()
+        .stdout("./tests/foobar/foo\n./tests/foobar/fox\n");
+}
+
+// '@recent:' matches against recently selected paths
+#[test]
+fn recent() {
+    let data = env::temp_dir().join(format!("lax_test_recent_data_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&data);
+
+    setup_command()
+        .env("XDG_DATA_HOME", &data)
+        .arg("-p")
+        .arg("@recent:fo*")
+        .assert()
+        .failure();
+    setup_command()
+        .env("XDG_DATA_HOME", &data)
+        .arg("-p")
+        .arg("@fox")
+        .assert()
+        .success();
+    setup_command()
+        .env("XDG_DATA_HOME", &data)
+        .arg("-p")
+        .arg("@recent:fo*")
+        .assert()
+        .success()
+        .stdout(
+            fs::canonicalize("tests/foobar/fox")
+                .unwrap()
+                .display()
+                .to_string(),
+        );
+    setup_command()
+        .env("XDG_DATA_HOME", &data)
+        .current_dir("src")
+        .arg("-p")
+        .arg("@recent:fo*")
+        .assert()
+        .failure();
+
+    fs::remove_dir_all(&data).unwrap();
+}
+
+// --probe reports how a pattern would resolve, without prompting
+#[test]
+fn probe() {
+    setup_command()
+        .arg("--probe")
+        .arg("@foo")
+        .assert()
+        .code(0)
+        .stdout("{\"status\":\"unambiguous\",\"paths\":[\"./tests/foobar/foo\"]}\n");
+    setup_command()
+        .arg("--probe")
+        .arg("@fo[ox]")
+        .assert()
+        .code(1);
+    setup_command()
+        .arg("--probe")
+        .arg("@no_such_file")
+        .assert()
+        .code(2)
+        .stdout("{\"status\":\"unmatched\",\"paths\":[]}\n");
+    setup_command()
+        .arg("--probe")
+        .arg("@foo^0")
+        .assert()
+        .code(3);
+}
+
+// --json prints the expansion as JSON instead of running anything
+#[test]
+fn json() {
+    let output = setup_command()
+        .arg("--json")
+        .arg("echo")
+        .arg("@foo")
+        .arg("@this_is_a_directory/")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with(
+        "{\"args\":[\"echo\",\"./tests/foobar/foo\",\"./tests/foobar/this_is_a_directory/\"],\
+         \"patterns\":[{\"pattern\":\"@foo\",\"paths\":[{\"path\":\"./tests/foobar/foo\",\
+         \"is_dir\":false,\"size\":0,\"mtime\":"
+    ));
+    assert!(stdout.contains("\"is_dir\":true"));
+}
+
+// -0 terminates each arg with NUL
+#[test]
+fn print0() {
+    setup_command()
+        .arg("-0")
+        .arg("echo")
+        .arg("@foo")
+        .assert()
+        .success()
+        .stdout("echo\0./tests/foobar/foo\0");
+}
+
+// --print-quoted quotes each arg for the shell
+#[test]
+fn print_quoted() {
+    setup_command()
+        .arg("--print-quoted")
+        .arg("echo")
+        .arg("it's")
+        .arg("@foo")
+        .assert()
+        .success()
+        .stdout("echo 'it'\\''s' ./tests/foobar/foo\n");
+}
+
+// Different kinds of failure have different exit codes
+#[test]
+fn exit_codes() {
+    let code = |args: &[&str]| setup_command().args(args).output().unwrap().status.code();
+    assert_eq!(code(&["echo", "@great_googly_moogly.txt"]), Some(2));
+    assert_eq!(code(&["echo", "@foo^9"]), Some(2));
+    assert_eq!(code(&["echo", "@foo^x"]), Some(3));
+    assert_eq!(code(&["echo", "@foo^/("]), Some(3));
+    assert_eq!(code(&["echo", "@no_such_directory/**/foo"]), Some(4));
+    assert_eq!(code(&["Great googly moogly!"]), Some(127));
+    assert_eq!(code(&["tests/foobar/foo"]), Some(126));
+}